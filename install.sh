@@ -91,6 +91,7 @@ mkdir -p "$TMPDIR/bin"
 tar xzf "$TMPDIR/$ASSET" -C "$TMPDIR/bin"
 mkdir -p "$PREFIX"
 install -m 0755 "$TMPDIR/bin/sshpod" "$PREFIX/sshpod"
+ln -sf sshpod "$PREFIX/git-remote-sshpod"
 
 echo "Installed to $PREFIX/sshpod"
 
@@ -0,0 +1,229 @@
+//! Sockets handed over by a service manager, so `sshpod dns` can be started
+//! on the first query by systemd (a `.socket` unit) or launchd (`Sockets` in
+//! the agent plist) and exit again with `--idle-timeout`.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// First descriptor passed by systemd (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+/// Socket name in the launchd plist.
+const LAUNCHD_SOCKET: &str = "Listeners";
+const LAUNCHD_LABEL: &str = "dev.sshpod.dns";
+const UNIT_NAME: &str = "sshpod-dns";
+
+/// Service manager to print activation units for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ServiceManager {
+    Systemd,
+    Launchd,
+}
+
+/// The UDP socket inherited from systemd or launchd, if the process was
+/// socket-activated.
+pub fn inherited_udp_socket() -> Result<Option<UdpSocket>> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::FromRawFd;
+        let fds = systemd_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::process::id(),
+        );
+        if fds > 0 {
+            // SAFETY: systemd passes its sockets starting at fd 3 to the pid
+            // named in LISTEN_PID, and nothing else in sshpod opens them.
+            let socket = unsafe { UdpSocket::from_raw_fd(SD_LISTEN_FDS_START) };
+            socket.set_nonblocking(true)?;
+            return Ok(Some(socket));
+        }
+    }
+    #[cfg(target_os = "macos")]
+    if let Some(socket) = launchd::activate_socket(LAUNCHD_SOCKET)? {
+        socket.set_nonblocking(true)?;
+        return Ok(Some(socket));
+    }
+    Ok(None)
+}
+
+/// Number of sockets systemd passed to this process, per `sd_listen_fds(3)`.
+#[cfg(unix)]
+fn systemd_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> i32 {
+    if listen_pid.and_then(|p| p.parse::<u32>().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds.and_then(|n| n.parse().ok()).unwrap_or(0)
+}
+
+#[cfg(target_os = "macos")]
+mod launchd {
+    use anyhow::{bail, Result};
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::net::UdpSocket;
+    use std::os::fd::FromRawFd;
+
+    const ESRCH: c_int = 3;
+    const ENOENT: c_int = 2;
+
+    extern "C" {
+        fn launch_activate_socket(
+            name: *const c_char,
+            fds: *mut *mut c_int,
+            cnt: *mut usize,
+        ) -> c_int;
+        fn free(ptr: *mut c_void);
+    }
+
+    pub fn activate_socket(name: &str) -> Result<Option<UdpSocket>> {
+        let name = CString::new(name)?;
+        let mut fds: *mut c_int = std::ptr::null_mut();
+        let mut cnt = 0usize;
+        // SAFETY: launchd allocates `fds` with malloc and hands ownership of
+        // both the array and the descriptors to the caller.
+        let err = unsafe { launch_activate_socket(name.as_ptr(), &mut fds, &mut cnt) };
+        match err {
+            0 => {}
+            ESRCH | ENOENT => return Ok(None),
+            _ => bail!("launch_activate_socket failed (errno {})", err),
+        }
+        if fds.is_null() {
+            return Ok(None);
+        }
+        let socket = unsafe {
+            let socket = (cnt > 0).then(|| UdpSocket::from_raw_fd(*fds));
+            free(fds.cast());
+            socket
+        };
+        Ok(socket)
+    }
+}
+
+/// Unit files (systemd) or an agent plist (launchd) that start
+/// `sshpod dns` on the first query to `listen`.
+pub fn render_units(
+    manager: ServiceManager,
+    exe: &str,
+    args: &[String],
+    listen: SocketAddr,
+) -> String {
+    match manager {
+        ServiceManager::Systemd => {
+            let command = std::iter::once(exe)
+                .chain(args.iter().map(String::as_str))
+                .map(|a| format!("\"{}\"", a.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!(
+                "# ~/.config/systemd/user/{name}.socket\n\
+                 [Unit]\n\
+                 Description=sshpod DNS stub\n\
+                 \n\
+                 [Socket]\n\
+                 ListenDatagram={listen}\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=sockets.target\n\
+                 \n\
+                 # ~/.config/systemd/user/{name}.service\n\
+                 [Unit]\n\
+                 Description=sshpod DNS stub\n\
+                 Requires={name}.socket\n\
+                 \n\
+                 [Service]\n\
+                 ExecStart={command}\n",
+                name = UNIT_NAME,
+            )
+        }
+        ServiceManager::Launchd => {
+            let program = std::iter::once(exe)
+                .chain(args.iter().map(String::as_str))
+                .map(|a| format!("    <string>{}</string>\n", xml_escape(a)))
+                .collect::<String>();
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+                 \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+                 <!-- ~/Library/LaunchAgents/{label}.plist -->\n\
+                 <plist version=\"1.0\">\n\
+                 <dict>\n\
+                 \x20 <key>Label</key>\n\
+                 \x20 <string>{label}</string>\n\
+                 \x20 <key>ProgramArguments</key>\n\
+                 \x20 <array>\n\
+                 {program}\
+                 \x20 </array>\n\
+                 \x20 <key>Sockets</key>\n\
+                 \x20 <dict>\n\
+                 \x20   <key>{socket}</key>\n\
+                 \x20   <dict>\n\
+                 \x20     <key>SockType</key>\n\
+                 \x20     <string>dgram</string>\n\
+                 \x20     <key>SockNodeName</key>\n\
+                 \x20     <string>{host}</string>\n\
+                 \x20     <key>SockServiceName</key>\n\
+                 \x20     <string>{port}</string>\n\
+                 \x20   </dict>\n\
+                 \x20 </dict>\n\
+                 </dict>\n\
+                 </plist>\n",
+                label = LAUNCHD_LABEL,
+                socket = LAUNCHD_SOCKET,
+                host = listen.ip(),
+                port = listen.port(),
+            )
+        }
+    }
+}
+
+/// Arguments for the activated `sshpod dns`, keeping the idle timeout so
+/// the service exits between bursts of queries.
+pub fn service_args(host: &str, domain: &str, idle_timeout: Duration) -> Vec<String> {
+    vec![
+        "dns".to_string(),
+        host.to_string(),
+        "--domain".to_string(),
+        domain.to_string(),
+        "--idle-timeout".to_string(),
+        format!("{}s", idle_timeout.as_secs()),
+    ]
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn systemd_fds_only_for_this_pid() {
+        assert_eq!(systemd_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(systemd_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(systemd_fds(None, Some("1"), 42), 0);
+        assert_eq!(systemd_fds(Some("42"), None, 42), 0);
+    }
+
+    #[test]
+    fn renders_units() {
+        let args = service_args("pod--web.sshpod", "cluster.local", Duration::from_secs(600));
+        let listen: SocketAddr = "127.0.0.1:5353".parse().unwrap();
+        let systemd = render_units(ServiceManager::Systemd, "/usr/bin/sshpod", &args, listen);
+        assert!(systemd.contains("ListenDatagram=127.0.0.1:5353\n"));
+        assert!(systemd.contains(
+            "ExecStart=\"/usr/bin/sshpod\" \"dns\" \"pod--web.sshpod\" \"--domain\" \
+             \"cluster.local\" \"--idle-timeout\" \"600s\"\n"
+        ));
+        let plist = render_units(ServiceManager::Launchd, "/usr/bin/sshpod", &args, listen);
+        assert!(plist.contains("    <string>pod--web.sshpod</string>\n"));
+        assert!(plist.contains("<string>dgram</string>"));
+        assert!(plist.contains("<string>5353</string>"));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::cli::AuditArgs;
+use crate::kubectl::{self, PodSummary};
+use crate::remote;
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+pub const MAX_PARALLEL_EXECS: usize = 8;
+
+/// A container with an sshpod install.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub context: Option<String>,
+    pub namespace: String,
+    pub pod: String,
+    pub pod_uid: String,
+    pub container: String,
+    pub version: String,
+    pub keys: Vec<String>,
+}
+
+pub async fn run(args: AuditArgs) -> Result<()> {
+    let context = args.context.as_deref();
+    if let Some(ctx) = context {
+        kubectl::ensure_context_exists(ctx).await?;
+    }
+    let namespace = if args.all_namespaces {
+        None
+    } else {
+        Some(resolve_namespace(context, args.namespace.clone()).await?)
+    };
+    let pods = kubectl::list_pods(context, namespace.as_deref()).await?;
+    let findings = find_installs(args.context.clone(), pods).await;
+
+    if findings.is_empty() {
+        println!("No sshpod installs found");
+        return Ok(());
+    }
+    println!("NAMESPACE\tPOD\tCONTAINER\tVERSION\tKEYS");
+    for f in findings {
+        let keys = if f.keys.is_empty() {
+            "-".to_string()
+        } else {
+            f.keys.join(",")
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            f.namespace, f.pod, f.container, f.version, keys
+        );
+    }
+    Ok(())
+}
+
+/// Namespace from the flag, the context, or `default`, in that order.
+pub async fn resolve_namespace(context: Option<&str>, namespace: Option<String>) -> Result<String> {
+    if let Some(ns) = namespace {
+        return Ok(ns);
+    }
+    let from_context = kubectl::get_context_namespace(context).await?;
+    Ok(from_context.unwrap_or_else(|| "default".to_string()))
+}
+
+/// Probes every container of the running pods in parallel for an install.
+pub async fn find_installs(context: Option<String>, pods: Vec<PodSummary>) -> Vec<Finding> {
+    let limit = Arc::new(Semaphore::new(MAX_PARALLEL_EXECS));
+    let mut tasks = JoinSet::new();
+    for pod in pods
+        .into_iter()
+        .filter(|p| p.phase.as_deref() == Some("Running"))
+    {
+        for container in pod.containers.clone() {
+            let limit = limit.clone();
+            let context = context.clone();
+            let pod = pod.clone();
+            tasks.spawn(async move {
+                let _permit = limit.acquire_owned().await.ok()?;
+                inspect(context.as_deref(), &pod, &container).await
+            });
+        }
+    }
+    let mut findings = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Some(finding)) = result {
+            findings.push(finding);
+        }
+    }
+    findings.sort_by(|a, b| {
+        (&a.namespace, &a.pod, &a.container).cmp(&(&b.namespace, &b.pod, &b.container))
+    });
+    findings
+}
+
+async fn inspect(context: Option<&str>, pod: &PodSummary, container: &str) -> Option<Finding> {
+    let base = remote::base_dir(&pod.uid, container);
+    let script = format!(
+        "[ -f \"{base}/bundle/VERSION\" ] || exit 3; cat \"{base}/bundle/VERSION\"; \
+         awk 'NF >= 3 {{print $3}}' \"{base}/authorized_keys\" 2>/dev/null || true"
+    );
+    let output = kubectl::exec_capture_optional(
+        context,
+        &pod.namespace,
+        &pod.name,
+        container,
+        &["sh", "-c", &script],
+    )
+    .await
+    .ok()??;
+    let (version, keys) = parse_report(&output)?;
+    Some(Finding {
+        context: context.map(str::to_string),
+        namespace: pod.namespace.clone(),
+        pod: pod.name.clone(),
+        pod_uid: pod.uid.clone(),
+        container: container.to_string(),
+        version,
+        keys,
+    })
+}
+
+/// First line is the bundle version; the rest are authorized_keys comments.
+fn parse_report(output: &str) -> Option<(String, Vec<String>)> {
+    let mut lines = output.lines().map(str::trim).filter(|l| !l.is_empty());
+    let version = lines.next()?.to_string();
+    let keys = lines.map(str::to_string).collect();
+    Some((version, keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_report_splits_version_and_keys() {
+        let (version, keys) = parse_report("0.1.1+sshd1\nalice@laptop\nbob@desk\n").unwrap();
+        assert_eq!(version, "0.1.1+sshd1");
+        assert_eq!(keys, vec!["alice@laptop", "bob@desk"]);
+        assert!(parse_report("").is_none());
+    }
+}
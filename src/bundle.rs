@@ -1,22 +1,69 @@
+use crate::crypto::CryptoPreset;
+use crate::download::{self, BundleOrigin};
 use crate::embedded;
 use crate::kubectl::{self, RemoteTarget};
-use anyhow::{anyhow, bail, Context, Result};
-use flate2::write::GzEncoder;
+use crate::remote::Probe;
+use crate::signature;
+use anyhow::{bail, Context, Result};
+use flate2::read::GzEncoder;
 use flate2::Compression;
-use log::info;
-use std::borrow::Cow;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 use std::env;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use xz2::read::XzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 pub const BUNDLE_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "+sshd1");
 
-pub async fn detect_remote_arch(target: &RemoteTarget) -> Result<String> {
-    let machine = kubectl::exec_capture_target(target, &["uname", "-m"])
+static BUNDLE_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes [`locate_bundle`] look only at `path`: the sshd bundle itself, or a
+/// directory holding `<name>_<arch>.xz` files.
+pub fn set_bundle_path(path: PathBuf) {
+    let _ = BUNDLE_PATH.set(path);
+}
+
+/// Where a bundle's xz bytes come from. Every pass over it (signature,
+/// digest, upload) opens it again and streams, so a bundle never sits in
+/// memory whole.
+#[derive(Clone, Debug)]
+enum BundleSource {
+    Embedded(&'static [u8]),
+    File(PathBuf),
+}
+
+impl BundleSource {
+    fn open(&self) -> Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            BundleSource::Embedded(data) => Box::new(*data),
+            BundleSource::File(path) => {
+                Box::new(BufReader::new(File::open(path).with_context(|| {
+                    format!("failed to read bundle {}", path.display())
+                })?))
+            }
+        })
+    }
+
+    /// The decompressed bundle.
+    fn open_plain(&self) -> Result<Box<dyn Read + Send>> {
+        decompress_xz(self.open()?)
+    }
+}
+
+/// Runs a pass over a bundle off the async runtime.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(work)
         .await
-        .context("failed to detect remote arch via uname -m")?;
+        .context("bundle worker panicked")?
+}
+
+/// Maps `uname -m` output to the bundle architecture.
+pub fn remote_arch(machine: &str) -> Result<String> {
     let arch = match machine.trim() {
         "x86_64" | "amd64" => "linux/amd64",
         "aarch64" | "arm64" => "linux/arm64",
@@ -27,158 +74,411 @@ pub async fn detect_remote_arch(target: &RemoteTarget) -> Result<String> {
     Ok(arch.to_string())
 }
 
-pub async fn ensure_bundle(target: &RemoteTarget, base: &str, arch: &str) -> Result<()> {
-    let version_path = format!("{}/bundle/VERSION", base);
-    let arch_path = format!("{}/bundle/ARCH", base);
-    let remote_version =
-        kubectl::exec_capture_optional_target(target, &["cat", &version_path]).await?;
-    let remote_arch = kubectl::exec_capture_optional_target(target, &["cat", &arch_path]).await?;
+pub async fn ensure_bundle(
+    target: &RemoteTarget,
+    base: &str,
+    arch: &str,
+    installed: &Probe,
+    offline: bool,
+    crypto: CryptoPreset,
+) -> Result<()> {
+    let variant_bundle = if crypto == CryptoPreset::Default {
+        None
+    } else {
+        match locate_bundle(arch, crypto.bundle_prefix()) {
+            Ok(path) => Some(path),
+            Err(_) => {
+                warn!(
+                    "[sshpod] no {} sshd bundle found for {}; using the default build",
+                    crypto.name(),
+                    arch
+                );
+                None
+            }
+        }
+    };
+    let expected_version = match variant_bundle {
+        Some(_) => format!("{}+{}", BUNDLE_VERSION, crypto.name()),
+        None => BUNDLE_VERSION.to_string(),
+    };
+    let remote_version = installed.bundle_version.as_deref();
+    let remote_arch = installed.bundle_arch.as_deref();
 
     info!(
         "[sshpod] checking bundle (remote version={:?}, remote arch={:?}, expected version={}, expected arch={})",
-        remote_version, remote_arch, BUNDLE_VERSION, arch
+        remote_version, remote_arch, expected_version, arch
     );
-    if remote_version.as_deref() == Some(BUNDLE_VERSION) && remote_arch.as_deref() == Some(arch) {
+    if remote_version == Some(expected_version.as_str()) && remote_arch == Some(arch) {
         info!("[sshpod] bundle already up to date");
         return Ok(());
     }
 
-    let bundle_data = load_bundle_data(arch).await?;
+    let source = match variant_bundle {
+        Some(path) => {
+            info!(
+                "[sshpod] using {} bundle file {}",
+                crypto.name(),
+                path.display()
+            );
+            verified_file(path).await?
+        }
+        None => load_bundle_data(arch, offline).await?,
+    };
 
     let meta = format!(
-        "printf '%s\\n' \"{BUNDLE_VERSION}\" > \"{base}/bundle/VERSION\"; \
+        "printf '%s\\n' \"{expected_version}\" > \"{base}/bundle/VERSION\"; \
          printf '%s\\n' \"{arch}\" > \"{base}/bundle/ARCH\"; \
          chmod 600 \"{base}/bundle/VERSION\" \"{base}/bundle/ARCH\";"
     );
 
-    let install_xz = format!(
-        "set -eu; umask 077; mkdir -p \"{base}/bundle\"; chmod 700 \"{base}\" \"{base}/bundle\"; \
-         xz -dc > \"{base}/bundle/sshd\"; chmod 700 \"{base}/bundle/sshd\"; {meta}"
-    );
-    let install_gz = format!(
-        "set -eu; umask 077; mkdir -p \"{base}/bundle\"; chmod 700 \"{base}\" \"{base}/bundle\"; \
-         gzip -dc > \"{base}/bundle/sshd\"; chmod 700 \"{base}/bundle/sshd\"; {meta}"
-    );
-    let install_plain = format!(
-        "set -eu; umask 077; mkdir -p \"{base}/bundle\"; chmod 700 \"{base}\" \"{base}/bundle\"; \
-         cat > \"{base}/bundle/sshd\"; chmod 700 \"{base}/bundle/sshd\"; {meta}"
-    );
-    let mut sshd_data: Option<Vec<u8>> = None;
+    install_payload(target, base, "sshd", &source, &meta).await?;
+    info!("[sshpod] bundle install completed");
+    Ok(())
+}
 
-    let xz_err = match try_install_xz(target, &bundle_data, &install_xz).await {
-        Ok(_) => {
-            info!("[sshpod] bundle install completed");
-            return Ok(());
+/// Installs the optional `<name>_<arch>.xz` tool bundle (e.g. a static rsync)
+/// as `$BASE/bundle/<name>`. Returns false when no such bundle is available.
+pub async fn ensure_tool(
+    target: &RemoteTarget,
+    base: &str,
+    arch: &str,
+    name: &str,
+) -> Result<bool> {
+    let path = match locate_bundle(arch, name) {
+        Ok(path) => path,
+        Err(err) => {
+            warn!("[sshpod] {} not installed: {:#}", name, err);
+            return Ok(false);
         }
-        Err(e) => e,
     };
+    let source = verified_file(path.clone()).await?;
+    let digest = {
+        let source = source.clone();
+        blocking(move || {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut source.open()?, &mut hasher)?;
+            Ok(format!("{:x}", hasher.finalize()))
+        })
+        .await?
+    };
+    let digest_path = format!("{}/bundle/{}.sha256", base, name);
+    if kubectl::exec_capture_optional_target(target, &["cat", &digest_path])
+        .await?
+        .as_deref()
+        == Some(digest.as_str())
+    {
+        info!("[sshpod] {} already installed", name);
+        return Ok(true);
+    }
+    info!("[sshpod] installing {} from {}", name, path.display());
+    let meta = format!("printf '%s\\n' \"{digest}\" > \"{digest_path}\";");
+    install_payload(target, base, name, &source, &meta)
+        .await
+        .with_context(|| format!("failed to install {}", name))?;
+    Ok(true)
+}
+
+/// Printed by the install script when the written file does not match.
+const CHECKSUM_MISMATCH: &str = "sshpod: checksum mismatch";
+const UPLOAD_ATTEMPTS: u32 = 3;
 
-    let gzip_err = match try_install_gzip(target, &bundle_data, &install_gz, &mut sshd_data).await {
-        Ok(_) => {
-            info!("[sshpod] bundle install completed");
-            return Ok(());
+/// Writes the bundle to `$BASE/bundle/<name>`, decompressing with zstd, xz
+/// or gzip in the container when available, then runs `meta` once the
+/// file's checksum matches. A truncated upload is sent again.
+async fn install_payload(
+    target: &RemoteTarget,
+    base: &str,
+    name: &str,
+    source: &BundleSource,
+    meta: &str,
+) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match upload_payload(target, base, name, source, meta).await {
+            Err(err)
+                if attempt < UPLOAD_ATTEMPTS
+                    && err.root_cause().to_string().contains(CHECKSUM_MISMATCH) =>
+            {
+                warn!(
+                    "[sshpod] {} arrived corrupted, uploading it again ({}/{})",
+                    name, attempt, UPLOAD_ATTEMPTS
+                );
+                attempt += 1;
+            }
+            result => return result,
         }
-        Err(e) => e,
+    }
+}
+
+async fn upload_payload(
+    target: &RemoteTarget,
+    base: &str,
+    name: &str,
+    source: &BundleSource,
+    meta: &str,
+) -> Result<()> {
+    let path = format!("{base}/bundle/{name}");
+    let digest = {
+        let source = source.clone();
+        blocking(move || PlainDigest::of(source.open_plain()?))
+            .await
+            .with_context(|| format!("failed to decompress {} payload", name))?
+    };
+    let verify = verify_script(&path, &digest);
+    let install = |decompress: &str| {
+        format!(
+            "set -eu; umask 077; mkdir -p \"{base}/bundle\"; chmod 700 \"{base}\"; chmod 755 \"{base}/bundle\"; \
+             {decompress} > \"{path}\"; chmod 755 \"{path}\"; {verify} {meta}"
+        )
     };
 
-    let sshd_data = ensure_plain_data(&bundle_data, &mut sshd_data)
-        .context("failed to prepare sshd payload for plain install")?;
-    install_bundle_with_command(target, &install_plain, sshd_data, "plain")
+    let tools = available_tools(target).await?;
+    let mut failures = Vec::new();
+
+    #[cfg(feature = "zstd")]
+    {
+        match try_install_zstd(target, source, &install("zstd -dc"), &tools).await {
+            Ok(_) => return Ok(()),
+            Err(e) => failures.push(format!("zstd: {}", e)),
+        }
+    }
+
+    match try_install_xz(target, source, &install("xz -dc"), &tools).await {
+        Ok(_) => return Ok(()),
+        Err(e) => failures.push(format!("xz: {}", e)),
+    }
+
+    match try_install_gzip(target, source, &install("gzip -dc"), &tools).await {
+        Ok(_) => return Ok(()),
+        Err(e) => failures.push(format!("gzip: {}", e)),
+    }
+
+    install_bundle_with_command(target, &install("cat"), source.open_plain()?, "plain")
         .await
         .with_context(|| {
             format!(
-                "failed to install bundle into {} (xz: {}; gzip: {})",
-                base, xz_err, gzip_err
+                "failed to install {} into {} ({})",
+                name,
+                base,
+                failures.join("; ")
             )
-        })?;
+        })
+}
 
-    info!("[sshpod] bundle install completed");
-    Ok(())
+/// What the container checks the written file against.
+struct PlainDigest {
+    sha256: String,
+    cksum: u32,
+    len: u64,
+}
+
+impl PlainDigest {
+    fn of(mut plain: impl Read) -> Result<PlainDigest> {
+        let mut sha = Sha256::new();
+        let mut cksum = Cksum::default();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = plain.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            sha.update(&buf[..n]);
+            cksum.update(&buf[..n]);
+        }
+        Ok(PlainDigest {
+            sha256: format!("{:x}", sha.finalize()),
+            len: cksum.len,
+            cksum: cksum.finish(),
+        })
+    }
 }
 
-async fn load_bundle_data(arch: &str) -> Result<Cow<'static, [u8]>> {
-    if let Some(data) = embedded::get_bundle(arch) {
+/// Shell fragment that deletes `path` and fails unless it matches `plain`,
+/// checked with whichever of sha256sum, shasum, openssl or cksum the
+/// container has (none at all skips the check).
+fn verify_script(path: &str, digest: &PlainDigest) -> String {
+    let sha = &digest.sha256;
+    let crc = format!("{} {}", digest.cksum, digest.len);
+    format!(
+        "if command -v sha256sum >/dev/null 2>&1; then set -- $(sha256sum \"{path}\"); got=\"${{1:-}}\"; want={sha}; \
+         elif command -v shasum >/dev/null 2>&1; then set -- $(shasum -a 256 \"{path}\"); got=\"${{1:-}}\"; want={sha}; \
+         elif command -v openssl >/dev/null 2>&1; then set -- $(openssl dgst -sha256 -r \"{path}\"); got=\"${{1:-}}\"; want={sha}; \
+         elif command -v cksum >/dev/null 2>&1; then set -- $(cksum \"{path}\"); got=\"${{1:-}} ${{2:-}}\"; want=\"{crc}\"; \
+         else got=; want=; fi; \
+         if [ \"$got\" != \"$want\" ]; then rm -f \"{path}\"; echo \"{CHECKSUM_MISMATCH}: got $got, want $want\" >&2; exit 3; fi;"
+    )
+}
+
+/// CRC printed by POSIX `cksum`: CRC-32 (MSB first) over the data and its
+/// length, complemented.
+#[derive(Default)]
+struct Cksum {
+    crc: u32,
+    len: u64,
+}
+
+impl Cksum {
+    fn update(&mut self, data: &[u8]) {
+        data.iter().for_each(|&byte| self.feed(byte));
+        self.len += data.len() as u64;
+    }
+
+    fn feed(&mut self, byte: u8) {
+        self.crc ^= u32::from(byte) << 24;
+        for _ in 0..8 {
+            self.crc = if self.crc & 0x8000_0000 != 0 {
+                (self.crc << 1) ^ 0x04C1_1DB7
+            } else {
+                self.crc << 1
+            };
+        }
+    }
+
+    fn finish(mut self) -> u32 {
+        let mut len = self.len;
+        while len > 0 {
+            self.feed(len as u8);
+            len >>= 8;
+        }
+        !self.crc
+    }
+}
+
+async fn load_bundle_data(arch: &str, offline: bool) -> Result<BundleSource> {
+    let explicit = BUNDLE_PATH.get().is_some();
+    if download::origin() == BundleOrigin::Url && !explicit {
+        return verified_file(download::fetch(&bundle_filename(arch, "sshd")).await?).await;
+    }
+    if let Some(data) = embedded::get_bundle(arch).filter(|_| !explicit) {
         info!("[sshpod] using embedded bundle for {}", arch);
-        Ok(Cow::from(data))
-    } else {
-        let bundle_path = locate_bundle(arch)?;
-        info!("[sshpod] using local bundle file {}", bundle_path.display());
-        let bytes = tokio::fs::read(&bundle_path)
-            .await
-            .with_context(|| format!("failed to read bundle {}", bundle_path.display()))?;
-        Ok(Cow::from(bytes))
+        let what = format!("embedded {} bundle", arch);
+        let signature = embedded::get_signature(arch);
+        blocking(move || signature::check(&what, data, signature)).await?;
+        return Ok(BundleSource::Embedded(data));
+    }
+    match locate_bundle(arch, "sshd") {
+        Ok(bundle_path) => {
+            info!("[sshpod] using local bundle file {}", bundle_path.display());
+            verified_file(bundle_path).await
+        }
+        Err(err) if explicit => Err(err),
+        Err(err) if offline => {
+            Err(err
+                .context("offline mode: bundles are never downloaded; provide a local bundle file"))
+        }
+        Err(err) => {
+            let path = download::fetch(&bundle_filename(arch, "sshd"))
+                .await
+                .with_context(|| format!("{:#}; downloading it instead failed", err))?;
+            verified_file(path).await
+        }
     }
 }
 
-async fn tool_available(target: &RemoteTarget, tool: &str) -> Result<bool> {
-    Ok(kubectl::exec_capture_optional_target(
-        target,
-        &["sh", "-c", &format!("command -v {}", tool)],
+/// A bundle file, checked against the `.minisig` next to it.
+async fn verified_file(path: PathBuf) -> Result<BundleSource> {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(".minisig");
+    let signature = tokio::fs::read_to_string(&signature_path).await.ok();
+    let source = BundleSource::File(path.clone());
+    let checked = source.clone();
+    blocking(move || {
+        signature::check(
+            &format!("bundle {}", path.display()),
+            checked.open()?,
+            signature.as_deref(),
+        )
+    })
+    .await?;
+    Ok(source)
+}
+
+/// Decompressors present in the container, found with a single exec.
+async fn available_tools(target: &RemoteTarget) -> Result<HashSet<String>> {
+    let script = "for tool in zstd xz gzip; do \
+                  if command -v \"$tool\" >/dev/null 2>&1; then echo \"$tool\"; fi; done";
+    Ok(
+        kubectl::exec_capture_optional_target(target, &["sh", "-c", script])
+            .await?
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect(),
     )
-    .await?
-    .is_some())
 }
 
-fn ensure_plain_data<'a>(
-    bundle_data: &'a [u8],
-    cache: &'a mut Option<Vec<u8>>,
-) -> Result<&'a [u8]> {
-    if cache.is_none() {
-        *cache = Some(decompress_xz(bundle_data)?);
+fn require_tool(tools: &HashSet<String>, tool: &str) -> Result<()> {
+    if !tools.contains(tool) {
+        info!(
+            "[sshpod] skipping {} install ({} not available)",
+            tool, tool
+        );
+        bail!("{} not available in container", tool);
     }
-    Ok(cache.as_ref().unwrap())
+    Ok(())
 }
 
-fn gzip_payload(data: &[u8]) -> Result<Vec<u8>> {
-    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
-    gz.write_all(data).context("failed to write gzip payload")?;
-    gz.finish().context("failed to finalize gzip payload")
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 10;
+
+#[cfg(feature = "zstd")]
+async fn try_install_zstd(
+    target: &RemoteTarget,
+    source: &BundleSource,
+    install_cmd: &str,
+    tools: &HashSet<String>,
+) -> Result<()> {
+    require_tool(tools, "zstd")?;
+    let payload = zstd::stream::read::Encoder::new(source.open_plain()?, ZSTD_LEVEL)
+        .context("failed to start zstd compression")?;
+    install_bundle_with_command(target, install_cmd, Box::new(payload), "zstd").await
 }
 
 async fn try_install_xz(
     target: &RemoteTarget,
-    bundle_data: &[u8],
+    source: &BundleSource,
     install_cmd: &str,
+    tools: &HashSet<String>,
 ) -> Result<()> {
-    if !tool_available(target, "xz").await? {
-        info!("[sshpod] skipping xz install (xz not available)");
-        return Err(anyhow!("xz not available in container"));
-    }
-    install_bundle_with_command(target, install_cmd, bundle_data, "xz").await
+    require_tool(tools, "xz")?;
+    install_bundle_with_command(target, install_cmd, source.open()?, "xz").await
 }
 
 async fn try_install_gzip(
     target: &RemoteTarget,
-    bundle_data: &[u8],
+    source: &BundleSource,
     install_cmd: &str,
-    sshd_cache: &mut Option<Vec<u8>>,
+    tools: &HashSet<String>,
 ) -> Result<()> {
-    if !tool_available(target, "gzip").await? {
-        info!("[sshpod] skipping gzip install (gzip not available)");
-        return Err(anyhow!("gzip not available in container"));
-    }
-    let sshd_data_ref = ensure_plain_data(bundle_data, sshd_cache)?;
-    let gz_data = gzip_payload(sshd_data_ref)?;
-    install_bundle_with_command(target, install_cmd, &gz_data, "gzip").await
+    require_tool(tools, "gzip")?;
+    let payload = GzEncoder::new(source.open_plain()?, Compression::default());
+    install_bundle_with_command(target, install_cmd, Box::new(payload), "gzip").await
 }
 
 async fn install_bundle_with_command(
     target: &RemoteTarget,
     install_cmd: &str,
-    payload: &[u8],
+    payload: Box<dyn Read + Send>,
     label: &str,
 ) -> Result<()> {
     info!("[sshpod] installing bundle via {}", label);
-    kubectl::exec_with_input_target(target, &["sh", "-c", install_cmd], payload).await?;
+    kubectl::exec_with_reader_target(target, &["sh", "-c", install_cmd], payload).await?;
     Ok(())
 }
 
-fn locate_bundle(arch: &str) -> Result<PathBuf> {
-    let filename = match arch {
-        "linux/amd64" => "sshd_amd64.xz".to_string(),
-        "linux/arm64" => "sshd_arm64.xz".to_string(),
-        _ => format!("sshd_{}.xz", arch.replace('/', "_")),
-    };
+fn bundle_filename(arch: &str, prefix: &str) -> String {
+    match arch {
+        "linux/amd64" => format!("{}_amd64.xz", prefix),
+        "linux/arm64" => format!("{}_arm64.xz", prefix),
+        _ => format!("{}_{}.xz", prefix, arch.replace('/', "_")),
+    }
+}
+
+fn locate_bundle(arch: &str, prefix: &str) -> Result<PathBuf> {
+    if let Some(path) = BUNDLE_PATH.get() {
+        return explicit_bundle(path, arch, prefix);
+    }
+    let filename = bundle_filename(arch, prefix);
     let mut candidates = Vec::new();
     let mut seen = HashSet::new();
 
@@ -206,56 +506,90 @@ fn locate_bundle(arch: &str) -> Result<PathBuf> {
     );
 }
 
-fn decompress_xz(data: &[u8]) -> Result<Vec<u8>> {
-    let mut decoder = XzDecoder::new(data);
+/// The bundle `--bundle-path` names: a file stands for the sshd bundle
+/// (other bundles are looked up beside it), a directory is searched.
+fn explicit_bundle(path: &Path, arch: &str, prefix: &str) -> Result<PathBuf> {
+    let filename = bundle_filename(arch, prefix);
+    let candidate = if path.is_dir() {
+        path.join(&filename)
+    } else if prefix == "sshd" {
+        path.to_path_buf()
+    } else {
+        path.parent().unwrap_or(Path::new(".")).join(&filename)
+    };
+    if !candidate.is_file() {
+        bail!(
+            "bundle file {} not found (--bundle-path {})",
+            candidate.display(),
+            path.display()
+        );
+    }
+    Ok(candidate)
+}
+
+#[cfg(not(any(feature = "xz-native", feature = "xz-pure")))]
+compile_error!("enable the xz-native or xz-pure feature");
+
+#[cfg(feature = "xz-native")]
+fn decompress_xz(data: Box<dyn Read + Send>) -> Result<Box<dyn Read + Send>> {
+    Ok(Box::new(xz2::read::XzDecoder::new(data)))
+}
+
+/// lzma-rs has no streaming reader, so this build holds the decompressed
+/// bundle in memory.
+#[cfg(all(feature = "xz-pure", not(feature = "xz-native")))]
+fn decompress_xz(data: Box<dyn Read + Send>) -> Result<Box<dyn Read + Send>> {
     let mut buf = Vec::new();
-    decoder
-        .read_to_end(&mut buf)
-        .context("failed to decompress xz")?;
-    Ok(buf)
+    lzma_rs::xz_decompress(&mut BufReader::new(data), &mut buf)
+        .map_err(|err| anyhow::anyhow!("failed to decompress xz: {:?}", err))?;
+    Ok(Box::new(std::io::Cursor::new(buf)))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{decompress_xz, ensure_plain_data, gzip_payload, load_bundle_data};
-    use flate2::read::GzDecoder;
-    use std::io::{Read, Write};
+    use super::{
+        decompress_xz, explicit_bundle, load_bundle_data, verify_script, BundleSource, Cksum,
+        PlainDigest,
+    };
+    use flate2::read::{GzDecoder, GzEncoder};
+    use flate2::Compression;
+    use std::io::Read;
     use std::{fs, path::PathBuf};
     use tokio::runtime::Runtime;
-    use xz2::write::XzEncoder;
 
-    #[test]
-    fn decompress_smoke() {
-        let mut encoder = XzEncoder::new(Vec::new(), 6);
-        encoder.write_all(b"hello world").unwrap();
-        let data = encoder.finish().unwrap();
-        let out = decompress_xz(&data).expect("decompress");
-        assert_eq!(out, b"hello world");
+    #[cfg(feature = "xz-native")]
+    fn compress_xz(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(all(feature = "xz-pure", not(feature = "xz-native")))]
+    fn compress_xz(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        lzma_rs::xz_compress(&mut &data[..], &mut out).unwrap();
+        out
+    }
+
+    fn read_all(mut reader: impl Read) -> Vec<u8> {
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        out
     }
 
     #[test]
-    fn ensure_plain_data_caches_decompression() {
-        let mut encoder = XzEncoder::new(Vec::new(), 6);
-        encoder.write_all(b"cache me").unwrap();
-        let data = encoder.finish().unwrap();
-
-        let mut cache = None;
-        let first = ensure_plain_data(&data, &mut cache).expect("first decode");
-        assert_eq!(first, b"cache me");
-        let first_ptr = first.as_ptr();
-
-        let second = ensure_plain_data(&data, &mut cache).expect("second decode");
-        assert_eq!(second, b"cache me");
-        assert_eq!(first_ptr, second.as_ptr(), "cache should be reused");
+    fn decompress_smoke() {
+        let data = compress_xz(b"hello world");
+        let out = decompress_xz(Box::new(std::io::Cursor::new(data))).expect("decompress");
+        assert_eq!(read_all(out), b"hello world");
     }
 
     #[test]
-    fn gzip_payload_round_trip() {
-        let gz = gzip_payload(b"ping").expect("gzip");
-        let mut decoder = GzDecoder::new(&gz[..]);
-        let mut out = String::new();
-        decoder.read_to_string(&mut out).expect("gunzip");
-        assert_eq!(out.as_bytes(), b"ping");
+    fn gzip_stream_round_trip() {
+        let source = BundleSource::Embedded(Box::leak(compress_xz(b"ping").into_boxed_slice()));
+        let gz = GzEncoder::new(source.open_plain().unwrap(), Compression::default());
+        assert_eq!(read_all(GzDecoder::new(&read_all(gz)[..])), b"ping");
     }
 
     #[test]
@@ -263,16 +597,70 @@ mod tests {
         let rt = Runtime::new().unwrap();
         let path = PathBuf::from("sshd_test.xz");
 
-        let mut encoder = XzEncoder::new(Vec::new(), 6);
-        encoder.write_all(b"from file").unwrap();
-        let data = encoder.finish().unwrap();
+        let data = compress_xz(b"from file");
         fs::write(&path, &data).expect("write test bundle");
 
         let loaded = rt
-            .block_on(load_bundle_data("test"))
+            .block_on(load_bundle_data("test", false))
             .expect("load bundle data");
-        assert_eq!(&*loaded, data.as_slice());
+        assert!(matches!(loaded, BundleSource::File(_)));
+        assert_eq!(read_all(loaded.open().unwrap()), data);
+        assert_eq!(read_all(loaded.open_plain().unwrap()), b"from file");
 
         fs::remove_file(&path).ok();
     }
+
+    #[test]
+    fn explicit_bundle_path_is_a_file_or_directory() {
+        let dir = std::env::temp_dir().join(format!("sshpod-bundle-path-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let sshd = dir.join("custom-sshd.xz");
+        fs::write(&sshd, b"sshd").unwrap();
+        fs::write(dir.join("rsync_arm64.xz"), b"rsync").unwrap();
+
+        assert_eq!(explicit_bundle(&sshd, "linux/amd64", "sshd").unwrap(), sshd);
+        assert_eq!(
+            explicit_bundle(&sshd, "linux/arm64", "rsync").unwrap(),
+            dir.join("rsync_arm64.xz")
+        );
+        assert_eq!(
+            explicit_bundle(&dir, "linux/arm64", "rsync").unwrap(),
+            dir.join("rsync_arm64.xz")
+        );
+        assert!(explicit_bundle(&dir, "linux/amd64", "sshd").is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn cksum_matches_coreutils() {
+        let cksum = |chunks: &[&[u8]]| {
+            let mut cksum = Cksum::default();
+            chunks.iter().for_each(|chunk| cksum.update(chunk));
+            cksum.finish()
+        };
+        assert_eq!(cksum(&[]), 4294967295);
+        assert_eq!(cksum(&[b"1234", b"56789"]), 930766865);
+        assert_eq!(cksum(&[b"hello\n"]), 3015617425);
+    }
+
+    #[test]
+    fn verify_script_rejects_truncated_files() {
+        let dir = std::env::temp_dir().join(format!("sshpod-verify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sshd");
+        let path_str = path.to_str().unwrap();
+        let digest = PlainDigest::of(&b"full contents"[..]).unwrap();
+        let run = || {
+            std::process::Command::new("sh")
+                .args(["-c", &verify_script(path_str, &digest)])
+                .status()
+                .unwrap()
+        };
+        fs::write(&path, b"full contents").unwrap();
+        assert!(run().success());
+        fs::write(&path, b"full con").unwrap();
+        assert_eq!(run().code(), Some(3));
+        assert!(!path.exists(), "a corrupt file is removed");
+        fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,128 @@
+use crate::audit::{self, Finding, MAX_PARALLEL_EXECS};
+use crate::cli::CleanArgs;
+use crate::kubectl::{self, RemoteTarget};
+use crate::remote;
+use crate::state::StateStore;
+use anyhow::{bail, Result};
+use log::warn;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+pub async fn run(args: CleanArgs) -> Result<()> {
+    let contexts: Vec<Option<String>> = if args.all_contexts {
+        kubectl::list_contexts()
+            .await?
+            .into_iter()
+            .map(Some)
+            .collect()
+    } else {
+        if let Some(ctx) = &args.context {
+            kubectl::ensure_context_exists(ctx).await?;
+        }
+        vec![args.context.clone()]
+    };
+
+    let mut findings = Vec::new();
+    for context in contexts {
+        let namespace = if args.all_namespaces {
+            None
+        } else {
+            Some(audit::resolve_namespace(context.as_deref(), args.namespace.clone()).await?)
+        };
+        match kubectl::list_pods(context.as_deref(), namespace.as_deref()).await {
+            Ok(pods) => findings.extend(audit::find_installs(context, pods).await),
+            Err(err) if args.all_contexts => {
+                warn!(
+                    "[sshpod] skipping context {}: {:#}",
+                    context.as_deref().unwrap_or("default"),
+                    err
+                );
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    if findings.is_empty() {
+        println!("No sshpod installs found");
+        return Ok(());
+    }
+    for f in &findings {
+        println!(
+            "{}{} {}/{} container={}",
+            if args.dry_run {
+                "would remove "
+            } else {
+                "removing "
+            },
+            f.context.as_deref().unwrap_or("default"),
+            f.namespace,
+            f.pod,
+            f.container
+        );
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let failures = remove_all(findings).await;
+    if failures > 0 {
+        bail!("failed to clean {} install(s)", failures);
+    }
+    Ok(())
+}
+
+async fn remove_all(findings: Vec<Finding>) -> usize {
+    let limit = Arc::new(Semaphore::new(MAX_PARALLEL_EXECS));
+    let mut tasks = JoinSet::new();
+    for finding in findings {
+        let limit = limit.clone();
+        tasks.spawn(async move {
+            let _permit = limit.acquire_owned().await?;
+            let target = RemoteTarget {
+                context: finding.context.clone(),
+                namespace: finding.namespace.clone(),
+                pod: finding.pod.clone(),
+                container: finding.container.clone(),
+            };
+            let base = remote::base_dir(&finding.pod_uid, &finding.container);
+            remote::uninstall(&target, &base).await?;
+            Ok::<_, anyhow::Error>(finding)
+        });
+    }
+
+    let mut removed = Vec::new();
+    let mut failures = 0;
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(Ok(finding)) => removed.push(finding),
+            Ok(Err(err)) => {
+                warn!("[sshpod] {:#}", err);
+                failures += 1;
+            }
+            Err(err) => {
+                warn!("[sshpod] clean task failed: {}", err);
+                failures += 1;
+            }
+        }
+    }
+
+    let forgotten = match StateStore::open() {
+        Ok(store) => {
+            store
+                .update(|state| {
+                    state.installs.retain(|install| {
+                        !removed.iter().any(|f| {
+                            f.pod_uid == install.pod_uid && f.container == install.container
+                        })
+                    })
+                })
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = forgotten {
+        warn!("[sshpod] failed to update local state: {:#}", err);
+    }
+    failures
+}
@@ -1,6 +1,27 @@
-use crate::{install, proxy};
+use crate::activation::ServiceManager;
+use crate::config::Config;
+use crate::crypto::CryptoPreset;
+use crate::download::{BundleOrigin, DEFAULT_URL};
+use crate::helper_pod::HelperPodArgs;
+use crate::hostspec::{self, SelectStrategy, TargetKind};
+use crate::{
+    audit, clean, cp, dns, docker, git_remote, install, inventory, invite, kubectl, logging,
+    manifest, mount, proxy, remote, self_test, status, top, volume,
+};
 use anyhow::{anyhow, Result};
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Set when running as `kubectl-sshpod`, i.e. as `kubectl sshpod ...`.
+static KUBECTL_PLUGIN: AtomicBool = AtomicBool::new(false);
+
+pub fn is_kubectl_plugin() -> bool {
+    KUBECTL_PLUGIN.load(Ordering::Relaxed)
+}
 
 #[derive(Parser)]
 #[command(
@@ -11,6 +32,19 @@ use clap::{Args, Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// kubectl binary to run (e.g. a pinned version or a wrapper)
+    #[arg(long, global = true, env = "SSHPOD_KUBECTL", value_name = "PATH")]
+    kubectl_bin: Option<PathBuf>,
+    /// Client to run: kubectl or OpenShift's oc [default: kubectl, or oc when
+    /// only oc is installed]
+    #[arg(long, global = true, env = "SSHPOD_CLIENT", value_enum)]
+    client: Option<kubectl::ClientKind>,
+    /// Give up on a kubectl call after this long (e.g. 30s, 2m; 0 disables)
+    #[arg(long, global = true, env = "SSHPOD_KUBECTL_TIMEOUT", value_parser = parse_duration)]
+    kubectl_timeout: Option<Duration>,
+    /// Retries for kubectl get/exec calls that fail with a transient API error
+    #[arg(long, global = true, env = "SSHPOD_KUBECTL_RETRIES")]
+    kubectl_retries: Option<u32>,
 }
 
 #[derive(Subcommand)]
@@ -18,14 +52,272 @@ enum Commands {
     /// ProxyCommand entry point
     Proxy(ProxyArgs),
     /// Update ~/.ssh/config with the sshpod ProxyCommand block
-    Configure,
+    Configure(ConfigureArgs),
+    /// Show installs and sessions recorded in the local state store
+    Status,
+    /// Print the minimal RBAC Role/RoleBinding sshpod needs in a namespace
+    Manifest(ManifestArgs),
+    /// Report pods that have sshpod installed, with version and key owners
+    Audit(AuditArgs),
+    /// Remove sshpod installs from every pod in a namespace, cluster, or all contexts
+    Clean(CleanArgs),
+    /// Serve a local DNS stub that resolves cluster names inside a pod
+    Dns(DnsArgs),
+    /// Browse a PersistentVolumeClaim over SFTP through a temporary helper pod
+    Volume(VolumeArgs),
+    /// Authorize a teammate's public key on a pod for pair debugging
+    Invite(InviteArgs),
+    /// Live CPU/memory/fd view of the processes in a container
+    Top(TopArgs),
+    /// Copy files to or from a pod over SFTP
+    Cp(CpArgs),
+    /// Mount a pod directory locally with sshfs until interrupted
+    Mount(MountArgs),
+    /// Print running pods as sshpod hosts for automation tools
+    Inventory(InventoryArgs),
+    /// Forward a pod's docker/containerd socket to a local unix socket
+    Docker(DockerArgs),
+    /// Check the whole connection pipeline to a pod with a temporary key
+    Test(TestArgs),
+    /// git remote helper; invoked by git as git-remote-sshpod
+    #[command(hide = true)]
+    GitRemote(GitRemoteArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct TestArgs {
+    /// sshpod hostname, e.g. deployment--api.namespace--prod
+    pub host: String,
+    /// Login user in the container
+    #[arg(long)]
+    pub user: Option<String>,
+    /// Algorithm preset, matching the one used by `sshpod configure`
+    #[arg(long, value_enum, default_value_t)]
+    pub crypto: CryptoPreset,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DockerArgs {
+    /// sshpod hostname, e.g. pod--builder.namespace--ci
+    pub host: String,
+    /// Runtime socket in the pod (default: first of the usual docker/containerd paths)
+    #[arg(long)]
+    pub socket: Option<String>,
+    /// Local socket path (default: ~/.local/state/sshpod/docker/<host>.sock)
+    #[arg(long)]
+    pub local: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct InventoryArgs {
+    /// Only include pods whose name matches this glob
+    pub pattern: Option<String>,
+    /// kubectl context to list (defaults to the current context)
+    #[arg(long)]
+    pub context: Option<String>,
+    /// Namespace to list (defaults to the context namespace)
+    #[arg(long, short = 'n')]
+    pub namespace: Option<String>,
+    /// List every namespace
+    #[arg(long, short = 'A', conflicts_with = "namespace")]
+    pub all_namespaces: bool,
+    #[arg(long, value_enum, default_value_t = InventoryFormat::Ansible)]
+    pub format: InventoryFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InventoryFormat {
+    Ansible,
+    Json,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GitRemoteArgs {
+    /// Remote name (or URL when git has no name for it)
+    pub remote: String,
+    /// <host>:<path> from an sshpod::<host>:<path> URL
+    pub url: String,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct MountArgs {
+    /// Remote directory as <host>:<path>
+    pub source: String,
+    /// Local directory to mount on
+    pub mountpoint: PathBuf,
+    /// Login user in the container
+    #[arg(long)]
+    pub user: Option<String>,
+    /// Algorithm preset, matching the one used by `sshpod configure`
+    #[arg(long, value_enum, default_value_t)]
+    pub crypto: CryptoPreset,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CpArgs {
+    /// Source: a local path or <host>:<path>
+    pub source: String,
+    /// Destination: a local path or <host>:<path>
+    pub dest: String,
+    /// Continue an interrupted transfer instead of starting over
+    #[arg(long)]
+    pub resume: bool,
+    /// Compare SHA-256 of both sides after the transfer
+    #[arg(long)]
+    pub checksum: bool,
+    /// Concurrent SFTP sessions (over one SSH connection) for directory copies
+    #[arg(short = 'j', long, default_value_t = 4)]
+    pub jobs: usize,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct TopArgs {
+    /// Hostspec of the container to watch
+    pub host: String,
+    /// Time between samples (e.g. 2s)
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub interval: Duration,
+    /// Exit after this many samples
+    #[arg(short = 'n', long)]
+    pub count: Option<u32>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct InviteArgs {
+    /// Hostspec of the pod to share
+    pub host: String,
+    /// Teammate's OpenSSH public key file
+    #[arg(long)]
+    pub key: PathBuf,
+    /// End the invitation and remove the key after this long (e.g. 30m, 1h)
+    #[arg(long, value_parser = parse_duration)]
+    pub ttl: Option<Duration>,
+    /// Address the teammate connects to; relayed to the pod's sshd. Loopback
+    /// by default; pass e.g. 0.0.0.0:2222 to accept other machines
+    #[arg(long, default_value = "127.0.0.1:0")]
+    pub listen: SocketAddr,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct VolumeArgs {
+    /// PVC name with optional tokens, e.g. data.namespace--ns.context--ctx
+    pub spec: String,
+    /// Mount the volume read-write instead of read-only
+    #[arg(long)]
+    pub rw: bool,
+    #[command(flatten)]
+    pub helper: HelperPodArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DnsArgs {
+    /// sshpod hostname of the pod used for lookups
+    pub host: String,
+    /// Address the DNS stub listens on
+    #[arg(long, default_value = "127.0.0.1:5353")]
+    pub listen: SocketAddr,
+    /// Domain answered by the stub; other names are refused
+    #[arg(long, default_value = "cluster.local")]
+    pub domain: String,
+    /// Exit after no queries for this long (e.g. 10m), for socket activation
+    #[arg(long, value_parser = parse_duration)]
+    pub idle_timeout: Option<Duration>,
+    /// Print systemd units or a launchd plist that start the stub on demand
+    #[arg(long, value_enum)]
+    pub print_units: Option<ServiceManager>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CleanArgs {
+    /// kubectl context to clean (defaults to the current context)
+    #[arg(long, conflicts_with = "all_contexts")]
+    pub context: Option<String>,
+    /// Namespace to clean (defaults to the context namespace)
+    #[arg(long, short = 'n')]
+    pub namespace: Option<String>,
+    /// Clean every namespace
+    #[arg(long, short = 'A', conflicts_with = "namespace")]
+    pub all_namespaces: bool,
+    /// Clean every context in the kubeconfig
+    #[arg(long)]
+    pub all_contexts: bool,
+    /// Only list what would be removed
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct AuditArgs {
+    /// kubectl context to scan (defaults to the current context)
+    #[arg(long)]
+    pub context: Option<String>,
+    /// Namespace to scan (defaults to the context namespace)
+    #[arg(long, short = 'n')]
+    pub namespace: Option<String>,
+    /// Scan every namespace
+    #[arg(long, short = 'A', conflicts_with = "namespace")]
+    pub all_namespaces: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ManifestArgs {
+    /// Namespace the Role is scoped to
+    #[arg(long, short = 'n')]
+    pub namespace: String,
+    /// Name of the Role and RoleBinding
+    #[arg(long, default_value = "sshpod")]
+    pub name: String,
+    /// User to bind the Role to (repeatable)
+    #[arg(long)]
+    pub user: Vec<String>,
+    /// Group to bind the Role to (repeatable)
+    #[arg(long)]
+    pub group: Vec<String>,
+    /// Leave out ephemeral containers (only needed for --debug-container)
+    #[arg(long)]
+    pub no_ephemeral_containers: bool,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct ConfigureArgs {
+    /// Bake --offline into the generated ProxyCommand
+    #[arg(long)]
+    pub offline: bool,
+    /// Algorithm preset baked into the ProxyCommand and IdentityFile
+    #[arg(long, value_enum, default_value_t)]
+    pub crypto: CryptoPreset,
+    /// Bake --with-rsync into the ProxyCommand so `rsync -e ssh` works on every pod
+    #[arg(long)]
+    pub with_rsync: bool,
+    /// Reach hosts of kubectl context CONTEXT through the sshpod host HOST (repeatable)
+    #[arg(long, value_name = "CONTEXT=HOST", value_parser = parse_jump_route)]
+    pub jump: Vec<(String, String)>,
 }
 
 #[derive(Args, Debug, Clone)]
 pub struct ProxyArgs {
-    /// Target host (e.g. api-xxxx.ns.sshpod)
+    /// Target host (e.g. api-xxxx.ns.sshpod), or a plain name with the targeting flags
     #[arg(long)]
     pub host: String,
+    /// Namespace, overriding namespace-- in the hostname
+    #[arg(long)]
+    pub namespace: Option<String>,
+    /// kubectl context, overriding context-- in the hostname
+    #[arg(long)]
+    pub context: Option<String>,
+    /// Container, overriding container-- in the hostname
+    #[arg(long)]
+    pub container: Option<String>,
+    /// kubeconfig file to use instead of $KUBECONFIG or ~/.kube/config
+    #[arg(long, env = "SSHPOD_KUBECONFIG", value_name = "PATH")]
+    pub kubeconfig: Option<PathBuf>,
+    /// Treat --host as a plain name of this kind instead of an encoded hostname
+    #[arg(long, value_enum)]
+    pub target_kind: Option<TargetKind>,
+    /// Look for a pod-- target in every namespace when no namespace is given
+    /// and it is not in the context's namespace
+    #[arg(long)]
+    pub all_namespaces: bool,
     /// SSH login user (defaults to local user)
     #[arg(long)]
     pub user: Option<String>,
@@ -35,13 +327,270 @@ pub struct ProxyArgs {
     /// Log level: error, info, debug
     #[arg(long, default_value = "info")]
     pub log_level: String,
+    /// Increase verbosity (-v: debug, -vv: trace and live remote script output)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// Print a per-phase latency table to stderr once setup finishes
+    #[arg(long)]
+    pub timings: bool,
+    /// Append all logs (at every level) to this file, rotating it past 5 MiB
+    #[arg(long, env = "SSHPOD_LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+    /// Interactive login shell in the container (e.g. bash, zsh, fish)
+    #[arg(long)]
+    pub shell: Option<String>,
+    /// Create the login user in root containers when it does not exist
+    #[arg(long)]
+    pub create_user: bool,
+    /// Reach this cluster's API through a SOCKS proxy over another sshpod host
+    #[arg(long)]
+    pub jump: Option<String>,
+    /// When the container is crash-looping, wait this long for it to run (e.g. 5m)
+    #[arg(long, value_parser = parse_duration)]
+    pub wait_running: Option<Duration>,
+    /// For completed pods, connect to a temporary copy that mounts the same volumes
+    #[arg(long)]
+    pub debug_copy: bool,
+    /// Run sshd in an ephemeral container sharing the target container's
+    /// process namespace (for distroless or shell-less images)
+    #[arg(long)]
+    pub debug_container: bool,
+    /// Image for --debug-container (needs sh) [default: busybox:1.36]
+    #[arg(long)]
+    pub debug_image: Option<String>,
+    /// Remove the client key from the pod's authorized_keys when the session ends
+    #[arg(long)]
+    pub ephemeral_key: bool,
+    /// Install the optional static rsync bundle (rsync_<arch>.xz) into the pod
+    #[arg(long)]
+    pub with_rsync: bool,
+    /// Serve SFTP with the bundled sftp-server (sftp-server_<arch>.xz) instead of internal-sftp
+    #[arg(long)]
+    pub sftp_server: bool,
+    /// Umask forced on files created over SFTP (implies --sftp-server)
+    #[arg(long, value_name = "MASK", value_parser = remote::parse_umask)]
+    pub sftp_umask: Option<String>,
+    /// Run setup probes over one long-lived kubectl exec instead of one exec each
+    #[arg(long, env = "SSHPOD_EXEC_CHANNEL", value_parser = clap::builder::BoolishValueParser::new())]
+    pub exec_channel: bool,
+    /// Show sshd auth failures and errors from the pod on stderr during the session
+    #[arg(long)]
+    pub follow: bool,
+    /// Connect straight to sshd on the pod IP when reachable, falling back to port-forward
+    #[arg(long)]
+    pub direct: bool,
+    /// For node-- targets, run sessions chrooted into the node's root filesystem
+    #[arg(long)]
+    pub chroot_host: bool,
+    /// How to choose among ready pods of a deployment, daemonset, service,
+    /// rollout or deploymentconfig
+    #[arg(long, value_enum)]
+    pub select: Option<SelectStrategy>,
+    /// Authorize this public key file instead of the sshpod client key
+    #[arg(long, value_name = "PATH", hide = true)]
+    pub authorize_key: Option<PathBuf>,
+    /// Write JSON progress events, one per line, to this inherited file descriptor
+    #[arg(long, value_name = "FD")]
+    pub events_fd: Option<i32>,
+    /// Write JSON progress events to this unix socket
+    #[arg(long, value_name = "PATH", conflicts_with = "events_fd")]
+    pub events_socket: Option<PathBuf>,
+    /// Forbid any network access other than the Kubernetes API
+    #[arg(long, env = "SSHPOD_OFFLINE", value_parser = clap::builder::BoolishValueParser::new())]
+    pub offline: bool,
+    /// Bundle file or directory to use instead of searching for one
+    #[arg(long, value_name = "PATH", env = "SSHPOD_BUNDLE_PATH")]
+    pub bundle_path: Option<PathBuf>,
+    /// Where the sshd bundle comes from
+    #[arg(long, value_enum, env = "SSHPOD_BUNDLE_SOURCE", default_value_t)]
+    pub bundle_source: BundleOrigin,
+    /// Base URL of downloadable bundles (<URL>/sshd_amd64.xz and its .sha256)
+    #[arg(long, value_name = "URL", env = "SSHPOD_BUNDLE_URL", default_value = DEFAULT_URL)]
+    pub bundle_url: String,
+    /// Algorithm preset for keys and the remote sshd_config
+    #[arg(long, value_enum, default_value_t)]
+    pub crypto: CryptoPreset,
+    /// Maximum disk space (MiB) sshpod may use inside the pod; 0 disables the cap
+    #[arg(long, default_value_t = 64)]
+    pub max_remote_disk_mib: u64,
+    /// Niceness of the remote sshd and its sessions (0 disables nice/ionice)
+    #[arg(long, default_value_t = 10, allow_negative_numbers = true)]
+    pub remote_nice: i32,
+    /// Process limit (ulimit -u) for the remote sshd; 0 leaves it unchanged
+    #[arg(long, default_value_t = 0)]
+    pub remote_max_processes: u32,
+    /// Remove the in-pod install and stop sshd this long after setup (e.g. 24h)
+    #[arg(long, value_parser = parse_duration)]
+    pub remote_ttl: Option<Duration>,
+}
+
+/// kubectl hands its own flags to plugins, so `kubectl sshpod --context c
+/// -n ns audit` arrives with them ahead of the subcommand. Moves
+/// `--context`/`--namespace` behind the subcommand when it takes them and
+/// returns `--kubeconfig` separately, since it applies to every command.
+fn hoist_kubectl_flags(argv: Vec<OsString>) -> (Vec<OsString>, Option<PathBuf>) {
+    let command = Cli::command();
+    let mut rest = argv.into_iter();
+    let mut out: Vec<OsString> = rest.next().into_iter().collect();
+    let mut hoisted: Vec<(&str, OsString)> = Vec::new();
+    let mut kubeconfig = None;
+    while let Some(arg) = rest.next() {
+        let Some(text) = arg.to_str().map(str::to_string) else {
+            out.push(arg);
+            continue;
+        };
+        if !text.starts_with('-') {
+            let takes = |long: &str| {
+                command
+                    .find_subcommand(&text)
+                    .is_some_and(|sub| sub.get_arguments().any(|a| a.get_long() == Some(long)))
+            };
+            let (inner, outer): (Vec<_>, Vec<_>) =
+                hoisted.drain(..).partition(|(long, _)| takes(long));
+            for (long, value) in outer {
+                out.extend([format!("--{}", long).into(), value]);
+            }
+            out.push(arg);
+            for (long, value) in inner {
+                out.extend([format!("--{}", long).into(), value]);
+            }
+            out.extend(rest);
+            return (out, kubeconfig);
+        }
+        let (flag, inline) = match text.split_once('=') {
+            Some((flag, value)) => (flag, Some(OsString::from(value))),
+            None => (text.as_str(), None),
+        };
+        let long = match flag {
+            "--context" => "context",
+            "-n" | "--namespace" => "namespace",
+            "--kubeconfig" => "kubeconfig",
+            _ => {
+                // Keep the value of sshpod's own global flags with the flag.
+                let takes_value = inline.is_none()
+                    && command.get_arguments().any(|a| {
+                        flag.strip_prefix("--") == a.get_long() && a.get_action().takes_values()
+                    });
+                out.push(arg);
+                if takes_value {
+                    out.extend(rest.next());
+                }
+                continue;
+            }
+        };
+        match inline.or_else(|| rest.next()) {
+            Some(value) if long == "kubeconfig" => kubeconfig = Some(PathBuf::from(value)),
+            Some(value) => hoisted.push((long, value)),
+            None => out.push(arg),
+        }
+    }
+    for (long, value) in hoisted {
+        out.extend([format!("--{}", long).into(), value]);
+    }
+    (out, kubeconfig)
+}
+
+/// Parses `CONTEXT=HOST` for `configure --jump`.
+fn parse_jump_route(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((context, host)) if !context.is_empty() && !host.is_empty() => {
+            Ok((context.to_string(), hostspec::with_suffix(host)))
+        }
+        _ => Err(format!("expected CONTEXT=HOST, got `{}`", value)),
+    }
+}
+
+/// Parses durations such as `90`, `90s`, `30m`, `24h`, or `7d`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", value))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit in `{}`", value)),
+    };
+    Ok(Duration::from_secs(number.saturating_mul(multiplier)))
 }
 
 pub async fn run() -> Result<()> {
-    let cli = Cli::parse();
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    // git runs `git-remote-sshpod <remote> <url>`; that name is a symlink to sshpod.
+    let invoked_as = argv
+        .first()
+        .and_then(|arg0| std::path::Path::new(arg0).file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned());
+    if invoked_as.as_deref() == Some("git-remote-sshpod") {
+        argv.insert(1, "git-remote".into());
+    }
+    // krew installs the binary as `kubectl-sshpod`; kubectl maps `-` to `_`
+    // when looking plugins up, so both names mean the same.
+    let cli = if matches!(
+        invoked_as.as_deref(),
+        Some("kubectl-sshpod" | "kubectl_sshpod")
+    ) {
+        KUBECTL_PLUGIN.store(true, Ordering::Relaxed);
+        let (argv, kubeconfig) = hoist_kubectl_flags(argv);
+        if let Some(path) = kubeconfig {
+            kubectl::set_kubeconfig(path);
+        }
+        let matches = Cli::command()
+            .name("kubectl sshpod")
+            .bin_name("kubectl sshpod")
+            .get_matches_from(argv);
+        Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+    } else {
+        Cli::parse_from(argv)
+    };
+    kubectl::set_client(cli.client);
+    if let Some(path) = cli.kubectl_bin.clone() {
+        kubectl::set_kubectl_bin(path);
+    }
+    if let Some(limit) = cli.kubectl_timeout {
+        kubectl::set_command_timeout(limit);
+    }
+    if let Some(count) = cli.kubectl_retries {
+        kubectl::set_retries(count);
+    }
+    if let Ok(backend) = std::env::var("SSHPOD_BACKEND") {
+        kubectl::set_backend(&backend)?;
+    }
+    // Config errors surface again once a command loads the config itself.
+    match std::env::var("SSHPOD_SUFFIX") {
+        Ok(suffix) if !suffix.is_empty() => hostspec::set_suffix(&suffix),
+        _ => {
+            if let Some(suffix) = Config::load().ok().as_ref().and_then(Config::suffix) {
+                hostspec::set_suffix(suffix);
+            }
+        }
+    }
+    if !matches!(cli.command, Some(Commands::Proxy(_))) {
+        logging::init_logger("warn", None);
+    }
     match cli.command {
         Some(Commands::Proxy(args)) => proxy::run(args).await?,
-        Some(Commands::Configure) => install::run().await?,
+        Some(Commands::Configure(args)) => install::run(args).await?,
+        Some(Commands::Status) => status::run().await?,
+        Some(Commands::Manifest(args)) => manifest::run(args).await?,
+        Some(Commands::Audit(args)) => audit::run(args).await?,
+        Some(Commands::Clean(args)) => clean::run(args).await?,
+        Some(Commands::Dns(args)) => dns::run(args).await?,
+        Some(Commands::Volume(args)) => volume::run(args).await?,
+        Some(Commands::Invite(args)) => invite::run(args).await?,
+        Some(Commands::Top(args)) => top::run(args).await?,
+        Some(Commands::Cp(args)) => cp::run(args).await?,
+        Some(Commands::Mount(args)) => mount::run(args).await?,
+        Some(Commands::Inventory(args)) => inventory::run(args).await?,
+        Some(Commands::Docker(args)) => docker::run(args).await?,
+        Some(Commands::Test(args)) => self_test::run(args).await?,
+        Some(Commands::GitRemote(args)) => git_remote::run(args).await?,
         None => {
             return Err(anyhow!(
                 "no command provided. Use the configure or proxy subcommands."
@@ -50,3 +599,118 @@ pub async fn run() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("24h"), Ok(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172800)));
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("5w").is_err());
+    }
+
+    #[test]
+    fn parse_jump_routes() {
+        assert_eq!(
+            parse_jump_route("prod=pod--bastion.context--hub"),
+            Ok((
+                "prod".to_string(),
+                "pod--bastion.context--hub.sshpod".to_string()
+            ))
+        );
+        assert!(parse_jump_route("prod").is_err());
+        assert!(parse_jump_route("=pod--bastion").is_err());
+    }
+
+    #[test]
+    fn hoists_kubectl_plugin_flags() {
+        let hoist = |argv: &[&str]| {
+            let (out, kubeconfig) = hoist_kubectl_flags(argv.iter().map(OsString::from).collect());
+            let out: Vec<String> = out
+                .into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect();
+            (out.join(" "), kubeconfig)
+        };
+        assert_eq!(
+            hoist(&[
+                "kubectl-sshpod",
+                "--context",
+                "prod",
+                "-n",
+                "web",
+                "audit",
+                "-A"
+            ]),
+            (
+                "kubectl-sshpod audit --context prod --namespace web -A".to_string(),
+                None
+            )
+        );
+        assert_eq!(
+            hoist(&[
+                "kubectl-sshpod",
+                "--kubeconfig=/tmp/kc",
+                "--kubectl-timeout",
+                "5s",
+                "proxy",
+                "--host",
+                "x"
+            ]),
+            (
+                "kubectl-sshpod --kubectl-timeout 5s proxy --host x".to_string(),
+                Some(PathBuf::from("/tmp/kc"))
+            )
+        );
+        // `status` takes no namespace, so clap gets to reject it.
+        assert_eq!(
+            hoist(&["kubectl-sshpod", "-n", "web", "status"]).0,
+            "kubectl-sshpod --namespace web status"
+        );
+    }
+
+    #[test]
+    fn proxy_targeting_flags() {
+        let host = |argv: &[&str]| {
+            let cli = Cli::try_parse_from(["sshpod", "proxy"].iter().chain(argv)).unwrap();
+            let Some(Commands::Proxy(args)) = cli.command else {
+                panic!("expected proxy");
+            };
+            proxy::host_from_args(&args)
+        };
+        let spec = host(&["--host", "web.v2", "--namespace", "ns", "--context", "ctx"]).unwrap();
+        assert_eq!(spec.target, hostspec::Target::Pod("web.v2".into()));
+        assert_eq!(spec.namespace.as_deref(), Some("ns"));
+        assert_eq!(spec.context.as_deref(), Some("ctx"));
+        let spec = host(&["--host", "api", "--target-kind", "deployment"]).unwrap();
+        assert_eq!(spec.target, hostspec::Target::Deployment("api".into()));
+        let spec = host(&["--host", "pod--a.namespace--x.sshpod", "--namespace", "y"]).unwrap();
+        assert_eq!(spec.namespace.as_deref(), Some("y"));
+        assert!(host(&["--host", "plain"]).is_err());
+    }
+
+    #[test]
+    fn proxy_flags_accept_boolish_env() {
+        let parse = || {
+            let cli = Cli::try_parse_from(["sshpod", "proxy", "--host", "pod--x"]).unwrap();
+            let Some(Commands::Proxy(args)) = cli.command else {
+                panic!("expected proxy");
+            };
+            args
+        };
+        std::env::set_var("SSHPOD_OFFLINE", "1");
+        assert!(parse().offline);
+        std::env::set_var("SSHPOD_OFFLINE", "no");
+        assert!(!parse().offline);
+        std::env::remove_var("SSHPOD_OFFLINE");
+        assert!(!parse().offline);
+        std::env::set_var("SSHPOD_EXEC_CHANNEL", "1");
+        assert!(parse().exec_channel);
+        std::env::remove_var("SSHPOD_EXEC_CHANNEL");
+    }
+}
@@ -0,0 +1,573 @@
+use crate::helper_pod::{self, HelperPodOptions};
+use crate::hostspec::{self, SelectStrategy};
+use crate::paths;
+use crate::remote;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use std::path::PathBuf;
+
+/// Keys accepted inside `[host "<pattern>"]` and `[defaults]` sections.
+const HOST_KEYS: &[&str] = &[
+    "user",
+    "container",
+    "namespace",
+    "context",
+    "sftp-only",
+    "env",
+    "shell",
+    "create-user",
+    "jump",
+    "banner",
+    "allow-commands",
+    "deny-commands",
+    "ephemeral-key",
+    "forward",
+    "user-map",
+    "rsync",
+    "sftp-server",
+    "sftp-umask",
+    "helper-image",
+    "node-selector",
+    "tolerations",
+    "runtime-class",
+    "service-account",
+    "requests",
+    "follow",
+    "direct",
+    "chroot-host",
+    "select",
+    "all-namespaces",
+    "debug-container",
+    "debug-image",
+    "skip-containers",
+];
+
+/// Service-mesh sidecars ignored when picking a pod's container.
+const DEFAULT_SKIP_CONTAINERS: &[&str] = &["istio-proxy", "linkerd-proxy", "envoy"];
+
+/// Parsed `~/.config/sshpod/config`.
+///
+/// The format follows git-config: `[section]` or `[section "name"]` headers
+/// followed by `key = value` lines; `#` and `;` start comments. For
+/// `[host "<glob>"]` sections the first matching section that sets a key wins,
+/// and `[defaults]` applies to every host after all host sections.
+/// `[context "<alias>"]` sections map a short name to a kubectl context and
+/// `[alias "<name>"]` sections map a short hostname to a full one.
+#[derive(Debug, Default)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+struct Section {
+    kind: String,
+    name: Option<String>,
+    values: Vec<(String, String)>,
+}
+
+/// Defaults resolved for a single hostname.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HostSettings {
+    pub user: Option<String>,
+    pub container: Option<String>,
+    pub namespace: Option<String>,
+    pub context: Option<String>,
+    pub sftp_only: bool,
+    /// Extra environment variable globs exported into sessions.
+    pub env: Vec<String>,
+    pub shell: Option<String>,
+    pub create_user: bool,
+    /// sshpod host whose SOCKS proxy carries kubectl traffic for this host.
+    pub jump: Option<String>,
+    /// Show the pod/namespace/context banner before login (default on).
+    pub banner: bool,
+    /// Command names a restricted session may run; empty allows everything
+    /// not denied.
+    pub allow_commands: Vec<String>,
+    pub deny_commands: Vec<String>,
+    pub ephemeral_key: bool,
+    /// `(local, remote)` ports forwarded to the pod for the whole session.
+    pub forwards: Vec<(u16, u16)>,
+    /// `(ssh user glob, account)` pairs; `@container` names the container's
+    /// own user.
+    pub user_map: Vec<(String, String)>,
+    /// Install the optional static rsync bundle into the pod.
+    pub rsync: bool,
+    /// Serve SFTP with the bundled sftp-server instead of internal-sftp.
+    pub sftp_server: bool,
+    pub sftp_umask: Option<String>,
+    /// Scheduling settings for helper pods sshpod creates.
+    pub helper_pod: HelperPodOptions,
+    /// Stream sshd auth failures to stderr during sessions.
+    pub follow: bool,
+    /// Connect to sshd on the pod IP instead of through port-forward.
+    pub direct: bool,
+    /// Chroot node-- sessions into the node's root filesystem.
+    pub chroot_host: bool,
+    /// How to choose among ready replicas of a workload.
+    pub select: Option<SelectStrategy>,
+    /// Search every namespace for pod-- targets not in the default one.
+    pub all_namespaces: bool,
+    /// Connect through an ephemeral container beside the target container.
+    pub debug_container: bool,
+    pub debug_image: Option<String>,
+    /// Sidecar containers passed over when a pod has several containers.
+    pub skip_containers: Vec<String>,
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("sshpod/config")),
+        _ => Ok(paths::home_dir()?.join(".config/sshpod/config")),
+    }
+}
+
+impl Config {
+    /// Loads the user config, returning an empty config when the file is absent.
+    pub fn load() -> Result<Config> {
+        let path = config_path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Config::parse(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Config> {
+        let mut sections = vec![Section {
+            kind: "defaults".to_string(),
+            name: None,
+            values: Vec::new(),
+        }];
+        for (idx, raw) in text.lines().enumerate() {
+            let line_no = idx + 1;
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .with_context(|| format!("line {}: unterminated section header", line_no))?
+                    .trim();
+                let (kind, name) = match header.split_once(char::is_whitespace) {
+                    Some((kind, rest)) => {
+                        let name = rest
+                            .trim()
+                            .strip_prefix('"')
+                            .and_then(|r| r.strip_suffix('"'))
+                            .with_context(|| {
+                                format!("line {}: section name must be quoted", line_no)
+                            })?;
+                        (kind.to_string(), Some(name.to_string()))
+                    }
+                    None => (header.to_string(), None),
+                };
+                match (kind.as_str(), &name) {
+                    ("host" | "context" | "alias", Some(_)) | ("defaults", None) => {}
+                    _ => bail!("line {}: unknown section [{}]", line_no, header),
+                }
+                sections.push(Section {
+                    kind,
+                    name,
+                    values: Vec::new(),
+                });
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("line {}: expected key = value", line_no))?;
+            let key = key.trim().to_ascii_lowercase();
+            let section = sections.last_mut().expect("defaults section always exists");
+            let only_key = match section.kind.as_str() {
+                "context" => Some("name"),
+                "alias" => Some("host"),
+                _ => None,
+            };
+            if let Some(only) = only_key {
+                if key != only {
+                    bail!(
+                        "line {}: unknown key `{}` ({} sections only take `{}`)",
+                        line_no,
+                        key,
+                        section.kind,
+                        only
+                    );
+                }
+            } else if key == "suffix" {
+                if section.kind != "defaults" {
+                    bail!("line {}: `suffix` is only allowed in [defaults]", line_no);
+                }
+            } else if !HOST_KEYS.contains(&key.as_str()) {
+                bail!("line {}: unknown key `{}`", line_no, key);
+            }
+            section
+                .values
+                .push((key, unquote(value.trim()).to_string()));
+        }
+        Ok(Config { sections })
+    }
+
+    /// Returns the first value for `key` from host sections matching `host`,
+    /// falling back to `[defaults]`.
+    pub fn get(&self, host: &str, key: &str) -> Option<&str> {
+        let host = host.trim_end_matches('.');
+        let from_hosts = self
+            .sections
+            .iter()
+            .filter(|s| s.kind == "host")
+            .filter(|s| s.name.as_deref().is_some_and(|p| glob_match(p, host)))
+            .find_map(|s| section_value(s, key));
+        from_hosts.or_else(|| {
+            self.sections
+                .iter()
+                .filter(|s| s.kind == "defaults")
+                .find_map(|s| section_value(s, key))
+        })
+    }
+
+    /// kubectl context an alias from a `[context "<alias>"]` section stands for.
+    pub fn context_alias(&self, alias: &str) -> Option<&str> {
+        self.sections
+            .iter()
+            .filter(|s| s.kind == "context" && s.name.as_deref() == Some(alias))
+            .find_map(|s| section_value(s, "name"))
+    }
+
+    /// Full hostname an `[alias "<name>"]` section gives `host`, which may
+    /// carry the suffix or not.
+    pub fn expand_alias(&self, host: &str) -> Option<String> {
+        let name = hostspec::strip_suffix(host).unwrap_or(host.trim_end_matches('.'));
+        self.sections
+            .iter()
+            .filter(|s| s.kind == "alias" && s.name.as_deref() == Some(name))
+            .find_map(|s| section_value(s, "host"))
+            .map(hostspec::with_suffix)
+    }
+
+    /// Hostname suffix from `[defaults]`, e.g. `k8s` for `*.k8s` hosts.
+    pub fn suffix(&self) -> Option<&str> {
+        self.sections
+            .iter()
+            .filter(|s| s.kind == "defaults")
+            .find_map(|s| section_value(s, "suffix"))
+    }
+
+    pub fn host_settings(&self, host: &str) -> Result<HostSettings> {
+        let owned = |key: &str| self.get(host, key).map(str::to_string);
+        let flag = |key: &str| -> Result<bool> {
+            match self.get(host, key) {
+                Some(value) => {
+                    parse_bool(value).with_context(|| format!("invalid {} value `{}`", key, value))
+                }
+                None => Ok(false),
+            }
+        };
+        let list = |key: &str| -> Vec<String> {
+            self.get(host, key)
+                .map(|v| {
+                    v.split([',', ' '])
+                        .filter(|s| !s.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let commands = |key: &str| -> Result<Vec<String>> {
+            let names = list(key);
+            if let Some(bad) = names.iter().find(|name| !is_command_name(name)) {
+                bail!(
+                    "invalid {} entry `{}`: expected a bare command name",
+                    key,
+                    bad
+                );
+            }
+            Ok(names)
+        };
+        let pairs = |key: &str| -> Result<Vec<(String, String)>> {
+            list(key)
+                .iter()
+                .map(|entry| helper_pod::parse_key_value(entry))
+                .collect::<Result<_>>()
+                .with_context(|| format!("invalid {}", key))
+        };
+        Ok(HostSettings {
+            user: owned("user"),
+            container: owned("container"),
+            namespace: owned("namespace"),
+            context: owned("context"),
+            sftp_only: flag("sftp-only")?,
+            env: list("env"),
+            shell: owned("shell"),
+            create_user: flag("create-user")?,
+            jump: owned("jump"),
+            banner: match self.get(host, "banner") {
+                Some(_) => flag("banner")?,
+                None => true,
+            },
+            allow_commands: commands("allow-commands")?,
+            deny_commands: commands("deny-commands")?,
+            ephemeral_key: flag("ephemeral-key")?,
+            forwards: list("forward")
+                .iter()
+                .map(|entry| {
+                    parse_forward(entry).with_context(|| format!("invalid forward `{}`", entry))
+                })
+                .collect::<Result<_>>()?,
+            user_map: list("user-map")
+                .iter()
+                .map(|entry| match entry.split_once(':') {
+                    Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+                        Ok((from.to_string(), to.to_string()))
+                    }
+                    _ => bail!(
+                        "invalid user-map entry `{}`: expected <user>:<account>",
+                        entry
+                    ),
+                })
+                .collect::<Result<_>>()?,
+            rsync: flag("rsync")?,
+            sftp_server: flag("sftp-server")?,
+            sftp_umask: owned("sftp-umask")
+                .map(|umask| remote::parse_umask(&umask).map_err(|err| anyhow!(err)))
+                .transpose()?,
+            helper_pod: HelperPodOptions {
+                image: owned("helper-image"),
+                node_selector: pairs("node-selector")?,
+                tolerations: list("tolerations")
+                    .iter()
+                    .map(|entry| helper_pod::parse_toleration(entry))
+                    .collect::<Result<_>>()
+                    .context("invalid tolerations")?,
+                runtime_class: owned("runtime-class"),
+                service_account: owned("service-account"),
+                requests: pairs("requests")?,
+            },
+            follow: flag("follow")?,
+            direct: flag("direct")?,
+            chroot_host: flag("chroot-host")?,
+            select: owned("select")
+                .map(|value| {
+                    SelectStrategy::from_str(&value, true)
+                        .map_err(|_| anyhow!("invalid select `{}`", value))
+                })
+                .transpose()?,
+            all_namespaces: flag("all-namespaces")?,
+            debug_container: flag("debug-container")?,
+            debug_image: owned("debug-image"),
+            skip_containers: match self.get(host, "skip-containers") {
+                Some(_) => list("skip-containers"),
+                None => DEFAULT_SKIP_CONTAINERS
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+            },
+        })
+    }
+}
+
+/// Parses `8888` or `18888:8888` (local:remote).
+fn parse_forward(entry: &str) -> Result<(u16, u16)> {
+    let (local, remote) = entry.split_once(':').unwrap_or((entry, entry));
+    let port = |value: &str| -> Result<u16> {
+        match value.parse() {
+            Ok(0) | Err(_) => bail!("expected a port number, got `{}`", value),
+            Ok(port) => Ok(port),
+        }
+    };
+    Ok((port(local)?, port(remote)?))
+}
+
+fn is_command_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-'))
+}
+
+impl HostSettings {
+    /// Account the ssh user should act as, from the first matching `user-map` entry.
+    pub fn map_user(&self, user: &str) -> Option<&str> {
+        self.user_map
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, user))
+            .map(|(_, account)| account.as_str())
+    }
+}
+
+fn section_value<'a>(section: &'a Section, key: &str) -> Option<&'a str> {
+    section
+        .values
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Shell-style glob supporting `*` and `?`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match(
+            "deployment--api.*.context--prod*",
+            "deployment--api.namespace--x.context--prod-eu.sshpod"
+        ));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("pod--a?c.sshpod", "pod--abc.sshpod"));
+        assert!(!glob_match("pod--a*.sshpod", "pod--b.sshpod"));
+    }
+
+    #[test]
+    fn first_matching_host_section_wins() {
+        let config = Config::parse(
+            r#"
+# comment
+[defaults]
+namespace = fallback
+[host "deployment--api.*"]
+container = web
+sftp-only = yes
+[host "*"]
+container = other
+namespace = "shared"
+env = AWS_*, HTTP_PROXY
+shell = zsh
+create-user = true
+jump = pod--bastion.sshpod
+banner = off
+allow-commands = ps, top, cat
+forward = 8888, 16006:6006
+user-map = alice:app, *:@container
+rsync = yes
+sftp-server = yes
+sftp-umask = 027
+node-selector = gpu=true
+tolerations = nvidia.com/gpu:NoSchedule
+requests = cpu=100m, memory=64Mi
+select = newest
+all-namespaces = yes
+"#,
+        )
+        .unwrap();
+        let settings = config
+            .host_settings("deployment--api.context--prod.sshpod")
+            .unwrap();
+        assert_eq!(settings.container.as_deref(), Some("web"));
+        assert_eq!(settings.namespace.as_deref(), Some("shared"));
+        assert!(settings.sftp_only);
+        assert!(settings.rsync);
+        assert!(settings.sftp_server);
+        assert_eq!(settings.sftp_umask.as_deref(), Some("027"));
+        assert_eq!(settings.select, Some(SelectStrategy::Newest));
+        assert!(settings.all_namespaces);
+        assert!(settings
+            .skip_containers
+            .contains(&"istio-proxy".to_string()));
+        assert_eq!(
+            settings.helper_pod.node_selector,
+            vec![("gpu".to_string(), "true".to_string())]
+        );
+        assert_eq!(settings.helper_pod.tolerations.len(), 1);
+        assert_eq!(settings.helper_pod.requests.len(), 2);
+        assert_eq!(settings.env, vec!["AWS_*", "HTTP_PROXY"]);
+        assert_eq!(settings.shell.as_deref(), Some("zsh"));
+        assert!(settings.create_user);
+        assert_eq!(settings.jump.as_deref(), Some("pod--bastion.sshpod"));
+        assert!(!settings.banner);
+        assert_eq!(settings.allow_commands, vec!["ps", "top", "cat"]);
+        assert_eq!(settings.forwards, vec![(8888, 8888), (16006, 6006)]);
+        assert_eq!(settings.map_user("alice"), Some("app"));
+        assert_eq!(settings.map_user("bob"), Some("@container"));
+
+        let other = Config::parse("[defaults]\nnamespace = fallback\n")
+            .unwrap()
+            .host_settings("pod--x.sshpod")
+            .unwrap();
+        assert_eq!(other.namespace.as_deref(), Some("fallback"));
+        assert!(!other.sftp_only);
+        assert!(other.banner);
+        assert_eq!(other.map_user("bob"), None);
+    }
+
+    #[test]
+    fn context_aliases() {
+        let config =
+            Config::parse("[context \"prod\"]\nname = gke_company_us-central1_prod-cluster\n")
+                .unwrap();
+        assert_eq!(
+            config.context_alias("prod"),
+            Some("gke_company_us-central1_prod-cluster")
+        );
+        assert_eq!(config.context_alias("dev"), None);
+        assert!(Config::parse("[context \"prod\"]\nuser = x\n").is_err());
+    }
+
+    #[test]
+    fn host_aliases() {
+        let config = Config::parse(
+            "[alias \"api-prod\"]\nhost = container--web.deployment--api.namespace--prod\n",
+        )
+        .unwrap();
+        let full = "container--web.deployment--api.namespace--prod.sshpod";
+        assert_eq!(
+            config.expand_alias("api-prod.sshpod").as_deref(),
+            Some(full)
+        );
+        assert_eq!(config.expand_alias("api-prod").as_deref(), Some(full));
+        assert_eq!(config.expand_alias("pod--api-prod.sshpod"), None);
+        assert!(Config::parse("[alias \"x\"]\nname = y\n").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_sections() {
+        assert!(Config::parse("[host \"*\"]\nbogus = 1\n").is_err());
+        assert!(Config::parse("[weird]\n").is_err());
+        assert!(Config::parse("[host *]\n").is_err());
+        let bad_command = Config::parse("[defaults]\nallow-commands = /bin/sh\n").unwrap();
+        assert!(bad_command.host_settings("pod--x.sshpod").is_err());
+        let bad_forward = Config::parse("[defaults]\nforward = 80:http\n").unwrap();
+        assert!(bad_forward.host_settings("pod--x.sshpod").is_err());
+    }
+}
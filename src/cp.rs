@@ -0,0 +1,657 @@
+use crate::cli::CpArgs;
+use crate::hostspec;
+use crate::remote::shell_quote;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// One side of a copy: a local path or `<host>:<path>` inside a pod.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Location {
+    Local(PathBuf),
+    Remote { host: String, path: String },
+}
+
+impl Location {
+    fn parse(arg: &str) -> Location {
+        match arg.split_once(':') {
+            Some((host, path)) if !host.is_empty() && !host.contains('/') => Location::Remote {
+                host: hostspec::with_suffix(host),
+                path: if path.is_empty() {
+                    ".".to_string()
+                } else {
+                    path.to_string()
+                },
+            },
+            _ => Location::Local(PathBuf::from(arg)),
+        }
+    }
+}
+
+pub async fn run(args: CpArgs) -> Result<()> {
+    let source = Location::parse(&args.source);
+    let dest = Location::parse(&args.dest);
+    let endpoints = resolve_endpoints(&source, &dest).await?;
+    let plan = if args.jobs > 1 {
+        plan_parallel(&endpoints, args.resume).await?
+    } else {
+        None
+    };
+    if let Some(plan) = plan {
+        run_parallel(plan, args.jobs).await?;
+    } else {
+        copy_single(&source, &dest, args.resume).await?;
+    }
+
+    if args.checksum {
+        verify(&endpoints).await?;
+    }
+    Ok(())
+}
+
+/// Where a copy lands on each side. Resolved before transferring because,
+/// as with `put -r`/`get -r`, an existing destination directory receives the
+/// source under its own name.
+struct Endpoints {
+    host: String,
+    upload: bool,
+    local: PathBuf,
+    remote: String,
+}
+
+async fn resolve_endpoints(source: &Location, dest: &Location) -> Result<Endpoints> {
+    match (source, dest) {
+        (Location::Local(local), Location::Remote { host, path }) => {
+            let remote = if remote_is_dir(host, path).await? {
+                join(path, &file_name(&local.to_string_lossy()))
+            } else {
+                path.clone()
+            };
+            Ok(Endpoints {
+                host: host.clone(),
+                upload: true,
+                local: local.clone(),
+                remote,
+            })
+        }
+        (Location::Remote { host, path }, Location::Local(local)) => {
+            let local = if local.is_dir() {
+                local.join(file_name(path))
+            } else {
+                local.clone()
+            };
+            Ok(Endpoints {
+                host: host.clone(),
+                upload: false,
+                local,
+                remote: path.clone(),
+            })
+        }
+        _ => bail!("exactly one of source and destination must be <host>:<path>"),
+    }
+}
+
+/// Copies through one sftp session, recursing into directories.
+async fn copy_single(source: &Location, dest: &Location, resume: bool) -> Result<()> {
+    let (host, command) = match (source, dest) {
+        (Location::Local(local), Location::Remote { host, path }) => (
+            host,
+            transfer_command("put", resume, &local.to_string_lossy(), path),
+        ),
+        (Location::Remote { host, path }, Location::Local(local)) => (
+            host,
+            transfer_command("get", resume, path, &local.to_string_lossy()),
+        ),
+        _ => bail!("exactly one of source and destination must be <host>:<path>"),
+    };
+    run_sftp(host, &command).await
+}
+
+/// sftp batch line for one transfer; `-a` resumes a partial destination file.
+fn transfer_command(verb: &str, resume: bool, from: &str, to: &str) -> String {
+    let flags = if resume { " -a" } else { "" };
+    format!(
+        "{}{} -r {} {}\n",
+        verb,
+        flags,
+        batch_quote(from),
+        batch_quote(to)
+    )
+}
+
+fn batch_quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Directory copy split into per-file transfers that can run concurrently.
+struct ParallelPlan {
+    host: String,
+    /// Batch lines creating the destination tree; runs before the transfers.
+    mkdirs: String,
+    transfers: Vec<String>,
+}
+
+/// Returns a plan when the source is a directory; single files go through
+/// one sftp session as before.
+async fn plan_parallel(endpoints: &Endpoints, resume: bool) -> Result<Option<ParallelPlan>> {
+    let Endpoints {
+        host,
+        local,
+        remote,
+        ..
+    } = endpoints;
+    if endpoints.upload {
+        if !local.is_dir() {
+            return Ok(None);
+        }
+        let (dirs, files) = walk_local(local)?;
+        let mut mkdirs = format!("-mkdir {}\n", batch_quote(remote));
+        for dir in &dirs {
+            mkdirs.push_str(&format!("-mkdir {}\n", batch_quote(&join(remote, dir))));
+        }
+        let transfers = files
+            .iter()
+            .map(|file| {
+                let from = local.join(file).to_string_lossy().into_owned();
+                transfer_command("put", resume, &from, &join(remote, file))
+            })
+            .collect();
+        return Ok(Some(ParallelPlan {
+            host: host.clone(),
+            mkdirs,
+            transfers,
+        }));
+    }
+    let Some((dirs, files)) = walk_remote(host, remote).await? else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(local)
+        .with_context(|| format!("failed to create {}", local.display()))?;
+    for dir in &dirs {
+        let dir = local.join(dir);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let transfers = files
+        .iter()
+        .map(|file| {
+            let to = local.join(file).to_string_lossy().into_owned();
+            transfer_command("get", resume, &join(remote, file), &to)
+        })
+        .collect();
+    Ok(Some(ParallelPlan {
+        host: host.clone(),
+        mkdirs: String::new(),
+        transfers,
+    }))
+}
+
+fn join(base: &str, rel: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), rel)
+}
+
+/// Relative directory and file paths under `root`, parents before children.
+fn walk_local(root: &Path) -> Result<(Vec<String>, Vec<String>)> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        let dir = root.join(&rel);
+        let entries =
+            std::fs::read_dir(&dir).with_context(|| format!("failed to read {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let child = rel.join(entry.file_name());
+            let name = child.to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() {
+                dirs.push(name);
+                pending.push(child);
+            } else {
+                files.push(name);
+            }
+        }
+    }
+    dirs.sort();
+    files.sort();
+    Ok((dirs, files))
+}
+
+async fn remote_is_dir(host: &str, path: &str) -> Result<bool> {
+    let status = Command::new("ssh")
+        .args([host, &format!("test -d {}", shell_quote(path))])
+        .stdin(Stdio::null())
+        .status()
+        .await
+        .context("failed to run ssh to inspect the destination")?;
+    Ok(status.success())
+}
+
+/// Prints `d <path>`/`f <path>` for everything under `$1`. GNU find is
+/// detected before listing so a failure halfway through is reported instead
+/// of being followed by a second, portable listing.
+const WALK_SCRIPT: &str = r#"cd "$1" 2>/dev/null || exit 3
+if find . -maxdepth 0 -printf '' >/dev/null 2>&1; then
+  find . -mindepth 1 \( -type d -printf 'd %P\n' \) -o -printf 'f %P\n'
+else
+  find . -mindepth 1 | while IFS= read -r p; do
+    if [ -d "$p" ]; then echo "d ${p#./}"; else echo "f ${p#./}"; fi
+  done
+fi
+"#;
+
+/// Lists the remote tree under `path`, or `None` when it is not a directory.
+async fn walk_remote(host: &str, path: &str) -> Result<Option<(Vec<String>, Vec<String>)>> {
+    let script = format!(
+        "sh -c {} sh {}",
+        shell_quote(WALK_SCRIPT),
+        shell_quote(path)
+    );
+    let output = Command::new("ssh")
+        .args([host, &script])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("failed to run ssh to list the remote directory")?;
+    if output.status.code() == Some(3) {
+        return Ok(None);
+    }
+    if !output.status.success() {
+        bail!(
+            "failed to list {}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Some(parse_listing(&String::from_utf8_lossy(
+        &output.stdout,
+    ))))
+}
+
+fn parse_listing(listing: &str) -> (Vec<String>, Vec<String>) {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for line in listing.lines() {
+        match line.split_once(' ') {
+            Some(("d", name)) if !name.is_empty() => dirs.push(name.to_string()),
+            Some(("f", name)) if !name.is_empty() => files.push(name.to_string()),
+            _ => {}
+        }
+    }
+    dirs.sort();
+    files.sort();
+    (dirs, files)
+}
+
+/// Runs the plan over `jobs` sftp sessions multiplexed on one SSH connection.
+async fn run_parallel(plan: ParallelPlan, jobs: usize) -> Result<()> {
+    let control_dir = std::env::temp_dir().join(format!("sshpod-cp-{}", std::process::id()));
+    std::fs::create_dir_all(&control_dir)
+        .with_context(|| format!("failed to create {}", control_dir.display()))?;
+    let control_path = control_dir.join("control");
+    let control = format!("ControlPath={}", control_path.display());
+
+    let status = Command::new("ssh")
+        .args([
+            "-o",
+            "ControlMaster=yes",
+            "-o",
+            &control,
+            "-N",
+            "-f",
+            &plan.host,
+        ])
+        .stdin(Stdio::null())
+        .status()
+        .await
+        .context("failed to start the shared ssh connection")?;
+    if !status.success() {
+        let _ = std::fs::remove_dir_all(&control_dir);
+        bail!("ssh exited with {}", status);
+    }
+
+    let result = async {
+        if !plan.mkdirs.is_empty() {
+            run_sftp_with(&plan.host, &plan.mkdirs, &control).await?;
+        }
+        let mut batches = vec![String::new(); jobs.min(plan.transfers.len()).max(1)];
+        let count = batches.len();
+        for (i, transfer) in plan.transfers.iter().enumerate() {
+            batches[i % count].push_str(transfer);
+        }
+        let mut tasks = tokio::task::JoinSet::new();
+        for batch in batches.into_iter().filter(|b| !b.is_empty()) {
+            let host = plan.host.clone();
+            let control = control.clone();
+            tasks.spawn(async move { run_sftp_with(&host, &batch, &control).await });
+        }
+        let mut first_error = None;
+        while let Some(joined) = tasks.join_next().await {
+            if let Err(err) = joined.context("sftp task panicked").and_then(|r| r) {
+                first_error.get_or_insert(err);
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+    .await;
+
+    let _ = Command::new("ssh")
+        .args(["-o", &control, "-O", "exit", &plan.host])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+    let _ = std::fs::remove_dir_all(&control_dir);
+    result
+}
+
+async fn run_sftp(host: &str, batch: &str) -> Result<()> {
+    run_sftp_with(host, batch, "ControlPath=none").await
+}
+
+async fn run_sftp_with(host: &str, batch: &str, control: &str) -> Result<()> {
+    let mut child = Command::new("sftp")
+        .args(["-q", "-o", control, "-b", "-", host])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("failed to run sftp")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(batch.as_bytes())
+            .await
+            .context("failed to send commands to sftp")?;
+    }
+    let status = child.wait().await.context("failed to wait for sftp")?;
+    if !status.success() {
+        bail!("sftp exited with {}", status);
+    }
+    Ok(())
+}
+
+/// Compares SHA-256 of every copied file on both sides. The source is
+/// walked; on the destination only the same paths are hashed, so files that
+/// were already there are left out.
+async fn verify(endpoints: &Endpoints) -> Result<()> {
+    let (local, remote) = if endpoints.upload {
+        let local = local_hashes(&endpoints.local, None)?;
+        let names: Vec<String> = local.keys().cloned().collect();
+        let remote = remote_hashes(&endpoints.host, &endpoints.remote, Some(&names)).await?;
+        (local, remote)
+    } else {
+        let remote = remote_hashes(&endpoints.host, &endpoints.remote, None).await?;
+        let names: Vec<String> = remote.keys().cloned().collect();
+        (local_hashes(&endpoints.local, Some(&names))?, remote)
+    };
+    let copied = if endpoints.upload {
+        local.len()
+    } else {
+        remote.len()
+    };
+    let mismatched = compare_hashes(&local, &remote);
+    if !mismatched.is_empty() {
+        let shown: Vec<&str> = mismatched.iter().take(10).map(String::as_str).collect();
+        bail!(
+            "checksum mismatch for {} file(s): {}",
+            mismatched.len(),
+            shown.join(", ")
+        );
+    }
+    eprintln!("[sshpod] sha256 verified for {} file(s)", copied);
+    Ok(())
+}
+
+/// Files whose hashes differ or exist on only one side; `.` names a single
+/// file copy.
+fn compare_hashes(
+    local: &BTreeMap<String, String>,
+    remote: &BTreeMap<String, String>,
+) -> Vec<String> {
+    local
+        .keys()
+        .chain(remote.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|name| local.get(*name) != remote.get(*name))
+        .cloned()
+        .collect()
+}
+
+/// Hashes `names` under `path` (missing ones are left out), or every file
+/// when `names` is `None`.
+fn local_hashes(path: &Path, names: Option<&[String]>) -> Result<BTreeMap<String, String>> {
+    if let Some(names) = names {
+        let mut hashes = BTreeMap::new();
+        for name in names {
+            let file = if name == "." {
+                path.to_path_buf()
+            } else {
+                path.join(name)
+            };
+            if file.is_file() {
+                hashes.insert(name.clone(), local_sha256(&file)?);
+            }
+        }
+        return Ok(hashes);
+    }
+    if !path.is_dir() {
+        return Ok(BTreeMap::from([(".".to_string(), local_sha256(path)?)]));
+    }
+    let (_, files) = walk_local(path)?;
+    files
+        .into_iter()
+        .map(|file| {
+            let hash = local_sha256(&path.join(&file))?;
+            Ok((file, hash))
+        })
+        .collect()
+}
+
+/// Hashes `$1`; with `$2` set, only the relative paths read from stdin.
+const REMOTE_HASH_SCRIPT: &str = r#"f="$1" listed="$2"
+if command -v sha256sum >/dev/null 2>&1; then
+  set -- sha256sum
+elif busybox sha256sum </dev/null >/dev/null 2>&1; then
+  set -- busybox sha256sum
+elif command -v openssl >/dev/null 2>&1; then
+  set -- openssl dgst -sha256 -r
+else
+  exit 4
+fi
+if [ ! -d "$f" ]; then
+  "$@" "$f" | { read -r hash _ && echo "$hash  ."; }
+elif [ -n "$listed" ]; then
+  cd "$f" && while IFS= read -r p; do
+    if [ -f "./$p" ]; then "$@" "./$p"; fi
+  done
+else
+  cd "$f" && find . -type f -exec "$@" {} +
+fi
+"#;
+
+/// Hashes `names` under `path` inside the pod, or every file when `names`
+/// is `None`. Without a hashing tool there, each file is read back over ssh
+/// and hashed locally, which still catches data that was damaged on the way
+/// in.
+async fn remote_hashes(
+    host: &str,
+    path: &str,
+    names: Option<&[String]>,
+) -> Result<BTreeMap<String, String>> {
+    let mut script = format!(
+        "sh -c {} sh {}",
+        shell_quote(REMOTE_HASH_SCRIPT),
+        shell_quote(path)
+    );
+    if names.is_some() {
+        script.push_str(" listed");
+    }
+    let mut child = Command::new("ssh")
+        .args([host, &script])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run ssh for the remote checksum")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let list: String = names
+            .unwrap_or_default()
+            .iter()
+            .map(|name| format!("{}\n", name))
+            .collect();
+        // The script exits early without a hashing tool, so it may not read this.
+        let _ = stdin.write_all(list.as_bytes()).await;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .context("failed to run ssh for the remote checksum")?;
+    if output.status.code() == Some(4) {
+        warn!("[sshpod] no sha256 tool in the pod; reading files back to hash them locally");
+        return read_back_hashes(host, path, names).await;
+    }
+    if !output.status.success() {
+        bail!(
+            "failed to hash {} in the pod: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_hashes(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses `sha256sum`/`openssl dgst -r` output into relative name -> hash.
+fn parse_hashes(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (hash, name) = line.split_once(' ')?;
+            let name = name.trim_start_matches([' ', '*']);
+            let name = name.strip_prefix("./").unwrap_or(name);
+            (hash.len() == 64).then(|| (name.to_string(), hash.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+async fn read_back_hashes(
+    host: &str,
+    path: &str,
+    names: Option<&[String]>,
+) -> Result<BTreeMap<String, String>> {
+    let listing = match names {
+        Some([single]) if single == "." => None,
+        Some(names) => Some(names.to_vec()),
+        None => walk_remote(host, path).await?.map(|(_, files)| files),
+    };
+    let files = match listing {
+        Some(files) => files
+            .into_iter()
+            .map(|file| (join(path, &file), file))
+            .collect(),
+        None => vec![(path.to_string(), ".".to_string())],
+    };
+    let mut hashes = BTreeMap::new();
+    for (remote, name) in files {
+        let output = Command::new("ssh")
+            .args([host, &format!("cat -- {}", shell_quote(&remote))])
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .context("failed to run ssh to read back a file")?;
+        // A listed file that is missing shows up as a mismatch.
+        if !output.status.success() && names.is_some() {
+            continue;
+        }
+        if !output.status.success() {
+            bail!(
+                "failed to read back {}: {}",
+                remote,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        hashes.insert(name, format!("{:x}", Sha256::digest(&output.stdout)));
+    }
+    Ok(hashes)
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path.trim_end_matches('/'))
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn local_sha256(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_locations_and_builds_batch() {
+        assert_eq!(
+            Location::parse("pod--web:/srv/data"),
+            Location::Remote {
+                host: "pod--web.sshpod".into(),
+                path: "/srv/data".into()
+            }
+        );
+        assert_eq!(
+            Location::parse("./a:b"),
+            Location::Local(PathBuf::from("./a:b"))
+        );
+        assert_eq!(
+            transfer_command("put", true, "my \"file\"", "/tmp"),
+            "put -a -r \"my \\\"file\\\"\" \"/tmp\"\n"
+        );
+        let (dirs, files) = parse_listing("d sub\nf sub/a b\nf top\nbogus\n");
+        assert_eq!(dirs, vec!["sub"]);
+        assert_eq!(files, vec!["sub/a b", "top"]);
+    }
+
+    #[test]
+    fn compares_hash_listings() {
+        let hash = "a".repeat(64);
+        let remote = parse_hashes(&format!("{hash}  ./sub/x y\n{hash} *./top\n"));
+        assert_eq!(remote.get("sub/x y"), Some(&hash));
+        let mut local = remote.clone();
+        assert!(compare_hashes(&local, &remote).is_empty());
+        local.insert("top".into(), "b".repeat(64));
+        local.insert("extra".into(), hash.clone());
+        assert_eq!(compare_hashes(&local, &remote), vec!["extra", "top"]);
+    }
+
+    #[test]
+    fn hashes_only_listed_local_files() {
+        let dir = std::env::temp_dir().join(format!("sshpod-cp-hash-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/copied"), "data").unwrap();
+        std::fs::write(dir.join("unrelated"), "old").unwrap();
+        let names = ["sub/copied".to_string(), "missing".to_string()];
+        let hashes = local_hashes(&dir, Some(&names)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(hashes.keys().collect::<Vec<_>>(), ["sub/copied"]);
+    }
+}
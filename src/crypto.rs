@@ -0,0 +1,83 @@
+use clap::ValueEnum;
+
+/// Algorithm preset applied to both the local keys and the remote sshd_config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CryptoPreset {
+    /// OpenSSH defaults with ed25519 keys
+    #[default]
+    Default,
+    /// FIPS 140-approved ciphers, MACs, key exchanges, and ECDSA keys
+    Fips,
+}
+
+const FIPS_SSHD_CONFIG: &str = "\
+Ciphers aes256-gcm@openssh.com,aes128-gcm@openssh.com,aes256-ctr,aes192-ctr,aes128-ctr
+MACs hmac-sha2-512-etm@openssh.com,hmac-sha2-256-etm@openssh.com,hmac-sha2-512,hmac-sha2-256
+KexAlgorithms ecdh-sha2-nistp521,ecdh-sha2-nistp384,ecdh-sha2-nistp256,diffie-hellman-group16-sha512,diffie-hellman-group14-sha256
+HostKeyAlgorithms ecdsa-sha2-nistp256,ecdsa-sha2-nistp384,ecdsa-sha2-nistp521
+PubkeyAcceptedAlgorithms ecdsa-sha2-nistp256,ecdsa-sha2-nistp384,ecdsa-sha2-nistp521
+";
+
+impl CryptoPreset {
+    pub fn name(self) -> &'static str {
+        match self {
+            CryptoPreset::Default => "default",
+            CryptoPreset::Fips => "fips",
+        }
+    }
+
+    /// Key type passed to `ssh-keygen -t`.
+    pub fn key_type(self) -> &'static str {
+        match self {
+            CryptoPreset::Default => "ed25519",
+            CryptoPreset::Fips => "ecdsa",
+        }
+    }
+
+    /// File name of the client identity under ~/.cache/sshpod.
+    pub fn client_key_name(self) -> &'static str {
+        match self {
+            CryptoPreset::Default => "id_ed25519",
+            CryptoPreset::Fips => "id_ecdsa",
+        }
+    }
+
+    /// File name of the host key, both locally and under `$BASE/hostkeys`.
+    pub fn host_key_name(self) -> &'static str {
+        match self {
+            CryptoPreset::Default => "ssh_host_ed25519_key",
+            CryptoPreset::Fips => "ssh_host_ecdsa_key",
+        }
+    }
+
+    /// Extra sshd_config directives restricting the negotiated algorithms.
+    pub fn sshd_config(self) -> &'static str {
+        match self {
+            CryptoPreset::Default => "",
+            CryptoPreset::Fips => FIPS_SSHD_CONFIG,
+        }
+    }
+
+    /// Prefix of the bundle file name, so a FIPS-validated sshd build can be
+    /// shipped next to the regular one.
+    pub fn bundle_prefix(self) -> &'static str {
+        match self {
+            CryptoPreset::Default => "sshd",
+            CryptoPreset::Fips => "sshd_fips",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fips_config_excludes_non_approved_algorithms() {
+        let config = CryptoPreset::Fips.sshd_config();
+        for banned in ["chacha20", "curve25519", "ed25519", "umac"] {
+            assert!(!config.contains(banned), "{} must not be allowed", banned);
+        }
+        assert!(CryptoPreset::Default.sshd_config().is_empty());
+    }
+}
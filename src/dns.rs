@@ -0,0 +1,229 @@
+use crate::activation;
+use crate::cli::DnsArgs;
+use crate::kubectl::{self, RemoteTarget};
+use crate::proxy;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+const CACHE_TTL: Duration = Duration::from_secs(30);
+const TYPE_A: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_REFUSED: u8 = 5;
+const RCODE_SERVFAIL: u8 = 2;
+/// Idle timeout written into the printed units when none is given.
+const DEFAULT_UNIT_IDLE: Duration = Duration::from_secs(10 * 60);
+
+/// Resolves the name inside the pod with getent, falling back to nslookup.
+const RESOLVE_SCRIPT: &str = r#"if command -v getent >/dev/null 2>&1; then
+  getent ahostsv4 "$1" | awk '{print $1}' | sort -u
+else
+  nslookup "$1" 2>/dev/null | awk '/^Address/ && NR > 2 {print $NF}'
+fi
+"#;
+
+type Cache = Arc<Mutex<HashMap<String, (Instant, Vec<Ipv4Addr>)>>>;
+
+pub async fn run(args: DnsArgs) -> Result<()> {
+    if let Some(manager) = args.print_units {
+        let exe = std::env::current_exe().context("failed to locate the sshpod binary")?;
+        let service_args = activation::service_args(
+            &args.host,
+            &args.domain,
+            args.idle_timeout.unwrap_or(DEFAULT_UNIT_IDLE),
+        );
+        print!(
+            "{}",
+            activation::render_units(manager, &exe.to_string_lossy(), &service_args, args.listen)
+        );
+        return Ok(());
+    }
+    let (target, _) = proxy::resolve_host(&args.host).await?;
+    let socket = match activation::inherited_udp_socket()? {
+        Some(socket) => {
+            info!("[sshpod] using the socket passed by the service manager");
+            UdpSocket::from_std(socket)?
+        }
+        None => UdpSocket::bind(args.listen)
+            .await
+            .with_context(|| format!("failed to bind DNS stub on {}", args.listen))?,
+    };
+    let socket = Arc::new(socket);
+    let listen = socket.local_addr().unwrap_or(args.listen);
+    let domain = args.domain.trim_matches('.').to_ascii_lowercase();
+    println!(
+        "Resolving *.{} via pod {} on {} (Ctrl-C to stop)",
+        domain, target.pod, listen
+    );
+    let cache: Cache = Arc::default();
+    let target = Arc::new(target);
+    let mut buf = [0u8; 512];
+    loop {
+        let received = match args.idle_timeout {
+            Some(idle) => match tokio::time::timeout(idle, socket.recv_from(&mut buf)).await {
+                Ok(received) => received,
+                Err(_) => {
+                    info!("[sshpod] no DNS queries for {:?}; exiting", idle);
+                    return Ok(());
+                }
+            },
+            None => socket.recv_from(&mut buf).await,
+        };
+        let (len, peer) = received?;
+        let Some(query) = Query::parse(&buf[..len]) else {
+            continue;
+        };
+        let socket = socket.clone();
+        let cache = cache.clone();
+        let target = target.clone();
+        let domain = domain.clone();
+        tokio::spawn(async move {
+            let response = answer(&query, &domain, &target, &cache).await;
+            if let Err(err) = socket.send_to(&response, peer).await {
+                warn!("[sshpod] failed to send DNS response: {}", err);
+            }
+        });
+    }
+}
+
+async fn answer(query: &Query, domain: &str, target: &RemoteTarget, cache: &Cache) -> Vec<u8> {
+    let name = query.name.to_ascii_lowercase();
+    if name != domain && !name.ends_with(&format!(".{}", domain)) {
+        return query.response(RCODE_REFUSED, &[]);
+    }
+    if query.qtype != TYPE_A {
+        return query.response(0, &[]);
+    }
+    if let Some((at, addrs)) = cache.lock().await.get(&name) {
+        if at.elapsed() < CACHE_TTL {
+            return query.response(if addrs.is_empty() { RCODE_NXDOMAIN } else { 0 }, addrs);
+        }
+    }
+    match kubectl::exec_capture_optional_target(target, &["sh", "-c", RESOLVE_SCRIPT, "sh", &name])
+        .await
+    {
+        Ok(output) => {
+            let addrs: Vec<Ipv4Addr> = output
+                .unwrap_or_default()
+                .lines()
+                .filter_map(|l| l.trim().parse().ok())
+                .collect();
+            info!("[sshpod] {} -> {:?}", name, addrs);
+            cache
+                .lock()
+                .await
+                .insert(name, (Instant::now(), addrs.clone()));
+            query.response(if addrs.is_empty() { RCODE_NXDOMAIN } else { 0 }, &addrs)
+        }
+        Err(err) => {
+            debug!("[sshpod] lookup of {} failed: {:#}", name, err);
+            query.response(RCODE_SERVFAIL, &[])
+        }
+    }
+}
+
+/// The parts of a single-question DNS query needed to answer it.
+#[derive(Debug, PartialEq, Eq)]
+struct Query {
+    id: u16,
+    flags: u16,
+    name: String,
+    qtype: u16,
+    /// Raw question section, echoed back in the response.
+    question: Vec<u8>,
+}
+
+impl Query {
+    fn parse(packet: &[u8]) -> Option<Query> {
+        if packet.len() < 12 || u16::from_be_bytes([packet[4], packet[5]]) != 1 {
+            return None;
+        }
+        let mut pos = 12;
+        let mut labels = Vec::new();
+        loop {
+            let len = *packet.get(pos)? as usize;
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            if len > 63 {
+                return None;
+            }
+            labels.push(std::str::from_utf8(packet.get(pos..pos + len)?).ok()?);
+            pos += len;
+        }
+        let qtype = u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]);
+        packet.get(pos + 3)?;
+        Some(Query {
+            id: u16::from_be_bytes([packet[0], packet[1]]),
+            flags: u16::from_be_bytes([packet[2], packet[3]]),
+            name: labels.join("."),
+            qtype,
+            question: packet[12..pos + 4].to_vec(),
+        })
+    }
+
+    fn response(&self, rcode: u8, addrs: &[Ipv4Addr]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(12 + self.question.len() + addrs.len() * 16);
+        out.extend_from_slice(&self.id.to_be_bytes());
+        // QR + AA + RA, keeping the client's RD bit.
+        let flags = 0x8480 | (self.flags & 0x0100) | rcode as u16;
+        out.extend_from_slice(&flags.to_be_bytes());
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(&self.question);
+        for addr in addrs {
+            out.extend_from_slice(&[0xc0, 0x0c]);
+            out.extend_from_slice(&TYPE_A.to_be_bytes());
+            out.extend_from_slice(&1u16.to_be_bytes());
+            out.extend_from_slice(&(CACHE_TTL.as_secs() as u32).to_be_bytes());
+            out.extend_from_slice(&4u16.to_be_bytes());
+            out.extend_from_slice(&addr.octets());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_packet(name: &str, qtype: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn parses_single_question() {
+        let query = Query::parse(&query_packet("api.default.svc.cluster.local", TYPE_A)).unwrap();
+        assert_eq!(query.id, 0x1234);
+        assert_eq!(query.name, "api.default.svc.cluster.local");
+        assert_eq!(query.qtype, TYPE_A);
+        assert!(Query::parse(&[0; 5]).is_none());
+    }
+
+    #[test]
+    fn builds_a_record_response() {
+        let packet = query_packet("db.svc.cluster.local", TYPE_A);
+        let query = Query::parse(&packet).unwrap();
+        let response = query.response(0, &[Ipv4Addr::new(10, 0, 0, 7)]);
+        assert_eq!(&response[0..2], &[0x12, 0x34]);
+        assert_eq!(response[3] & 0x0f, 0);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+        assert_eq!(&response[response.len() - 4..], &[10, 0, 0, 7]);
+        assert_eq!(&response[12..packet.len()], &packet[12..]);
+    }
+}
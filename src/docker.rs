@@ -0,0 +1,118 @@
+use crate::cli::DockerArgs;
+use crate::hostspec;
+use crate::kubectl;
+use crate::paths;
+use crate::proxy;
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+use tokio::process::Command;
+
+/// Runtime sockets probed in order when `--socket` is not given.
+const SOCKETS: &[&str] = &[
+    "/var/run/docker.sock",
+    "/run/docker.sock",
+    "/run/containerd/containerd.sock",
+    "/var/run/containerd/containerd.sock",
+];
+
+pub async fn run(args: DockerArgs) -> Result<()> {
+    let host = hostspec::with_suffix(&args.host);
+    let remote = match args.socket {
+        Some(socket) => socket,
+        None => find_socket(&host).await?,
+    };
+    let local = match args.local {
+        Some(path) => path,
+        None => default_local_socket(&host)?,
+    };
+    if let Some(dir) = local.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    let mut child = Command::new("ssh")
+        .args(["-N", "-o", "ExitOnForwardFailure=yes"])
+        .args(["-o", "StreamLocalBindUnlink=yes"])
+        .arg("-L")
+        .arg(format!("{}:{}", local.display(), remote))
+        .arg(&host)
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to run ssh")?;
+    eprintln!(
+        "[sshpod] forwarding {} to {}; press Ctrl-C to stop",
+        remote,
+        local.display()
+    );
+    println!("{}", export_line(&remote, &local));
+
+    let result = tokio::select! {
+        status = child.wait() => {
+            let status = status.context("failed to wait for ssh")?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!("ssh exited with {}", status))
+            }
+        }
+        _ = tokio::signal::ctrl_c() => {
+            let _ = child.kill().await;
+            Ok(())
+        }
+    };
+    let _ = std::fs::remove_file(&local);
+    result
+}
+
+async fn find_socket(host: &str) -> Result<String> {
+    let (target, _) = proxy::resolve_host(host).await?;
+    let script = format!(
+        "for s in {}; do [ -S \"$s\" ] && {{ echo \"$s\"; exit 0; }}; done; exit 1",
+        SOCKETS.join(" ")
+    );
+    match kubectl::exec_capture_optional_target(&target, &["sh", "-c", &script]).await? {
+        Some(socket) if !socket.is_empty() => Ok(socket),
+        _ => bail!(
+            "no docker or containerd socket is mounted in {} (looked for {}); pass --socket",
+            target.pod,
+            SOCKETS.join(", ")
+        ),
+    }
+}
+
+fn default_local_socket(host: &str) -> Result<PathBuf> {
+    let name = hostspec::strip_suffix(host)
+        .unwrap_or(host)
+        .replace('/', "_");
+    Ok(paths::state_dir()?
+        .join("docker")
+        .join(format!("{}.sock", name)))
+}
+
+/// Shell line pointing the matching CLI at the forwarded socket.
+fn export_line(remote: &str, local: &std::path::Path) -> String {
+    if remote.contains("containerd") {
+        format!("export CONTAINERD_ADDRESS={}", local.display())
+    } else {
+        format!("export DOCKER_HOST=unix://{}", local.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn export_line_matches_runtime() {
+        let local = Path::new("/tmp/app.sock");
+        assert_eq!(
+            export_line("/var/run/docker.sock", local),
+            "export DOCKER_HOST=unix:///tmp/app.sock"
+        );
+        assert_eq!(
+            export_line("/run/containerd/containerd.sock", local),
+            "export CONTAINERD_ADDRESS=/tmp/app.sock"
+        );
+    }
+}
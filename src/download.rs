@@ -0,0 +1,155 @@
+//! Fetching sshd bundles from a release URL for architectures the binary
+//! does not embed, or when asked to (`--bundle-source url`).
+//! Downloads are checked against the `.sha256` published next to each
+//! bundle and cached under `~/.cache/sshpod/bundles/<version>`.
+
+use crate::paths;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use log::info;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::OnceLock;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+pub const DEFAULT_URL: &str = concat!(
+    "https://github.com/imos/sshpod/releases/download/v",
+    env!("CARGO_PKG_VERSION")
+);
+
+/// Where the sshd bundle comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BundleOrigin {
+    /// Embedded bundle, then a local file, then a download
+    #[default]
+    Auto,
+    /// Always the downloaded (and cached) bundle
+    Url,
+}
+
+struct Settings {
+    origin: BundleOrigin,
+    url: String,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+pub fn configure(origin: BundleOrigin, url: String) {
+    let _ = SETTINGS.set(Settings { origin, url });
+}
+
+pub fn origin() -> BundleOrigin {
+    SETTINGS.get().map(|s| s.origin).unwrap_or_default()
+}
+
+fn base_url() -> &'static str {
+    SETTINGS.get().map_or(DEFAULT_URL, |s| s.url.as_str())
+}
+
+/// Cached copy of bundle `filename`, downloading it first if needed. The
+/// `.minisig` is fetched alongside when the release has one.
+pub async fn fetch(filename: &str) -> Result<PathBuf> {
+    let dir = paths::home_dir()?
+        .join(".cache/sshpod/bundles")
+        .join(env!("CARGO_PKG_VERSION"));
+    let path = dir.join(filename);
+    if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        info!("[sshpod] using cached bundle {}", path.display());
+        return Ok(path);
+    }
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let url = format!("{}/{}", base_url().trim_end_matches('/'), filename);
+    info!("[sshpod] downloading bundle {}", url);
+    let partial = dir.join(format!("{}.part", filename));
+    download(&format!("{}.sha256", url), &partial).await?;
+    let expected = parse_checksum(&tokio::fs::read_to_string(&partial).await?)
+        .with_context(|| format!("malformed checksum file {}.sha256", url))?;
+    download(&url, &partial).await?;
+    let actual = sha256_file(&partial).await?;
+    if actual != expected {
+        let _ = tokio::fs::remove_file(&partial).await;
+        bail!(
+            "checksum mismatch for {} (expected {}, got {})",
+            url,
+            expected,
+            actual
+        );
+    }
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".minisig");
+    if download(&format!("{}.minisig", url), Path::new(&signature))
+        .await
+        .is_err()
+    {
+        let _ = tokio::fs::remove_file(&signature).await;
+    }
+    tokio::fs::rename(&partial, &path)
+        .await
+        .with_context(|| format!("failed to cache {}", path.display()))?;
+    Ok(path)
+}
+
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(format!("{:x}", hasher.finalize()));
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// Downloads with curl, or wget where curl is missing.
+async fn download(url: &str, dest: &Path) -> Result<()> {
+    let attempts: [(&str, &[&str]); 2] = [
+        ("curl", &["-fsSL", "--retry", "2", "-o"]),
+        ("wget", &["-q", "-O"]),
+    ];
+    for (tool, args) in attempts {
+        let status = Command::new(tool)
+            .args(args)
+            .arg(dest)
+            .arg(url)
+            .stdin(Stdio::null())
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => bail!("{} {} failed ({})", tool, url, status),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to run {}", tool)),
+        }
+    }
+    bail!("downloading bundles needs curl or wget")
+}
+
+/// The digest from a `sha256sum`-style line (`<hex>  <name>`) or a bare hex.
+fn parse_checksum(text: &str) -> Option<String> {
+    let digest = text.split_whitespace().next()?.to_ascii_lowercase();
+    (digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit())).then_some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_checksum_files() {
+        let hex = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        assert_eq!(parse_checksum(hex).as_deref(), Some(hex));
+        assert_eq!(
+            parse_checksum(&format!("{}  sshd_amd64.xz\n", hex.to_uppercase())).as_deref(),
+            Some(hex)
+        );
+        assert_eq!(parse_checksum("<html>Not Found</html>"), None);
+        assert_eq!(parse_checksum(""), None);
+    }
+}
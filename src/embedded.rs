@@ -1,7 +1,22 @@
+/// Bundle compiled into the binary. Each architecture has its own cargo
+/// feature (`bundle-amd64`, `bundle-arm64`); the rest are located or
+/// downloaded instead.
 pub fn get_bundle(arch: &str) -> Option<&'static [u8]> {
     match arch {
+        #[cfg(feature = "bundle-amd64")]
         "linux/amd64" => Some(include_bytes!("../bundles/sshd_amd64.xz")),
+        #[cfg(feature = "bundle-arm64")]
         "linux/arm64" => Some(include_bytes!("../bundles/sshd_arm64.xz")),
         _ => None,
     }
 }
+
+/// minisign signature of the embedded bundle, passed in when building a
+/// signed release (`SSHPOD_BUNDLE_SIG_<ARCH>` holding the `.minisig` text).
+pub fn get_signature(arch: &str) -> Option<&'static str> {
+    match arch {
+        "linux/amd64" => option_env!("SSHPOD_BUNDLE_SIG_AMD64"),
+        "linux/arm64" => option_env!("SSHPOD_BUNDLE_SIG_ARM64"),
+        _ => None,
+    }
+}
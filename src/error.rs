@@ -0,0 +1,105 @@
+//! Failures that wrappers need to tell apart (retry or give up). Everything
+//! else stays a plain `anyhow` error; these travel inside one and are found
+//! again with [`find`].
+
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Debug, Error, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SshpodError {
+    #[error("context `{context}` not found. Available contexts: {}", available.join(", "))]
+    ContextNotFound {
+        context: String,
+        available: Vec<String>,
+    },
+    #[error("pod {pod} did not become ready: {reason}")]
+    PodNotReady { pod: String, reason: String },
+    #[error("failed to install the sshd bundle into pod {pod}: {reason}")]
+    BundleInstallFailed { pod: String, reason: String },
+    #[error("port-forward to pod {pod} dropped: {reason}")]
+    PortForwardDropped { pod: String, reason: String },
+    #[error("kubectl {action} timed out after {seconds}s (is the API server reachable?)")]
+    Timeout { action: String, seconds: u64 },
+    #[error("{hint} (kubectl {action}: {detail})")]
+    CredentialsExpired {
+        action: String,
+        hint: String,
+        detail: String,
+    },
+    #[error("kubectl {action} failed: {stderr}")]
+    Kubectl {
+        action: String,
+        stderr: String,
+        transient: bool,
+    },
+}
+
+impl SshpodError {
+    /// Whether running the same command again may succeed without the user
+    /// changing anything.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SshpodError::PodNotReady { .. }
+            | SshpodError::PortForwardDropped { .. }
+            | SshpodError::Timeout { .. } => true,
+            SshpodError::Kubectl { transient, .. } => *transient,
+            SshpodError::ContextNotFound { .. }
+            | SshpodError::BundleInstallFailed { .. }
+            | SshpodError::CredentialsExpired { .. } => false,
+        }
+    }
+
+    /// `EX_TEMPFAIL` for retryable failures, `EX_CONFIG` for ones the user
+    /// has to fix locally, 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            _ if self.is_retryable() => 75,
+            SshpodError::ContextNotFound { .. } | SshpodError::CredentialsExpired { .. } => 78,
+            _ => 1,
+        }
+    }
+}
+
+/// The outermost [`SshpodError`] in `err`'s chain.
+pub fn find(err: &anyhow::Error) -> Option<&SshpodError> {
+    err.chain().find_map(|cause| cause.downcast_ref())
+}
+
+/// Process exit code for an error that reached `main`.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    find(err).map_or(1, SshpodError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn found_through_context() {
+        let err = Err::<(), _>(SshpodError::Timeout {
+            action: "get pod".into(),
+            seconds: 30,
+        })
+        .context("failed to resolve target")
+        .unwrap_err();
+        assert!(find(&err).unwrap().is_retryable());
+        assert_eq!(exit_code(&err), 75);
+        assert_eq!(exit_code(&anyhow::anyhow!("plain")), 1);
+
+        let err = anyhow::Error::new(SshpodError::ContextNotFound {
+            context: "prod".into(),
+            available: vec!["dev".into(), "stg".into()],
+        });
+        assert_eq!(
+            err.to_string(),
+            "context `prod` not found. Available contexts: dev, stg"
+        );
+        assert_eq!(exit_code(&err), 78);
+        assert_eq!(
+            serde_json::to_value(find(&err).unwrap()).unwrap()["kind"],
+            "context_not_found"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// Side channel for `--events-fd`/`--events-socket`: one JSON object per line.
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+/// Writes events to an inherited file descriptor.
+#[cfg(unix)]
+pub fn init_fd(fd: i32) -> Result<()> {
+    use std::os::fd::FromRawFd;
+    if fd <= 2 {
+        bail!("--events-fd must not be stdin/stdout/stderr (got {})", fd);
+    }
+    // SAFETY: F_GETFD/F_SETFD only inspect and flag the descriptor.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        bail!(
+            "--events-fd {} is not an open file descriptor: {}",
+            fd,
+            std::io::Error::last_os_error()
+        );
+    }
+    // kubectl and ssh children must not hold the event stream open.
+    unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) };
+    // SAFETY: the descriptor is open and was inherited for sshpod; it is
+    // claimed before anything (such as the log file) can be opened.
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+    install(Box::new(file))
+}
+
+#[cfg(not(unix))]
+pub fn init_fd(_fd: i32) -> Result<()> {
+    bail!("--events-fd is only supported on Unix")
+}
+
+/// Writes events to a listening unix socket.
+#[cfg(unix)]
+pub fn init_socket(path: &Path) -> Result<()> {
+    let stream = std::os::unix::net::UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    install(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+pub fn init_socket(_path: &Path) -> Result<()> {
+    bail!("--events-socket is only supported on Unix")
+}
+
+fn install(sink: Box<dyn Write + Send>) -> Result<()> {
+    if SINK.set(Mutex::new(sink)).is_err() {
+        bail!("event sink already initialized");
+    }
+    Ok(())
+}
+
+/// Emits `{"event": <event>, "ts": <unix secs>, ...fields}` when a sink is set.
+/// Write failures are ignored so a vanished reader never breaks the session.
+pub fn emit(event: &str, fields: Value) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let line = render(event, fields);
+    if let Ok(mut sink) = sink.lock() {
+        let _ = writeln!(sink, "{}", line).and_then(|_| sink.flush());
+    }
+}
+
+fn render(event: &str, fields: Value) -> String {
+    let mut object = json!({
+        "event": event,
+        "ts": crate::state::now_secs(),
+    });
+    if let (Some(target), Value::Object(extra)) = (object.as_object_mut(), fields) {
+        target.extend(extra);
+    }
+    object.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_merges_fields() {
+        let line = render("port", json!({"remote": 2222}));
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "port");
+        assert_eq!(value["remote"], 2222);
+        assert!(value["ts"].is_u64());
+    }
+}
@@ -0,0 +1,150 @@
+//! Long-lived `kubectl exec -i sh` per container that runs probe commands in
+//! turn (`--exec-channel`), saving the exec handshake on every call.
+
+use crate::kubectl::{self, RemoteTarget};
+use crate::remote::shell_quote;
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::Mutex;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CHANNELS: OnceLock<Mutex<HashMap<String, Channel>>> = OnceLock::new();
+
+struct Channel {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    calls: u64,
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn key(target: &RemoteTarget) -> String {
+    format!(
+        "{}/{}/{}/{}",
+        target.context.as_deref().unwrap_or_default(),
+        target.namespace,
+        target.pod,
+        target.container
+    )
+}
+
+/// Runs `command` over the target's channel and returns whether it exited 0
+/// with its trimmed stdout. `None` means the caller should exec directly:
+/// channels are disabled, or this one could not be opened or just broke.
+pub async fn run(target: &RemoteTarget, command: &[&str]) -> Option<(bool, String)> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    #[cfg(feature = "kube-api")]
+    if kubectl::use_api() {
+        return None;
+    }
+    let mut channels = CHANNELS.get_or_init(Default::default).lock().await;
+    let key = key(target);
+    if !channels.contains_key(&key) {
+        match Channel::open(target) {
+            Ok(channel) => {
+                channels.insert(key.clone(), channel);
+            }
+            Err(err) => {
+                debug!("[sshpod] exec channel unavailable: {:#}", err);
+                return None;
+            }
+        }
+    }
+    let channel = channels.get_mut(&key)?;
+    let limit = kubectl::command_timeout();
+    let reply = if limit.is_zero() {
+        channel.call(command).await
+    } else {
+        tokio::time::timeout(limit, channel.call(command))
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out after {}s", limit.as_secs())))
+    };
+    match reply {
+        Ok(reply) => Some(reply),
+        Err(err) => {
+            debug!("[sshpod] exec channel to {} failed: {:#}", target.pod, err);
+            channels.remove(&key);
+            None
+        }
+    }
+}
+
+/// Ends every open channel; later probes open new ones.
+pub async fn close_all() {
+    if let Some(channels) = CHANNELS.get() {
+        channels.lock().await.clear();
+    }
+}
+
+impl Channel {
+    fn open(target: &RemoteTarget) -> Result<Channel> {
+        let mut child = kubectl::spawn_shell_target(target)?;
+        let stdin = child.stdin.take().context("exec channel has no stdin")?;
+        let stdout = child.stdout.take().context("exec channel has no stdout")?;
+        Ok(Channel {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            calls: 0,
+        })
+    }
+
+    async fn call(&mut self, command: &[&str]) -> Result<(bool, String)> {
+        self.calls += 1;
+        let token = format!("__sshpod_{}_{}__", std::process::id(), self.calls);
+        let quoted: Vec<String> = command.iter().map(|arg| shell_quote(arg)).collect();
+        let line = format!(
+            "{} </dev/null 2>/dev/null; printf '\\n%s %s\\n' {} \"$?\"\n",
+            quoted.join(" "),
+            token
+        );
+        self.stdin.write_all(line.as_bytes()).await?;
+        self.stdin.flush().await?;
+        let mut output = Vec::new();
+        loop {
+            let line = self
+                .stdout
+                .next_line()
+                .await?
+                .context("exec channel closed")?;
+            if let Some(code) = sentinel_status(&line, &token) {
+                return Ok((code == 0, output.join("\n").trim().to_string()));
+            }
+            output.push(line);
+        }
+    }
+}
+
+/// Exit status from the line that ends a command's output.
+fn sentinel_status(line: &str, token: &str) -> Option<i32> {
+    line.strip_prefix(token)?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sentinel_lines() {
+        assert_eq!(
+            sentinel_status("__sshpod_1_2__ 0", "__sshpod_1_2__"),
+            Some(0)
+        );
+        assert_eq!(
+            sentinel_status("__sshpod_1_2__ 127", "__sshpod_1_2__"),
+            Some(127)
+        );
+        assert_eq!(sentinel_status("__sshpod_1_1__ 0", "__sshpod_1_2__"), None);
+        assert_eq!(sentinel_status("uid=0", "__sshpod_1_2__"), None);
+    }
+}
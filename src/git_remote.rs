@@ -0,0 +1,118 @@
+use crate::cli::GitRemoteArgs;
+use crate::hostspec;
+use crate::remote::shell_quote;
+use anyhow::{bail, Context, Result};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+/// git remote helper speaking the `connect` capability: git's pack protocol
+/// runs over `ssh <host> git-upload-pack|git-receive-pack <path>`.
+pub async fn run(args: GitRemoteArgs) -> Result<()> {
+    let (host, path) = parse_url(&args.url)?;
+    let mut stdin = BufReader::new(tokio::io::stdin());
+    let mut stdout = tokio::io::stdout();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdin.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let command = line.trim_end();
+        if command.is_empty() {
+            return Ok(());
+        }
+        if command == "capabilities" {
+            stdout.write_all(b"connect\n\n").await?;
+            stdout.flush().await?;
+            continue;
+        }
+        let Some(service) = command.strip_prefix("connect ") else {
+            stdout.write_all(b"unsupported\n").await?;
+            stdout.flush().await?;
+            continue;
+        };
+        if !matches!(service, "git-upload-pack" | "git-receive-pack") {
+            bail!("unsupported git service `{}`", service);
+        }
+        let mut child = Command::new("ssh")
+            .args([&host, &format!("{} {}", service, shell_quote(&path))])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("failed to run ssh")?;
+        stdout.write_all(b"\n").await?;
+        stdout.flush().await?;
+
+        let mut child_stdin = child.stdin.take().context("ssh stdin unavailable")?;
+        let mut child_stdout = child.stdout.take().context("ssh stdout unavailable")?;
+        let upload = async {
+            // `stdin` may already hold bytes git sent after the connect line.
+            tokio::io::copy(&mut stdin, &mut child_stdin).await?;
+            child_stdin.shutdown().await
+        };
+        let download = async {
+            tokio::io::copy(&mut child_stdout, &mut stdout).await?;
+            stdout.flush().await
+        };
+        // The service closing its output ends the exchange even while git
+        // keeps our stdin open.
+        tokio::pin!(upload, download);
+        let mut upload_done = false;
+        loop {
+            tokio::select! {
+                result = &mut download => {
+                    result.context("failed to relay git protocol data")?;
+                    break;
+                }
+                _ = &mut upload, if !upload_done => upload_done = true,
+            }
+        }
+        let status = child.wait().await.context("failed to wait for ssh")?;
+        if !status.success() {
+            bail!("{} exited with {}", service, status);
+        }
+        return Ok(());
+    }
+}
+
+/// Splits `<host>:<path>` (from `sshpod::<host>:<path>`) or `sshpod://<host>/<path>`.
+fn parse_url(url: &str) -> Result<(String, String)> {
+    let (host, path) = match url.strip_prefix("sshpod://") {
+        Some(rest) => {
+            let (host, path) = rest.split_once('/').context("URL has no repository path")?;
+            (host, format!("/{}", path))
+        }
+        None => {
+            let (host, path) = url
+                .split_once(':')
+                .context("expected sshpod::<host>:<path>")?;
+            (host, path.to_string())
+        }
+    };
+    if host.is_empty() || path.is_empty() {
+        bail!("expected sshpod::<host>:<path>, got `{}`", url);
+    }
+    Ok((hostspec::with_suffix(host), path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_remote_urls() {
+        assert_eq!(
+            parse_url("deployment--api.namespace--dev:/srv/app.git").unwrap(),
+            (
+                "deployment--api.namespace--dev.sshpod".to_string(),
+                "/srv/app.git".to_string()
+            )
+        );
+        assert_eq!(
+            parse_url("sshpod://pod--web.sshpod/repo.git").unwrap(),
+            ("pod--web.sshpod".to_string(), "/repo.git".to_string())
+        );
+        assert!(parse_url("pod--web").is_err());
+    }
+}
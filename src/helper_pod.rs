@@ -0,0 +1,224 @@
+use anyhow::{bail, Result};
+use clap::Args;
+use serde_json::{json, Map, Value};
+
+/// Image used for helper pods when neither `--image` nor `helper-image` is set.
+pub const DEFAULT_IMAGE: &str = "busybox:1.36";
+
+/// Scheduling flags for pods sshpod creates itself.
+#[derive(Args, Debug, Clone, Default)]
+pub struct HelperPodArgs {
+    /// Image for the helper pod (needs sh) [default: busybox:1.36]
+    #[arg(long)]
+    pub image: Option<String>,
+    /// Node selector label for the helper pod, e.g. gpu=true (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub node_selector: Vec<(String, String)>,
+    /// Taint to tolerate: key[=value][:effect], or * for all taints (repeatable)
+    #[arg(long, value_name = "TOLERATION", value_parser = parse_toleration)]
+    pub toleration: Vec<Toleration>,
+    /// RuntimeClass for the helper pod
+    #[arg(long)]
+    pub runtime_class: Option<String>,
+    /// ServiceAccount for the helper pod
+    #[arg(long)]
+    pub service_account: Option<String>,
+    /// Resource request for the helper container, e.g. cpu=100m (repeatable)
+    #[arg(long, value_name = "RESOURCE=QUANTITY", value_parser = parse_key_value)]
+    pub request: Vec<(String, String)>,
+}
+
+/// Helper pod settings merged from the config file and command line.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HelperPodOptions {
+    pub image: Option<String>,
+    pub node_selector: Vec<(String, String)>,
+    pub tolerations: Vec<Toleration>,
+    pub runtime_class: Option<String>,
+    pub service_account: Option<String>,
+    pub requests: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toleration {
+    /// `None` tolerates every taint.
+    pub key: Option<String>,
+    pub value: Option<String>,
+    pub effect: Option<String>,
+}
+
+impl HelperPodOptions {
+    /// Command-line flags override scalar settings and extend list settings.
+    pub fn merge(&self, args: &HelperPodArgs) -> HelperPodOptions {
+        let mut merged = self.clone();
+        if args.image.is_some() {
+            merged.image = args.image.clone();
+        }
+        if args.runtime_class.is_some() {
+            merged.runtime_class = args.runtime_class.clone();
+        }
+        if args.service_account.is_some() {
+            merged.service_account = args.service_account.clone();
+        }
+        merged
+            .node_selector
+            .extend(args.node_selector.iter().cloned());
+        merged.tolerations.extend(args.toleration.iter().cloned());
+        merged.requests.extend(args.request.iter().cloned());
+        merged
+    }
+
+    pub fn image(&self) -> &str {
+        self.image.as_deref().unwrap_or(DEFAULT_IMAGE)
+    }
+
+    /// Adds scheduling fields to a pod `spec` and resource requests to each
+    /// of its containers.
+    pub fn apply(&self, spec: &mut Value) {
+        let Some(spec) = spec.as_object_mut() else {
+            return;
+        };
+        if !self.node_selector.is_empty() {
+            spec.insert("nodeSelector".into(), pairs(&self.node_selector));
+        }
+        if !self.tolerations.is_empty() {
+            let tolerations = self.tolerations.iter().map(Toleration::to_json).collect();
+            spec.insert("tolerations".into(), Value::Array(tolerations));
+        }
+        if let Some(class) = &self.runtime_class {
+            spec.insert("runtimeClassName".into(), json!(class));
+        }
+        if let Some(account) = &self.service_account {
+            spec.insert("serviceAccountName".into(), json!(account));
+        }
+        if !self.requests.is_empty() {
+            if let Some(containers) = spec.get_mut("containers").and_then(Value::as_array_mut) {
+                for container in containers {
+                    container["resources"] = json!({ "requests": pairs(&self.requests) });
+                }
+            }
+        }
+    }
+}
+
+impl Toleration {
+    fn to_json(&self) -> Value {
+        let mut toleration = Map::new();
+        match (&self.key, &self.value) {
+            (Some(key), Some(value)) => {
+                toleration.insert("key".into(), json!(key));
+                toleration.insert("operator".into(), json!("Equal"));
+                toleration.insert("value".into(), json!(value));
+            }
+            (Some(key), None) => {
+                toleration.insert("key".into(), json!(key));
+                toleration.insert("operator".into(), json!("Exists"));
+            }
+            (None, _) => {
+                toleration.insert("operator".into(), json!("Exists"));
+            }
+        }
+        if let Some(effect) = &self.effect {
+            toleration.insert("effect".into(), json!(effect));
+        }
+        Value::Object(toleration)
+    }
+}
+
+fn pairs(entries: &[(String, String)]) -> Value {
+    Value::Object(
+        entries
+            .iter()
+            .map(|(key, value)| (key.clone(), json!(value)))
+            .collect(),
+    )
+}
+
+pub fn parse_key_value(value: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((key, val)) if !key.is_empty() && !val.is_empty() => {
+            Ok((key.to_string(), val.to_string()))
+        }
+        _ => bail!("expected KEY=VALUE, got `{}`", value),
+    }
+}
+
+/// Parses `key[=value][:effect]`; `*` (optionally with an effect) tolerates
+/// all taints.
+pub fn parse_toleration(value: &str) -> Result<Toleration> {
+    let (rest, effect) = match value.rsplit_once(':') {
+        Some((rest, effect)) => {
+            if !matches!(effect, "NoSchedule" | "PreferNoSchedule" | "NoExecute") {
+                bail!(
+                    "invalid taint effect `{}`: expected NoSchedule, PreferNoSchedule or NoExecute",
+                    effect
+                );
+            }
+            (rest, Some(effect.to_string()))
+        }
+        None => (value, None),
+    };
+    let (key, val) = match rest.split_once('=') {
+        Some((key, val)) => (key, Some(val.to_string())),
+        None => (rest, None),
+    };
+    if key.is_empty() || val.as_deref() == Some("") {
+        bail!("expected key[=value][:effect], got `{}`", value);
+    }
+    if key == "*" {
+        if val.is_some() {
+            bail!("`*` tolerates every taint and takes no value");
+        }
+        return Ok(Toleration {
+            key: None,
+            value: None,
+            effect,
+        });
+    }
+    Ok(Toleration {
+        key: Some(key.to_string()),
+        value: val,
+        effect,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_scheduling_fields() {
+        let options = HelperPodOptions {
+            node_selector: vec![("gpu".into(), "true".into())],
+            tolerations: vec![
+                parse_toleration("nvidia.com/gpu:NoSchedule").unwrap(),
+                parse_toleration("dedicated=tools").unwrap(),
+                parse_toleration("*").unwrap(),
+            ],
+            runtime_class: Some("gvisor".into()),
+            service_account: Some("debug".into()),
+            requests: vec![("cpu".into(), "100m".into())],
+            ..HelperPodOptions::default()
+        };
+        let mut spec = json!({ "containers": [{ "name": "c" }] });
+        options.apply(&mut spec);
+        assert_eq!(spec["nodeSelector"]["gpu"], "true");
+        assert_eq!(
+            spec["tolerations"][0],
+            json!({ "key": "nvidia.com/gpu", "operator": "Exists", "effect": "NoSchedule" })
+        );
+        assert_eq!(
+            spec["tolerations"][1],
+            json!({ "key": "dedicated", "operator": "Equal", "value": "tools" })
+        );
+        assert_eq!(spec["tolerations"][2], json!({ "operator": "Exists" }));
+        assert_eq!(spec["runtimeClassName"], "gvisor");
+        assert_eq!(spec["serviceAccountName"], "debug");
+        assert_eq!(
+            spec["containers"][0]["resources"]["requests"]["cpu"],
+            "100m"
+        );
+        assert!(parse_toleration("key:Sometimes").is_err());
+        assert!(parse_key_value("cpu").is_err());
+    }
+}
@@ -1,11 +1,64 @@
+use clap::ValueEnum;
+use std::sync::OnceLock;
 use thiserror::Error;
 
+/// Hostname suffix used unless `SSHPOD_SUFFIX` or the config's `suffix` says otherwise.
+pub const DEFAULT_SUFFIX: &str = "sshpod";
+
+static SUFFIX: OnceLock<String> = OnceLock::new();
+
+/// Sets the hostname suffix for this process; only the first call has effect.
+pub fn set_suffix(suffix: &str) {
+    let suffix = suffix.trim_matches('.');
+    if !suffix.is_empty() {
+        let _ = SUFFIX.set(suffix.to_string());
+    }
+}
+
+/// Hostname suffix without the leading dot, e.g. `sshpod`.
+pub fn suffix() -> &'static str {
+    SUFFIX.get().map(String::as_str).unwrap_or(DEFAULT_SUFFIX)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HostSpec {
     pub context: Option<String>,
     pub namespace: Option<String>,
     pub target: Target,
     pub container: Option<String>,
+    pub tunnel: Option<Endpoint>,
+    /// Node the selected pod must run on (`node--<name>`).
+    pub node: Option<String>,
+    /// Replica index into the name-sorted pods of a deployment
+    /// (`deployment--api--2` or `index--2`).
+    pub index: Option<u32>,
+    /// How to choose among ready replicas (`select--newest`).
+    pub select: Option<SelectStrategy>,
+    /// Only pods carrying this annotation value (`annotation--role=leader`).
+    pub annotation: Option<(String, String)>,
+}
+
+/// How a pod is chosen among the ready pods of a workload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SelectStrategy {
+    /// First ready pod in the API's order
+    #[default]
+    First,
+    /// A random ready pod, spreading sessions across replicas
+    Random,
+    /// The most recently created ready pod
+    Newest,
+    /// The longest-running ready pod
+    Oldest,
+    /// The ready pod whose containers restarted the fewest times
+    LeastRestarts,
+}
+
+/// `host:port` reachable from inside the pod, relayed instead of sshd.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -13,30 +66,158 @@ pub enum Target {
     Pod(String),
     Deployment(String),
     Job(String),
+    /// StatefulSet name and an optional pod ordinal (`statefulset--db--2`).
+    StatefulSet(String, Option<u32>),
+    DaemonSet(String),
+    CronJob(String),
+    Service(String),
+    /// Argo Rollouts `Rollout`.
+    Rollout(String),
+    /// OpenShift `DeploymentConfig`.
+    DeploymentConfig(String),
+    /// Privileged pod on the node, created by sshpod (`node--<name>` alone).
+    Node(String),
+}
+
+impl Target {
+    /// Resource kind as `kubectl get` spells it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Target::Pod(_) => "pod",
+            Target::Deployment(_) => "deployment",
+            Target::Job(_) => "job",
+            Target::StatefulSet(..) => "statefulset",
+            Target::DaemonSet(_) => "daemonset",
+            Target::CronJob(_) => "cronjob",
+            Target::Service(_) => "service",
+            Target::Rollout(_) => "rollout",
+            Target::DeploymentConfig(_) => "deploymentconfig",
+            Target::Node(_) => "node",
+        }
+    }
+}
+
+/// Target kind named by `--target-kind` instead of a hostname prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TargetKind {
+    Pod,
+    Deployment,
+    Job,
+    #[value(name = "statefulset")]
+    StatefulSet,
+    #[value(name = "daemonset")]
+    DaemonSet,
+    #[value(name = "cronjob")]
+    CronJob,
+    Service,
+    Rollout,
+    #[value(name = "deploymentconfig")]
+    DeploymentConfig,
+    Node,
+}
+
+impl TargetKind {
+    pub fn target(self, name: &str) -> Target {
+        let name = name.to_string();
+        match self {
+            TargetKind::Pod => Target::Pod(name),
+            TargetKind::Deployment => Target::Deployment(name),
+            TargetKind::Job => Target::Job(name),
+            TargetKind::StatefulSet => Target::StatefulSet(name, None),
+            TargetKind::DaemonSet => Target::DaemonSet(name),
+            TargetKind::CronJob => Target::CronJob(name),
+            TargetKind::Service => Target::Service(name),
+            TargetKind::Rollout => Target::Rollout(name),
+            TargetKind::DeploymentConfig => Target::DeploymentConfig(name),
+            TargetKind::Node => Target::Node(name),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum HostSpecError {
-    #[error("hostname must end with .sshpod")]
-    MissingSuffix,
+    #[error("hostname must end with .{0}")]
+    MissingSuffix(String),
     #[error(
-        "hostname must include one of pod--/deployment--/job-- (container-- optional, namespace-- optional, context-- optional), ending with .sshpod"
+        "hostname must include one of pod--/deployment--/job--/statefulset--/daemonset--/cronjob--/service--/rollout--/deploymentconfig--/node-- (container--/namespace--/context--/node--/index--/select--/annotation-- optional), ending with the sshpod suffix"
     )]
     InvalidFormat,
+    #[error("tunnel-- must be followed by <host>:<port>")]
+    InvalidTunnel,
 }
 
-pub fn parse(host: &str) -> Result<HostSpec, HostSpecError> {
+/// Appends the configured hostname suffix (`.sshpod` unless `SSHPOD_SUFFIX`
+/// or `suffix` in the config says otherwise) when a host omits it.
+pub fn with_suffix(host: &str) -> String {
     let trimmed = host.trim_end_matches('.');
-    let without_suffix = trimmed
-        .strip_suffix(".sshpod")
-        .ok_or(HostSpecError::MissingSuffix)?;
+    if strip_suffix(trimmed).is_some() {
+        trimmed.to_string()
+    } else {
+        format!("{}.{}", trimmed, suffix())
+    }
+}
+
+/// `host` without the trailing `.<suffix>`, if it has one.
+pub fn strip_suffix(host: &str) -> Option<&str> {
+    host.trim_end_matches('.')
+        .strip_suffix(suffix())?
+        .strip_suffix('.')
+}
+
+pub fn parse(host: &str) -> Result<HostSpec, HostSpecError> {
+    parse_with_suffix(host, suffix())
+}
+
+/// Spec for a plain object name, for callers that pass the rest as flags.
+pub fn from_name(kind: TargetKind, name: &str) -> HostSpec {
+    HostSpec {
+        context: None,
+        namespace: None,
+        target: kind.target(name),
+        container: None,
+        tunnel: None,
+        node: None,
+        index: None,
+        select: None,
+        annotation: None,
+    }
+}
+
+fn parse_with_suffix(host: &str, suffix: &str) -> Result<HostSpec, HostSpecError> {
+    let without_suffix = host
+        .trim_end_matches('.')
+        .strip_suffix(suffix)
+        .and_then(|rest| rest.strip_suffix('.'))
+        .ok_or_else(|| HostSpecError::MissingSuffix(suffix.to_string()))?;
 
     let mut container = None;
     let mut namespace = None;
     let mut context = None;
     let mut target = None;
+    let mut tunnel = None;
+    let mut node = None;
+    let mut index = None;
+    let mut select = None;
+    let mut annotation = None;
 
-    for token in without_suffix.split('.').filter(|s| !s.is_empty()) {
+    let mut tokens = without_suffix.split('.').filter(|s| !s.is_empty());
+    while let Some(token) = tokens.next() {
+        if let Some(rest) = token.strip_prefix("tunnel--") {
+            if tunnel.is_some() {
+                return Err(HostSpecError::InvalidFormat);
+            }
+            // Tunnel hosts may contain dots; keep joining tokens until the port.
+            let mut spec = rest.to_string();
+            while !spec.contains(':') {
+                let next = tokens.next().ok_or(HostSpecError::InvalidTunnel)?;
+                spec.push('.');
+                spec.push_str(next);
+            }
+            tunnel = Some(parse_endpoint(&spec)?);
+            continue;
+        }
+        let token = unescape(token);
+        let token = token.as_str();
         if let Some(rest) = token.strip_prefix("container--") {
             if rest.is_empty() || container.is_some() {
                 return Err(HostSpecError::InvalidFormat);
@@ -51,6 +232,38 @@ pub fn parse(host: &str) -> Result<HostSpec, HostSpecError> {
             namespace = Some(rest.to_string());
             continue;
         }
+        if let Some(rest) = token.strip_prefix("node--") {
+            if rest.is_empty() || node.is_some() {
+                return Err(HostSpecError::InvalidFormat);
+            }
+            node = Some(rest.to_string());
+            continue;
+        }
+        if let Some(rest) = token.strip_prefix("index--") {
+            if index.is_some() {
+                return Err(HostSpecError::InvalidFormat);
+            }
+            index = Some(rest.parse().map_err(|_| HostSpecError::InvalidFormat)?);
+            continue;
+        }
+        if let Some(rest) = token.strip_prefix("select--") {
+            if select.is_some() {
+                return Err(HostSpecError::InvalidFormat);
+            }
+            select = Some(
+                SelectStrategy::from_str(rest, true).map_err(|_| HostSpecError::InvalidFormat)?,
+            );
+            continue;
+        }
+        if let Some(rest) = token.strip_prefix("annotation--") {
+            match rest.split_once('=') {
+                Some((key, value)) if !key.is_empty() && annotation.is_none() => {
+                    annotation = Some((key.to_string(), value.to_string()));
+                }
+                _ => return Err(HostSpecError::InvalidFormat),
+            }
+            continue;
+        }
         if let Some(rest) = token.strip_prefix("context--") {
             if rest.is_empty() || context.is_some() {
                 return Err(HostSpecError::InvalidFormat);
@@ -59,23 +272,77 @@ pub fn parse(host: &str) -> Result<HostSpec, HostSpecError> {
             continue;
         }
         if target.is_none() {
-            target = Some(parse_target(token)?);
+            let (parsed, suffix_index) = parse_target(token)?;
+            if suffix_index.is_some() {
+                if index.is_some() {
+                    return Err(HostSpecError::InvalidFormat);
+                }
+                index = suffix_index;
+            }
+            target = Some(parsed);
             continue;
         }
         return Err(HostSpecError::InvalidFormat);
     }
 
-    let target = target.ok_or(HostSpecError::InvalidFormat)?;
+    // Alone, node-- names a node target; next to a target it constrains it.
+    let target = match (target, node.take()) {
+        (Some(target), constraint) => {
+            node = constraint;
+            target
+        }
+        (None, Some(name)) => Target::Node(name),
+        (None, None) => return Err(HostSpecError::InvalidFormat),
+    };
 
     Ok(HostSpec {
         target,
         namespace,
         context,
         container,
+        tunnel,
+        node,
+        index,
+        select,
+        annotation,
+    })
+}
+
+/// Encodes a name for a hostname label: `.` becomes `__`, which Kubernetes
+/// object names cannot contain.
+pub fn escape(name: &str) -> String {
+    name.replace('.', "__")
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("__", ".")
+}
+
+fn parse_endpoint(spec: &str) -> Result<Endpoint, HostSpecError> {
+    let (host, port) = spec.rsplit_once(':').ok_or(HostSpecError::InvalidTunnel)?;
+    let port = port.parse().map_err(|_| HostSpecError::InvalidTunnel)?;
+    if host.is_empty() || port == 0 {
+        return Err(HostSpecError::InvalidTunnel);
+    }
+    Ok(Endpoint {
+        host: host.to_string(),
+        port,
     })
 }
 
-fn parse_target(token: &str) -> Result<Target, HostSpecError> {
+/// Splits a trailing `--<digits>` replica index off a workload name.
+fn split_index(rest: &str) -> Result<(&str, Option<u32>), HostSpecError> {
+    match rest.rsplit_once("--") {
+        Some((name, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+            let index = index.parse().map_err(|_| HostSpecError::InvalidFormat)?;
+            Ok((name, Some(index)))
+        }
+        _ => Ok((rest, None)),
+    }
+}
+
+/// Parses the target token, returning any replica index it carries.
+fn parse_target(token: &str) -> Result<(Target, Option<u32>), HostSpecError> {
     if token.is_empty() {
         return Err(HostSpecError::InvalidFormat);
     }
@@ -83,31 +350,92 @@ fn parse_target(token: &str) -> Result<Target, HostSpecError> {
         if rest.is_empty() {
             return Err(HostSpecError::InvalidFormat);
         }
-        return Ok(Target::Pod(rest.to_string()));
+        return Ok((Target::Pod(rest.to_string()), None));
     }
     if let Some(rest) = token.strip_prefix("deployment--") {
-        if rest.is_empty() {
+        let (name, index) = split_index(rest)?;
+        if name.is_empty() {
             return Err(HostSpecError::InvalidFormat);
         }
-        return Ok(Target::Deployment(rest.to_string()));
+        return Ok((Target::Deployment(name.to_string()), index));
     }
     if let Some(rest) = token.strip_prefix("job--") {
         if rest.is_empty() {
             return Err(HostSpecError::InvalidFormat);
         }
-        return Ok(Target::Job(rest.to_string()));
+        return Ok((Target::Job(rest.to_string()), None));
+    }
+    if let Some(rest) = token.strip_prefix("service--") {
+        if rest.is_empty() {
+            return Err(HostSpecError::InvalidFormat);
+        }
+        return Ok((Target::Service(rest.to_string()), None));
+    }
+    if let Some(rest) = token.strip_prefix("rollout--") {
+        if rest.is_empty() {
+            return Err(HostSpecError::InvalidFormat);
+        }
+        return Ok((Target::Rollout(rest.to_string()), None));
     }
-    Ok(Target::Pod(token.to_string()))
+    if let Some(rest) = token.strip_prefix("deploymentconfig--") {
+        if rest.is_empty() {
+            return Err(HostSpecError::InvalidFormat);
+        }
+        return Ok((Target::DeploymentConfig(rest.to_string()), None));
+    }
+    if let Some(rest) = token.strip_prefix("cronjob--") {
+        if rest.is_empty() {
+            return Err(HostSpecError::InvalidFormat);
+        }
+        return Ok((Target::CronJob(rest.to_string()), None));
+    }
+    if let Some(rest) = token.strip_prefix("daemonset--") {
+        if rest.is_empty() {
+            return Err(HostSpecError::InvalidFormat);
+        }
+        return Ok((Target::DaemonSet(rest.to_string()), None));
+    }
+    if let Some(rest) = token.strip_prefix("statefulset--") {
+        let (name, ordinal) = split_index(rest)?;
+        if name.is_empty() {
+            return Err(HostSpecError::InvalidFormat);
+        }
+        return Ok((Target::StatefulSet(name.to_string(), ordinal), None));
+    }
+    Ok((Target::Pod(token.to_string()), None))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn escaped_dots() {
+        let spec = parse("pod--web__v2.namespace--team__a.context--prod__eu.sshpod").unwrap();
+        assert_eq!(spec.target, Target::Pod("web.v2".into()));
+        assert_eq!(spec.namespace.as_deref(), Some("team.a"));
+        assert_eq!(spec.context.as_deref(), Some("prod.eu"));
+        let spec = parse(&format!("deployment--{}--1.sshpod", escape("api.v1"))).unwrap();
+        assert_eq!(spec.target, Target::Deployment("api.v1".into()));
+        assert_eq!(spec.index, Some(1));
+    }
+
+    #[test]
+    fn custom_suffix() {
+        let spec = parse_with_suffix("pod--a.namespace--n.pod.internal", "pod.internal").unwrap();
+        assert_eq!(spec.target, Target::Pod("a".into()));
+        assert_eq!(spec.namespace.as_deref(), Some("n"));
+        assert!(matches!(
+            parse_with_suffix("pod--a.sshpod", "k8s"),
+            Err(HostSpecError::MissingSuffix(_))
+        ));
+        assert!(parse_with_suffix("pod--a.xk8s", "k8s").is_err());
+    }
+
     #[test]
     fn reject_missing_suffix() {
         let err = parse("pod--app.context--ctx").unwrap_err();
-        assert!(matches!(err, HostSpecError::MissingSuffix));
+        assert!(matches!(err, HostSpecError::MissingSuffix(_)));
     }
 
     #[test]
@@ -133,6 +461,75 @@ mod tests {
         assert_eq!(spec.context.as_deref(), Some("ctx"));
     }
 
+    #[test]
+    fn statefulset_ordinal() {
+        let spec = parse("statefulset--db--2.sshpod").unwrap();
+        assert_eq!(spec.target, Target::StatefulSet("db".into(), Some(2)));
+        let spec = parse("statefulset--redis--cache.sshpod").unwrap();
+        assert_eq!(
+            spec.target,
+            Target::StatefulSet("redis--cache".into(), None)
+        );
+        assert!(parse("statefulset----1.sshpod").is_err());
+    }
+
+    #[test]
+    fn deployment_replica_index() {
+        let spec = parse("deployment--api--2.namespace--n.sshpod").unwrap();
+        assert_eq!(spec.target, Target::Deployment("api".into()));
+        assert_eq!(spec.index, Some(2));
+        let spec = parse("index--1.deployment--api.sshpod").unwrap();
+        assert_eq!(spec.index, Some(1));
+        assert!(parse("deployment--api--2.index--1.sshpod").is_err());
+        assert!(parse("index--x.deployment--api.sshpod").is_err());
+    }
+
+    #[test]
+    fn annotation_token() {
+        let spec = parse("annotation--example__com/role=leader.deployment--api.sshpod").unwrap();
+        assert_eq!(
+            spec.annotation,
+            Some(("example.com/role".to_string(), "leader".to_string()))
+        );
+        assert!(parse("annotation--role.deployment--api.sshpod").is_err());
+    }
+
+    #[test]
+    fn select_strategy_token() {
+        let spec = parse("select--least-restarts.deployment--api.sshpod").unwrap();
+        assert_eq!(spec.select, Some(SelectStrategy::LeastRestarts));
+        assert_eq!(parse("deployment--api.sshpod").unwrap().select, None);
+        assert!(parse("select--busiest.deployment--api.sshpod").is_err());
+    }
+
+    #[test]
+    fn daemonset_on_node() {
+        let spec = parse("daemonset--fluentd.node--worker-1.namespace--logging.sshpod").unwrap();
+        assert_eq!(spec.target, Target::DaemonSet("fluentd".into()));
+        assert_eq!(spec.node.as_deref(), Some("worker-1"));
+        assert!(parse("daemonset--a.node--x.node--y.sshpod").is_err());
+        let spec = parse("node--worker-1.context--prod.sshpod").unwrap();
+        assert_eq!(spec.target, Target::Node("worker-1".into()));
+        assert_eq!(spec.node, None);
+    }
+
+    #[test]
+    fn tunnel_endpoint_with_dots() {
+        let spec = parse("tunnel--postgres.internal:5432.pod--jump.namespace--n.sshpod")
+            .expect("tunnel should parse");
+        assert_eq!(
+            spec.tunnel,
+            Some(Endpoint {
+                host: "postgres.internal".into(),
+                port: 5432
+            })
+        );
+        assert_eq!(spec.target, Target::Pod("jump".into()));
+        assert_eq!(spec.namespace.as_deref(), Some("n"));
+        assert!(parse("tunnel--db.pod--a.sshpod").is_err());
+        assert!(parse("tunnel--db:http.pod--a.sshpod").is_err());
+    }
+
     #[test]
     fn round_trip_common_patterns() {
         let cases = [
@@ -146,6 +543,15 @@ mod tests {
                 ("d", Some("c"), Some("n"), None),
             ),
             ("job--j.context--c.sshpod", ("j", Some("c"), None, None)),
+            ("cronjob--nightly.sshpod", ("nightly", None, None, None)),
+            (
+                "service--web.namespace--n.sshpod",
+                ("web", None, Some("n"), None),
+            ),
+            (
+                "statefulset--db.namespace--n.sshpod",
+                ("db", None, Some("n"), None),
+            ),
             (
                 "container--x.pod--a.namespace--n.context--c.sshpod",
                 ("a", Some("c"), Some("n"), Some("x")),
@@ -154,11 +560,28 @@ mod tests {
                 "pod--app.namespace--ns.sshpod",
                 ("app", None, Some("ns"), None),
             ),
+            (
+                "rollout--canary.namespace--n.sshpod",
+                ("canary", None, Some("n"), None),
+            ),
+            (
+                "deploymentconfig--api.namespace--n.sshpod",
+                ("api", None, Some("n"), None),
+            ),
         ];
         for (input, (name, ctx, ns, container)) in cases {
             let spec = parse(input).expect("should parse");
             match &spec.target {
-                Target::Pod(p) | Target::Deployment(p) | Target::Job(p) => assert_eq!(p, name),
+                Target::Pod(p)
+                | Target::Deployment(p)
+                | Target::Job(p)
+                | Target::StatefulSet(p, _)
+                | Target::DaemonSet(p)
+                | Target::CronJob(p)
+                | Target::Service(p)
+                | Target::Rollout(p)
+                | Target::DeploymentConfig(p)
+                | Target::Node(p) => assert_eq!(p, name),
             }
             assert_eq!(spec.context.as_deref(), ctx);
             assert_eq!(spec.namespace.as_deref(), ns);
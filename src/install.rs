@@ -1,3 +1,6 @@
+use crate::cli::{self, ConfigureArgs};
+use crate::crypto::CryptoPreset;
+use crate::hostspec;
 use crate::paths;
 use anyhow::{Context, Result};
 use std::fs;
@@ -6,7 +9,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 const START_MARKER: &str = "# >>> sshpod start";
 const END_MARKER: &str = "# <<< sshpod end";
 
-pub async fn run() -> Result<()> {
+pub async fn run(args: ConfigureArgs) -> Result<()> {
     let ssh_dir = paths::home_dir()?.join(".ssh");
     fs::create_dir_all(&ssh_dir)
         .with_context(|| format!("failed to create {}", ssh_dir.display()))?;
@@ -32,7 +35,7 @@ pub async fn run() -> Result<()> {
         String::new()
     };
 
-    let updated = merge_config(&current, &render_block());
+    let updated = merge_config(&current, &render_block(&args));
 
     if current == updated {
         println!("No changes needed for {}", config_path.display());
@@ -66,23 +69,53 @@ pub async fn run() -> Result<()> {
     Ok(())
 }
 
-fn render_block() -> String {
+fn render_block(args: &ConfigureArgs) -> String {
+    let mut extra = String::new();
+    if args.offline {
+        extra.push_str(" --offline");
+    }
+    if args.crypto != CryptoPreset::Default {
+        extra.push_str(&format!(" --crypto {}", args.crypto.name()));
+    }
+    if args.with_rsync {
+        extra.push_str(" --with-rsync");
+    }
+    let command = if cli::is_kubectl_plugin() {
+        // krew puts the plugin on PATH; the standalone install lives here.
+        "kubectl sshpod"
+    } else {
+        "~/.local/bin/sshpod"
+    };
+    let suffix = hostspec::suffix();
+    // ssh keeps the first ProxyCommand it matches, so the per-context jump
+    // routes go before the catch-all block that supplies everything else.
+    let jumps: String = args
+        .jump
+        .iter()
+        .map(|(context, jump)| {
+            format!(
+                "Host context--{ctx}.*.{suffix} *.context--{ctx}.{suffix} *.context--{ctx}.*.{suffix}\n  ProxyCommand {command} proxy --host %h --user %r --port %p{extra} --jump {jump}\n",
+                ctx = context,
+            )
+        })
+        .collect();
     format!(
         r#"{start}
-Host *.sshpod
-  ProxyCommand ~/.local/bin/sshpod proxy --host %h --user %r --port %p
+{jumps}Host *.{suffix}
+  ProxyCommand {command} proxy --host %h --user %r --port %p{extra}
   StrictHostKeyChecking no
   UserKnownHostsFile /dev/null
   GlobalKnownHostsFile /dev/null
   CheckHostIP no
-  IdentityFile ~/.cache/sshpod/id_ed25519
+  IdentityFile ~/.cache/sshpod/{identity}
   IdentitiesOnly yes
   BatchMode yes
   ForwardAgent yes
 {end}
 "#,
         start = START_MARKER,
-        end = END_MARKER
+        end = END_MARKER,
+        identity = args.crypto.client_key_name()
     )
 }
 
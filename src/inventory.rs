@@ -0,0 +1,145 @@
+use crate::audit;
+use crate::cli::{InventoryArgs, InventoryFormat};
+use crate::config;
+use crate::hostspec;
+use crate::kubectl;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// One reachable container, addressed by its sshpod hostname.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Entry {
+    host: String,
+    context: Option<String>,
+    namespace: String,
+    pod: String,
+    container: String,
+}
+
+pub async fn run(args: InventoryArgs) -> Result<()> {
+    let context = args.context.as_deref();
+    if let Some(ctx) = context {
+        kubectl::ensure_context_exists(ctx).await?;
+    }
+    let namespace = if args.all_namespaces {
+        None
+    } else {
+        Some(audit::resolve_namespace(context, args.namespace.clone()).await?)
+    };
+    let mut entries = Vec::new();
+    for pod in kubectl::list_pods(context, namespace.as_deref()).await? {
+        if pod.phase.as_deref() != Some("Running") {
+            continue;
+        }
+        if let Some(pattern) = &args.pattern {
+            if !config::glob_match(pattern, &pod.name) {
+                continue;
+            }
+        }
+        let multi = pod.containers.len() > 1;
+        for container in &pod.containers {
+            entries.push(Entry {
+                host: hostname(
+                    context,
+                    &pod.namespace,
+                    &pod.name,
+                    multi.then_some(container),
+                ),
+                context: args.context.clone(),
+                namespace: pod.namespace.clone(),
+                pod: pod.name.clone(),
+                container: container.clone(),
+            });
+        }
+    }
+    match args.format {
+        InventoryFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        InventoryFormat::Ansible => print!("{}", render_ansible(&entries)),
+    }
+    Ok(())
+}
+
+fn hostname(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    container: Option<&String>,
+) -> String {
+    let mut host = String::new();
+    if let Some(container) = container {
+        host.push_str(&format!("container--{}.", hostspec::escape(container)));
+    }
+    host.push_str(&format!(
+        "pod--{}.namespace--{}",
+        hostspec::escape(pod),
+        hostspec::escape(namespace)
+    ));
+    if let Some(ctx) = context {
+        host.push_str(&format!(".context--{}", hostspec::escape(ctx)));
+    }
+    host.push('.');
+    host.push_str(hostspec::suffix());
+    host
+}
+
+/// INI inventory grouped by namespace; connection vars point ssh at the
+/// sshpod ProxyCommand so no ssh config block is required.
+fn render_ansible(entries: &[Entry]) -> String {
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for entry in entries {
+        groups
+            .entry(group_name(&entry.namespace))
+            .or_default()
+            .push(&entry.host);
+    }
+    let mut out = String::new();
+    for (group, hosts) in &groups {
+        out.push_str(&format!("[{}]\n", group));
+        for host in hosts {
+            out.push_str(host);
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out.push_str("[sshpod:children]\n");
+    for group in groups.keys() {
+        out.push_str(group);
+        out.push('\n');
+    }
+    out.push_str(
+        "\n[sshpod:vars]\n\
+         ansible_ssh_common_args='-o ProxyCommand=\"sshpod proxy --host %h --user %r --port %p\" \
+         -o StrictHostKeyChecking=no -o UserKnownHostsFile=/dev/null -o IdentitiesOnly=yes'\n\
+         ansible_ssh_private_key_file=~/.cache/sshpod/id_ed25519\n",
+    );
+    out
+}
+
+fn group_name(namespace: &str) -> String {
+    format!("namespace_{}", namespace.replace(['-', '.'], "_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_ansible_groups() {
+        let entry = Entry {
+            host: hostname(None, "team-a", "web-0", None),
+            context: None,
+            namespace: "team-a".into(),
+            pod: "web-0".into(),
+            container: "app".into(),
+        };
+        assert_eq!(entry.host, "pod--web-0.namespace--team-a.sshpod");
+        let out = render_ansible(&[entry]);
+        assert!(out.starts_with("[namespace_team_a]\npod--web-0.namespace--team-a.sshpod\n"));
+        assert!(out.contains("[sshpod:children]\nnamespace_team_a\n"));
+        assert_eq!(
+            hostname(Some("prod"), "ns", "p", Some(&"c".to_string())),
+            "container--c.pod--p.namespace--ns.context--prod.sshpod"
+        );
+    }
+}
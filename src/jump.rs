@@ -0,0 +1,83 @@
+use crate::kubectl;
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::process::Stdio;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+use tokio::time::{sleep, Duration, Instant};
+
+const DEPTH_ENV: &str = "SSHPOD_JUMP_DEPTH";
+const MAX_DEPTH: u32 = 4;
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// SOCKS proxy opened over another sshpod host; kubectl traffic for the
+/// target cluster flows through it while it is alive.
+pub struct JumpProxy {
+    child: Child,
+}
+
+impl JumpProxy {
+    /// Starts `ssh -D` to `jump_host` and points kubectl at it via HTTPS_PROXY.
+    pub async fn start(jump_host: &str) -> Result<JumpProxy> {
+        let depth: u32 = std::env::var(DEPTH_ENV)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        if depth >= MAX_DEPTH {
+            bail!(
+                "jump chain deeper than {} hops; check for a jump loop at {}",
+                MAX_DEPTH,
+                jump_host
+            );
+        }
+
+        let port = free_local_port().await?;
+        info!(
+            "[sshpod] opening SOCKS proxy on 127.0.0.1:{} via {}",
+            port, jump_host
+        );
+        let child = Command::new("ssh")
+            .args([
+                "-N",
+                "-D",
+                &format!("127.0.0.1:{}", port),
+                "-o",
+                "ExitOnForwardFailure=yes",
+                jump_host,
+            ])
+            .env(DEPTH_ENV, (depth + 1).to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("failed to spawn ssh for the jump host")?;
+        let mut proxy = JumpProxy { child };
+
+        let deadline = Instant::now() + READY_TIMEOUT;
+        while TcpStream::connect(("127.0.0.1", port)).await.is_err() {
+            if let Some(status) = proxy.child.try_wait()? {
+                bail!("ssh to jump host {} exited with {}", jump_host, status);
+            }
+            if Instant::now() > deadline {
+                bail!("timed out waiting for SOCKS proxy via {}", jump_host);
+            }
+            sleep(Duration::from_millis(200)).await;
+        }
+
+        // kubectl (and its port-forward/exec streams) honour HTTPS_PROXY with socks5.
+        kubectl::set_https_proxy(format!("socks5://127.0.0.1:{}", port));
+        Ok(proxy)
+    }
+
+    pub async fn stop(&mut self) {
+        let _ = self.child.start_kill();
+        let _ = self.child.wait().await;
+    }
+}
+
+async fn free_local_port() -> Result<u16> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .context("failed to reserve a local port")?;
+    Ok(listener.local_addr()?.port())
+}
@@ -4,26 +4,30 @@ use std::path::Path;
 use std::process::Stdio;
 use tokio::fs;
 use tokio::process::Command;
+use zeroize::Zeroizing;
 
 pub struct Key {
-    pub private: String,
+    /// Wiped from memory when the key is dropped.
+    pub private: Zeroizing<String>,
     pub public: String,
 }
 
-pub async fn ensure_key(name: &str) -> Result<Key> {
+pub async fn ensure_key(name: &str, key_type: &str) -> Result<Key> {
     let cache_dir = paths::home_dir()?.join(".cache/sshpod");
     prepare_dir(&cache_dir, 0o700).await?;
 
     let private_key = cache_dir.join(name);
     let public_key = private_key.with_extension("pub");
 
-    ensure_ed25519_keys(&private_key)
+    ensure_keypair(&private_key, key_type)
         .await
         .with_context(|| format!("failed to create keypair {}", name))?;
 
-    let private = fs::read_to_string(&private_key)
-        .await
-        .with_context(|| format!("failed to read {}", private_key.display()))?;
+    let private = Zeroizing::new(
+        fs::read_to_string(&private_key)
+            .await
+            .with_context(|| format!("failed to read {}", private_key.display()))?,
+    );
     let public = fs::read_to_string(&public_key)
         .await
         .with_context(|| format!("failed to read {}", public_key.display()))?;
@@ -31,6 +35,14 @@ pub async fn ensure_key(name: &str) -> Result<Key> {
     Ok(Key { private, public })
 }
 
+/// Generates a throwaway keypair at `path` (and `path.pub`), e.g. for self-checks.
+pub async fn generate_key(path: &Path, key_type: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        prepare_dir(dir, 0o700).await?;
+    }
+    ensure_keypair(path, key_type).await
+}
+
 async fn prepare_dir(path: &Path, mode: u32) -> Result<()> {
     fs::create_dir_all(path)
         .await
@@ -43,14 +55,14 @@ async fn prepare_dir(path: &Path, mode: u32) -> Result<()> {
     Ok(())
 }
 
-async fn ensure_ed25519_keys(private_key: &Path) -> Result<()> {
+async fn ensure_keypair(private_key: &Path, key_type: &str) -> Result<()> {
     let public_key = private_key.with_extension("pub");
     if !private_key.exists() || !public_key.exists() {
         let status = Command::new("ssh-keygen")
             .args([
                 "-q",
                 "-t",
-                "ed25519",
+                key_type,
                 "-f",
                 private_key.to_str().unwrap_or_default(),
                 "-N",
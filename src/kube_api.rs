@@ -0,0 +1,388 @@
+//! Direct Kubernetes API backend (`SSHPOD_BACKEND=api`): pod lookups, exec and
+//! port-forward over the API server without spawning kubectl.
+
+use anyhow::{Context, Result};
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Status;
+use kube::api::{Api, AttachParams};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::{Client, Config};
+use log::debug;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::process::{ExitStatus, Output};
+use std::sync::OnceLock;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Clients keyed by context name; "" is the current context.
+static CLIENTS: OnceLock<Mutex<HashMap<String, Client>>> = OnceLock::new();
+
+async fn client(context: Option<&str>) -> Result<Client> {
+    let mut clients = CLIENTS.get_or_init(Default::default).lock().await;
+    let key = context.unwrap_or_default().to_string();
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.clone());
+    }
+    let options = KubeConfigOptions {
+        context: context.map(str::to_string),
+        ..Default::default()
+    };
+    let config = Config::from_custom_kubeconfig(kubeconfig()?, &options)
+        .await
+        .with_context(|| format!("failed to load kubeconfig for context `{}`", key))?;
+    let client = Client::try_from(config).context("failed to build Kubernetes client")?;
+    clients.insert(key, client.clone());
+    Ok(client)
+}
+
+fn kubeconfig() -> Result<Kubeconfig> {
+    match crate::kubectl::kubeconfig() {
+        Some(path) => Kubeconfig::read_from(path)
+            .with_context(|| format!("failed to read kubeconfig {}", path.display())),
+        None => Kubeconfig::read().context("failed to read kubeconfig"),
+    }
+}
+
+pub fn list_contexts() -> Result<Vec<String>> {
+    Ok(kubeconfig()?
+        .contexts
+        .into_iter()
+        .map(|context| context.name)
+        .collect())
+}
+
+pub fn current_context() -> Result<Option<String>> {
+    Ok(kubeconfig()?
+        .current_context
+        .filter(|name| !name.is_empty()))
+}
+
+pub fn context_namespace(context: &str) -> Result<Option<String>> {
+    Ok(kubeconfig()?
+        .contexts
+        .into_iter()
+        .find(|named| named.name == context)
+        .and_then(|named| named.context)
+        .and_then(|context| context.namespace)
+        .filter(|ns| !ns.is_empty()))
+}
+
+/// REST path for the `kubectl get ... -o json` invocations sshpod makes, or
+/// `None` when `args` use something this backend does not translate.
+pub fn api_path(args: &[&str]) -> Option<String> {
+    let mut positional = Vec::new();
+    let mut namespace = None;
+    let mut selector = None;
+    let mut field_selector = None;
+    let mut all_namespaces = false;
+    let mut iter = args.iter();
+    while let Some(&arg) = iter.next() {
+        match arg {
+            "-n" => namespace = Some(*iter.next()?),
+            "-l" => selector = Some(*iter.next()?),
+            "-o" => {
+                if *iter.next()? != "json" {
+                    return None;
+                }
+            }
+            "--all-namespaces" => all_namespaces = true,
+            _ if arg.starts_with("--field-selector=") => {
+                field_selector = arg.strip_prefix("--field-selector=");
+            }
+            _ if arg.starts_with('-') => return None,
+            _ => positional.push(arg),
+        }
+    }
+    let (resource, name) = match positional.as_slice() {
+        ["get", resource] => (*resource, None),
+        ["get", resource, name] => (*resource, Some(*name)),
+        _ => return None,
+    };
+    let (prefix, plural) = match resource.trim_end_matches('s') {
+        "pod" => ("api/v1", "pods"),
+        "service" => ("api/v1", "services"),
+        "deployment" => ("apis/apps/v1", "deployments"),
+        "statefulset" => ("apis/apps/v1", "statefulsets"),
+        "daemonset" => ("apis/apps/v1", "daemonsets"),
+        "job" => ("apis/batch/v1", "jobs"),
+        "cronjob" => ("apis/batch/v1", "cronjobs"),
+        "rollout" => ("apis/argoproj.io/v1alpha1", "rollouts"),
+        "deploymentconfig" => ("apis/apps.openshift.io/v1", "deploymentconfigs"),
+        _ => return None,
+    };
+    let mut path = match (namespace, all_namespaces) {
+        (Some(ns), false) => format!("/{}/namespaces/{}/{}", prefix, ns, plural),
+        (None, true) if name.is_none() => format!("/{}/{}", prefix, plural),
+        _ => return None,
+    };
+    if let Some(name) = name {
+        path.push('/');
+        path.push_str(name);
+    }
+    let query: Vec<String> = [
+        ("labelSelector", selector),
+        ("fieldSelector", field_selector),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some(format!("{}={}", key, encode_query(value?))))
+    .collect();
+    if !query.is_empty() {
+        path.push('?');
+        path.push_str(&query.join("&"));
+    }
+    Some(path)
+}
+
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub async fn get_json<T: DeserializeOwned>(
+    context: Option<&str>,
+    path: &str,
+    action: &str,
+) -> Result<T> {
+    let request = http::Request::get(path)
+        .body(Vec::new())
+        .context("failed to build API request")?;
+    client(context)
+        .await?
+        .request(request)
+        .await
+        .map_err(|err| crate::kubectl::failure(action, err.to_string().as_bytes()))
+}
+
+/// Runs `command` in a container and collects its output like
+/// `kubectl exec` would.
+pub async fn exec(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    container: &str,
+    command: &[&str],
+    input: Option<&[u8]>,
+) -> Result<Output> {
+    let pods: Api<Pod> = Api::namespaced(client(context).await?, namespace);
+    let params = AttachParams::default()
+        .container(container)
+        .stdin(input.is_some());
+    let command: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+    let mut process = pods
+        .exec(pod, command, &params)
+        .await
+        .with_context(|| format!("failed to exec in {}/{}", namespace, pod))?;
+    let status = process.take_status();
+    let stdout = read_all(process.stdout());
+    let stderr = read_all(process.stderr());
+    let write = async {
+        if let (Some(data), Some(mut stdin)) = (input, process.stdin()) {
+            stdin
+                .write_all(data)
+                .await
+                .context("failed to write to exec stdin")?;
+            stdin
+                .shutdown()
+                .await
+                .context("failed to close exec stdin")?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let (written, stdout, mut stderr) = tokio::join!(write, stdout, stderr);
+    written?;
+    let status = match status {
+        Some(status) => status.await,
+        None => None,
+    };
+    process.join().await.context("exec stream failed")?;
+    let code = match status {
+        Some(status) => {
+            let code = exit_code(&status);
+            if code != 0 && stderr.is_empty() {
+                stderr = status.message.unwrap_or_default().into_bytes();
+            }
+            code
+        }
+        None => 0,
+    };
+    Ok(Output {
+        status: exit_status(code),
+        stdout,
+        stderr,
+    })
+}
+
+async fn read_all(reader: Option<impl AsyncRead + Unpin>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mut reader) = reader {
+        let _ = reader.read_to_end(&mut buf).await;
+    }
+    buf
+}
+
+/// Exit code carried by the status the API server sends when exec finishes.
+fn exit_code(status: &Status) -> i32 {
+    if status.status.as_deref() == Some("Success") {
+        return 0;
+    }
+    status
+        .details
+        .as_ref()
+        .and_then(|details| details.causes.as_ref())
+        .and_then(|causes| {
+            causes
+                .iter()
+                .find(|cause| cause.reason.as_deref() == Some("ExitCode"))
+        })
+        .and_then(|cause| cause.message.as_deref()?.parse().ok())
+        .unwrap_or(1)
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// Listens on localhost and tunnels every connection to `remote_port` on the
+/// pod. Aborting the returned task closes the listener and open tunnels.
+pub async fn port_forward(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    local_port: Option<u16>,
+    remote_port: u16,
+) -> Result<(JoinHandle<Result<()>>, u16)> {
+    let pods: Api<Pod> = Api::namespaced(client(context).await?, namespace);
+    // Fail here rather than on the first connection when the pod is gone.
+    pods.get(pod)
+        .await
+        .with_context(|| format!("failed to get pod {}/{}", namespace, pod))?;
+    let listener = TcpListener::bind(("127.0.0.1", local_port.unwrap_or(0)))
+        .await
+        .context("failed to bind port-forward listener")?;
+    let port = listener.local_addr()?.port();
+    let pod = pod.to_string();
+    let task = tokio::spawn(async move {
+        let mut tunnels = JoinSet::new();
+        loop {
+            let (mut conn, peer) = listener
+                .accept()
+                .await
+                .context("failed to accept port-forward connection")?;
+            debug!("[port-forward] connection from {}", peer);
+            let pods = pods.clone();
+            let pod = pod.clone();
+            tunnels.spawn(async move {
+                let mut forwarder = match pods.portforward(&pod, &[remote_port]).await {
+                    Ok(forwarder) => forwarder,
+                    Err(err) => {
+                        debug!("[port-forward] {}", err);
+                        return;
+                    }
+                };
+                if let Some(mut upstream) = forwarder.take_stream(remote_port) {
+                    if let Err(err) = tokio::io::copy_bidirectional(&mut conn, &mut upstream).await
+                    {
+                        debug!("[port-forward] {}", err);
+                    }
+                }
+                let _ = forwarder.join().await;
+            });
+            while tunnels.try_join_next().is_some() {}
+        }
+    });
+    Ok((task, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{StatusCause, StatusDetails};
+
+    #[test]
+    fn translates_kubectl_get_args() {
+        assert_eq!(
+            api_path(&["get", "pod", "web-0", "-n", "prod", "-o", "json"]).as_deref(),
+            Some("/api/v1/namespaces/prod/pods/web-0")
+        );
+        assert_eq!(
+            api_path(&[
+                "get",
+                "pods",
+                "-n",
+                "prod",
+                "-l",
+                "app=web,tier in (a)",
+                "-o",
+                "json"
+            ])
+            .as_deref(),
+            Some("/api/v1/namespaces/prod/pods?labelSelector=app%3Dweb%2Ctier%20in%20%28a%29")
+        );
+        assert_eq!(
+            api_path(&["get", "pods", "--all-namespaces", "-o", "json"]).as_deref(),
+            Some("/api/v1/pods")
+        );
+        assert_eq!(
+            api_path(&["get", "rollout", "api", "-n", "prod", "-o", "json"]).as_deref(),
+            Some("/apis/argoproj.io/v1alpha1/namespaces/prod/rollouts/api")
+        );
+        assert_eq!(
+            api_path(&[
+                "get",
+                "pods",
+                "-n",
+                "prod",
+                "-l",
+                "app=web",
+                "--field-selector=status.phase!=Failed",
+                "-o",
+                "json"
+            ])
+            .as_deref(),
+            Some("/api/v1/namespaces/prod/pods?labelSelector=app%3Dweb&fieldSelector=status.phase%21%3DFailed")
+        );
+        assert_eq!(api_path(&["get", "pod", "web-0", "-o", "yaml"]), None);
+        assert_eq!(api_path(&["get", "nodes", "-o", "json"]), None);
+    }
+
+    #[test]
+    fn reads_exit_code_from_status() {
+        let success = Status {
+            status: Some("Success".into()),
+            ..Default::default()
+        };
+        assert_eq!(exit_code(&success), 0);
+        let failed = Status {
+            status: Some("Failure".into()),
+            details: Some(StatusDetails {
+                causes: Some(vec![StatusCause {
+                    reason: Some("ExitCode".into()),
+                    message: Some("3".into()),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(exit_code(&failed), 3);
+        assert!(!exit_status(3).success());
+    }
+}
@@ -1,9 +1,20 @@
+use crate::config::glob_match;
+use crate::error::SshpodError;
+use crate::exec_channel;
+use crate::hostspec::SelectStrategy;
 use anyhow::{bail, Context, Result};
-use serde::{de::DeserializeOwned, Deserialize};
+use clap::ValueEnum;
+use log::{info, trace};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::collections::HashMap;
-use std::process::{Output, Stdio};
-use tokio::io::AsyncWriteExt;
-use tokio::process::Command;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{Semaphore, SemaphorePermit};
 
 #[derive(Clone, Debug)]
 pub struct RemoteTarget {
@@ -13,31 +24,77 @@ pub struct RemoteTarget {
     pub container: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PodInfo {
     pub uid: String,
     pub containers: Vec<String>,
+    pub os: Option<String>,
+    /// Reason (e.g. `CrashLoopBackOff`) for containers currently waiting.
+    pub waiting: HashMap<String, String>,
+    pub phase: Option<String>,
+    /// Image of each container, keyed by container name.
+    pub images: HashMap<String, String>,
+    pub node: Option<String>,
+    pub ip: Option<String>,
+    /// `kubectl.kubernetes.io/default-container`, as honored by `kubectl exec`.
+    pub default_container: Option<String>,
+}
+
+/// Pod identity and layout as needed by cluster-wide commands.
+#[derive(Debug, Clone)]
+pub struct PodSummary {
+    pub namespace: String,
+    pub name: String,
+    pub uid: String,
+    pub containers: Vec<String>,
+    pub phase: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct Pod {
     metadata: PodMetadata,
     spec: PodSpec,
+    #[serde(default)]
+    status: Option<PodStatus>,
 }
 
 #[derive(Deserialize)]
 struct PodMetadata {
     uid: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    namespace: String,
+}
+
+#[derive(Deserialize)]
+struct FullPodList {
+    items: Vec<Pod>,
 }
 
 #[derive(Deserialize)]
 struct PodSpec {
     containers: Vec<ContainerSpec>,
+    #[serde(default)]
+    os: Option<PodOs>,
+    #[serde(default, rename = "nodeSelector")]
+    node_selector: HashMap<String, String>,
+    #[serde(default, rename = "nodeName")]
+    node_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PodOs {
+    name: String,
 }
 
 #[derive(Deserialize)]
 struct ContainerSpec {
     name: String,
+    #[serde(default)]
+    image: String,
 }
 
 #[derive(Deserialize)]
@@ -56,26 +113,38 @@ struct MatchExpression {
     values: Vec<String>,
 }
 
+/// The parts of any workload (built-in or custom resource) that lead to its
+/// pods.
 #[derive(Deserialize)]
-struct Deployment {
-    spec: DeploymentSpec,
+struct Workload {
+    #[serde(default)]
+    spec: WorkloadSpec,
 }
 
-#[derive(Deserialize)]
-struct DeploymentSpec {
-    selector: LabelSelector,
+#[derive(Default, Deserialize)]
+struct WorkloadSpec {
+    #[serde(default)]
+    selector: Option<SpecSelector>,
+    /// Argo Rollouts may borrow the selector of another workload.
+    #[serde(default, rename = "workloadRef")]
+    workload_ref: Option<WorkloadRef>,
+    #[serde(default)]
+    template: Option<PodTemplate>,
 }
 
+/// `.spec.selector` is a `LabelSelector` on most kinds but a plain label
+/// map on Services and DeploymentConfigs.
 #[derive(Deserialize)]
-struct Job {
-    spec: JobSpec,
+#[serde(untagged)]
+enum SpecSelector {
+    Labels(HashMap<String, String>),
+    Selector(LabelSelector),
 }
 
 #[derive(Deserialize)]
-struct JobSpec {
-    #[serde(default)]
-    selector: Option<LabelSelector>,
-    template: PodTemplate,
+struct WorkloadRef {
+    kind: String,
+    name: String,
 }
 
 #[derive(Deserialize)]
@@ -98,73 +167,401 @@ struct PodList {
 struct PodListItem {
     metadata: PodMetadataName,
     #[serde(default)]
+    spec: Option<PodListSpec>,
+    #[serde(default)]
     status: Option<PodStatus>,
 }
 
+#[derive(Deserialize)]
+struct PodListSpec {
+    #[serde(default, rename = "nodeName")]
+    node_name: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct PodMetadataName {
     name: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(default)]
+    namespace: Option<String>,
+    #[serde(default, rename = "creationTimestamp")]
+    creation_timestamp: Option<String>,
+    #[serde(default, rename = "deletionTimestamp")]
+    deletion_timestamp: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct PodStatus {
     #[serde(default)]
     phase: Option<String>,
+    #[serde(default, rename = "podIP")]
+    pod_ip: Option<String>,
     #[serde(default, rename = "conditions")]
     conditions: Option<Vec<PodCondition>>,
+    #[serde(default, rename = "containerStatuses")]
+    container_statuses: Vec<ContainerStatus>,
 }
 
 #[derive(Deserialize)]
-struct PodCondition {
-    #[serde(rename = "type")]
-    type_name: String,
-    status: String,
+struct ContainerStatus {
+    name: String,
+    #[serde(default, rename = "restartCount")]
+    restart_count: u32,
+    #[serde(default)]
+    state: Option<ContainerState>,
 }
 
 #[derive(Deserialize)]
-struct DeploymentList {
-    items: Vec<DeploymentItem>,
+struct ContainerState {
+    #[serde(default)]
+    waiting: Option<ContainerStateWaiting>,
 }
 
 #[derive(Deserialize)]
-struct DeploymentItem {
-    metadata: PodMetadataName,
+struct ContainerStateWaiting {
     #[serde(default)]
-    status: Option<DeploymentStatus>,
+    reason: Option<String>,
 }
 
 #[derive(Deserialize)]
-struct DeploymentStatus {
-    #[serde(default, rename = "availableReplicas")]
-    available_replicas: Option<u32>,
-    #[serde(default, rename = "readyReplicas")]
-    ready_replicas: Option<u32>,
+struct EphemeralStatusPod {
+    #[serde(default)]
+    status: Option<EphemeralPodStatus>,
 }
 
 #[derive(Deserialize)]
-struct JobList {
-    items: Vec<JobItem>,
+struct EphemeralPodStatus {
+    #[serde(default, rename = "ephemeralContainerStatuses")]
+    ephemeral_container_statuses: Vec<EphemeralContainerStatus>,
 }
 
 #[derive(Deserialize)]
-struct JobItem {
-    metadata: PodMetadataName,
+struct EphemeralContainerStatus {
+    name: String,
     #[serde(default)]
-    status: Option<JobStatus>,
+    state: HashMap<String, serde_json::Value>,
+}
+
+/// Where an ephemeral container is in its lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EphemeralState {
+    Missing,
+    /// Waiting, with the reason (e.g. `ErrImagePull`) when one is given.
+    Waiting(Option<String>),
+    Running,
+    Terminated,
 }
 
 #[derive(Deserialize)]
-struct JobStatus {
-    #[serde(default)]
-    succeeded: Option<u32>,
-    #[serde(default)]
-    active: Option<u32>,
-    #[serde(default)]
-    ready: Option<u32>,
+struct PodCondition {
+    #[serde(rename = "type")]
+    type_name: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct OwnedJobList {
+    items: Vec<OwnedJob>,
+}
+
+#[derive(Deserialize)]
+struct OwnedJob {
+    metadata: OwnedMetadata,
+}
+
+#[derive(Deserialize)]
+struct OwnedMetadata {
+    name: String,
+    #[serde(default, rename = "creationTimestamp")]
+    creation_timestamp: Option<String>,
+    #[serde(default, rename = "ownerReferences")]
+    owner_references: Vec<OwnerReference>,
+}
+
+#[derive(Deserialize)]
+struct OwnerReference {
+    kind: String,
+    name: String,
+}
+
+/// Set once at startup when `SSHPOD_BACKEND=api` selects the API backend.
+static USE_API: OnceLock<bool> = OnceLock::new();
+
+/// Selects how sshpod talks to the cluster: `kubectl` (default) or `api`.
+pub fn set_backend(name: &str) -> Result<()> {
+    match name {
+        "kubectl" => Ok(()),
+        "api" if cfg!(feature = "kube-api") => {
+            let _ = USE_API.set(true);
+            Ok(())
+        }
+        "api" => bail!("SSHPOD_BACKEND=api needs sshpod built with the kube-api feature"),
+        other => bail!(
+            "unknown SSHPOD_BACKEND `{}` (expected kubectl or api)",
+            other
+        ),
+    }
+}
+
+#[cfg(feature = "kube-api")]
+pub fn use_api() -> bool {
+    USE_API.get().copied().unwrap_or(false)
+}
+
+static KUBECONFIG: OnceLock<PathBuf> = OnceLock::new();
+static HTTPS_PROXY: OnceLock<String> = OnceLock::new();
+static KUBECTL_BIN: OnceLock<PathBuf> = OnceLock::new();
+static CLIENT: OnceLock<ClientKind> = OnceLock::new();
+
+/// Command-line client sshpod drives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ClientKind {
+    Kubectl,
+    /// OpenShift's `oc`, which takes the same arguments.
+    Oc,
+}
+
+/// Sends every later kubectl invocation through `url` (a jump host's SOCKS
+/// proxy).
+pub fn set_https_proxy(url: String) {
+    let _ = HTTPS_PROXY.set(url);
+}
+
+/// Uses `client`, or when `None`, `oc` only if `kubectl` is not on PATH.
+pub fn set_client(client: Option<ClientKind>) {
+    let client = client.unwrap_or_else(|| {
+        if !on_path("kubectl") && on_path("oc") {
+            ClientKind::Oc
+        } else {
+            ClientKind::Kubectl
+        }
+    });
+    let _ = CLIENT.set(client);
+}
+
+pub fn client() -> ClientKind {
+    CLIENT.get().copied().unwrap_or(ClientKind::Kubectl)
+}
+
+fn on_path(name: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(name).is_file()))
+}
+
+/// Runs `path` (e.g. a specific kubectl version or a wrapper) instead of
+/// `kubectl` from PATH.
+pub fn set_kubectl_bin(path: PathBuf) {
+    let _ = KUBECTL_BIN.set(path);
+}
+
+/// Points every kubectl call (and the API backend) at `path` instead of the
+/// default kubeconfig.
+pub fn set_kubeconfig(path: PathBuf) {
+    let _ = KUBECONFIG.set(path);
+}
+
+pub fn kubeconfig() -> Option<&'static Path> {
+    KUBECONFIG.get().map(PathBuf::as_path)
 }
 
-fn kubectl_base(context: Option<&str>) -> Command {
-    let mut cmd = Command::new("kubectl");
+/// Limit on each short kubectl call, so a hung API server fails the
+/// connection instead of stalling ssh. Zero disables it.
+static COMMAND_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn set_command_timeout(limit: Duration) {
+    let _ = COMMAND_TIMEOUT.set(limit);
+}
+
+/// Current per-call limit; zero when disabled.
+pub fn command_timeout() -> Duration {
+    COMMAND_TIMEOUT
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_COMMAND_TIMEOUT)
+}
+
+/// Short kubectl calls allowed to run at once, so retries, list fallbacks
+/// and parallel lookups queue up instead of forking a burst of processes
+/// that trips client-side throttling.
+const MAX_CONCURRENT_CALLS: usize = 4;
+static CALL_SLOTS: Semaphore = Semaphore::const_new(MAX_CONCURRENT_CALLS);
+
+async fn call_slot() -> SemaphorePermit<'static> {
+    // The semaphore is never closed.
+    CALL_SLOTS
+        .acquire()
+        .await
+        .expect("kubectl call semaphore closed")
+}
+
+/// Awaits a kubectl process once a call slot is free, killing it (via
+/// `kill_on_drop`) once it outlives the command timeout. The process must
+/// be started inside `output` so it only runs while holding the slot.
+async fn timed(
+    output: impl std::future::Future<Output = std::io::Result<Output>>,
+    action: &str,
+) -> Result<Output> {
+    timed_at_least(output, action, Duration::ZERO).await
+}
+
+/// [`timed`] for a call that waits on purpose for up to `floor`, such as
+/// `kubectl wait`; a shorter configured timeout is raised to it.
+async fn timed_at_least(
+    output: impl std::future::Future<Output = std::io::Result<Output>>,
+    action: &str,
+    floor: Duration,
+) -> Result<Output> {
+    let _slot = call_slot().await;
+    let limit = match command_timeout() {
+        limit if limit.is_zero() => limit,
+        limit => limit.max(floor),
+    };
+    let output = if limit.is_zero() {
+        output.await
+    } else {
+        tokio::time::timeout(limit, output)
+            .await
+            .map_err(|_| SshpodError::Timeout {
+                action: action.to_string(),
+                seconds: limit.as_secs(),
+            })?
+    };
+    output.with_context(|| format!("failed to run kubectl {}", action))
+}
+
+static RETRIES: OnceLock<u32> = OnceLock::new();
+const DEFAULT_RETRIES: u32 = 3;
+
+/// How often a kubectl get/exec is retried after a transient API failure.
+pub fn set_retries(count: u32) {
+    let _ = RETRIES.set(count);
+}
+
+/// Reruns `attempt` with exponential backoff while kubectl reports a
+/// transient API failure, returning the last output either way.
+async fn with_retries<F, Fut>(action: &str, mut attempt: F) -> Result<Output>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<Output>>,
+{
+    let retries = RETRIES.get().copied().unwrap_or(DEFAULT_RETRIES);
+    let mut delay = Duration::from_millis(500);
+    let mut tries = 0;
+    loop {
+        let output = attempt().await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() || tries >= retries || !is_transient(&stderr) {
+            return Ok(output);
+        }
+        tries += 1;
+        info!(
+            "[sshpod] kubectl {} hit a transient error, retrying in {}ms ({}/{}): {}",
+            action,
+            delay.as_millis(),
+            tries,
+            retries,
+            stderr.trim()
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(Duration::from_secs(8));
+    }
+}
+
+/// Whether kubectl's own error output (not the remote command's) points at a
+/// flaky connection or an overloaded API server.
+fn is_transient(stderr: &str) -> bool {
+    const MARKERS: &[&str] = &[
+        "connection refused",
+        "connection reset by peer",
+        "TLS handshake timeout",
+        "i/o timeout",
+        "etcdserver: leader changed",
+        "etcdserver: request timed out",
+        "TooManyRequests",
+        "Too Many Requests",
+        "the server is currently unable to handle the request",
+        "http2: client connection lost",
+    ];
+    stderr
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| {
+            line.starts_with("error:")
+                || line.starts_with("Error from server")
+                || line.starts_with("Unable to connect to the server")
+                // klog lines such as "E0501 12:00:00.000000 ...".
+                || (line.starts_with('E') && line[1..].starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .any(|line| MARKERS.iter().any(|marker| line.contains(marker)))
+}
+
+/// Re-login command for a failure caused by expired or rejected cluster
+/// credentials, picked from the exec plugin named in the error.
+fn credentials_hint(stderr: &str) -> Option<String> {
+    let lower = stderr.to_ascii_lowercase();
+    let expired = lower.contains("unauthorized")
+        || lower.contains("must be logged in to the server")
+        || lower.contains("getting credentials: exec")
+        || lower.contains("token has expired")
+        || lower.contains("token is expired");
+    if !expired {
+        return None;
+    }
+    let login = if lower.contains("gke-gcloud-auth-plugin") || lower.contains("gcloud") {
+        "run `gcloud auth login` (and `gcloud container clusters get-credentials` if needed)"
+    } else if lower.contains("aws-iam-authenticator") || lower.contains("aws") {
+        "run `aws sso login` (or refresh your AWS credentials)"
+    } else if lower.contains("kubelogin") || lower.contains("azure") || lower.contains("az login") {
+        "run `az login` (then `kubelogin convert-kubeconfig` if needed)"
+    } else if lower.contains("oc login") || client() == ClientKind::Oc {
+        "run `oc login`"
+    } else {
+        "log in to the cluster again or refresh the kubeconfig"
+    };
+    Some(format!(
+        "cluster credentials are expired or were rejected; {} and retry",
+        login
+    ))
+}
+
+/// Error for a failed kubectl call: guidance first when the credentials are
+/// the problem, the first stderr line as detail either way.
+pub(crate) fn failure(action: &str, stderr: &[u8]) -> anyhow::Error {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    let action = action.to_string();
+    match credentials_hint(stderr) {
+        Some(hint) => SshpodError::CredentialsExpired {
+            action,
+            hint,
+            detail: stderr.lines().last().unwrap_or_default().trim().to_string(),
+        },
+        None => SshpodError::Kubectl {
+            action,
+            stderr: stderr.to_string(),
+            transient: is_transient(stderr),
+        },
+    }
+    .into()
+}
+
+pub(crate) fn kubectl_base(context: Option<&str>) -> Command {
+    let mut cmd = match KUBECTL_BIN.get() {
+        Some(path) => Command::new(path),
+        None => match client() {
+            ClientKind::Kubectl => Command::new("kubectl"),
+            ClientKind::Oc => Command::new("oc"),
+        },
+    };
+    cmd.kill_on_drop(true);
+    if let Some(proxy) = HTTPS_PROXY.get() {
+        cmd.env("HTTPS_PROXY", proxy);
+    }
+    if let Some(path) = kubeconfig() {
+        cmd.arg("--kubeconfig").arg(path);
+    }
     if let Some(ctx) = context {
         cmd.arg("--context").arg(ctx);
     }
@@ -176,17 +573,18 @@ async fn run_kubectl_json<T: DeserializeOwned>(
     args: &[&str],
     action: &str,
 ) -> Result<T> {
-    let output = kubectl_base(context)
-        .args(args)
-        .output()
-        .await
-        .with_context(|| format!("failed to run kubectl {}", action))?;
+    #[cfg(feature = "kube-api")]
+    if use_api() {
+        if let Some(path) = crate::kube_api::api_path(args) {
+            return crate::kube_api::get_json(context, &path, action).await;
+        }
+    }
+    let output = with_retries(action, || {
+        timed(kubectl_base(context).args(args).output(), action)
+    })
+    .await?;
     if !output.status.success() {
-        bail!(
-            "kubectl {} failed: {}",
-            action,
-            String::from_utf8_lossy(&output.stderr).trim()
-        );
+        return Err(failure(action, &output.stderr));
     }
     serde_json::from_slice(&output.stdout)
         .with_context(|| format!("failed to parse kubectl {} json output", action))
@@ -199,18 +597,76 @@ async fn fetch_with_ready_list<T: DeserializeOwned>(
     args: &[&str],
     action: &str,
 ) -> Result<T> {
-    match run_kubectl_json(context, args, action).await {
+    // List alongside the lookup so a miss costs one round trip, not two; a
+    // hit drops the listing (and kills its kubectl) unfinished.
+    let fetch = run_kubectl_json(context, args, action);
+    let listing = list_resources(context, namespace, kind);
+    tokio::pin!(fetch, listing);
+    let mut listed = None;
+    let fetched = loop {
+        tokio::select! {
+            fetched = &mut fetch => break fetched,
+            list = &mut listing, if listed.is_none() => listed = Some(list),
+        }
+    };
+    match fetched {
         Ok(value) => Ok(value),
-        Err(err) => {
-            let mut message = err.to_string();
-            if let Ok(list) = list_resources(context, namespace, kind).await {
-                if !list.is_empty() {
-                    message.push_str(&format!(" Ready {kind}s: {}", list.join(", ")));
+        Err(mut err) => {
+            let mut hint = String::new();
+            let list = match listed {
+                Some(list) => list,
+                None => listing.await,
+            };
+            if let Ok(list) = list {
+                // args are always `get <kind> <name> ...`.
+                let requested = args.get(2).copied().unwrap_or_default();
+                let closest = closest_names(requested, &list);
+                if !closest.is_empty() {
+                    hint = format!(" Did you mean {}?", closest.join(" or "));
+                } else if !list.is_empty() {
+                    hint = format!(" Ready {kind}s: {}", list.join(", "));
                 }
             }
-            bail!(message);
+            if let Some(SshpodError::Kubectl { stderr, .. }) = err.downcast_mut() {
+                stderr.push_str(&hint);
+                return Err(err);
+            }
+            bail!("{}{}", err, hint);
+        }
+    }
+}
+
+/// Up to three of `names` closest to `requested` by edit distance, ignoring
+/// ones too different to be a typo or a truncated name.
+fn closest_names<'a>(requested: &str, names: &'a [String]) -> Vec<&'a str> {
+    let mut scored: Vec<(usize, &str)> = names
+        .iter()
+        .filter_map(|name| {
+            // A prefix (e.g. a deployment name for its pods) is a strong hint.
+            let distance = if name.starts_with(requested) || requested.starts_with(name.as_str()) {
+                0
+            } else {
+                edit_distance(requested, name)
+            };
+            (distance <= (requested.len() / 3).max(2)).then_some((distance, name.as_str()))
+        })
+        .collect();
+    scored.sort();
+    scored.into_iter().take(3).map(|(_, name)| name).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
         }
+        previous = current;
     }
+    previous[b.len()]
 }
 
 pub async fn ensure_context_exists(context: &str) -> Result<()> {
@@ -218,24 +674,27 @@ pub async fn ensure_context_exists(context: &str) -> Result<()> {
     if contexts.iter().any(|c| c == context) {
         return Ok(());
     }
-    bail!(
-        "context `{}` not found. Available contexts: {}",
-        context,
-        contexts.join(", ")
-    );
+    Err(SshpodError::ContextNotFound {
+        context: context.to_string(),
+        available: contexts,
+    }
+    .into())
 }
 
 pub async fn list_contexts() -> Result<Vec<String>> {
-    let output = Command::new("kubectl")
-        .args(["config", "get-contexts", "-o", "name"])
-        .output()
-        .await
-        .context("failed to run kubectl config get-contexts")?;
+    #[cfg(feature = "kube-api")]
+    if use_api() {
+        return crate::kube_api::list_contexts();
+    }
+    let output = timed(
+        kubectl_base(None)
+            .args(["config", "get-contexts", "-o", "name"])
+            .output(),
+        "config get-contexts",
+    )
+    .await?;
     if !output.status.success() {
-        bail!(
-            "kubectl config get-contexts failed: {}",
-            String::from_utf8_lossy(&output.stderr).trim()
-        );
+        return Err(failure("config get-contexts", &output.stderr));
     }
     let list = String::from_utf8_lossy(&output.stdout)
         .lines()
@@ -245,27 +704,59 @@ pub async fn list_contexts() -> Result<Vec<String>> {
     Ok(list)
 }
 
-pub async fn get_context_namespace(context: &str) -> Result<Option<String>> {
-    let output = Command::new("kubectl")
-        .args([
-            "config",
-            "view",
-            "-o",
-            &format!(
-                "jsonpath={{.contexts[?(@.name==\"{}\")].context.namespace}}",
-                context
-            ),
-        ])
-        .output()
-        .await
-        .context("failed to run kubectl config view")?;
+/// Name of the kubeconfig's `current-context`, if one is set.
+pub async fn current_context() -> Result<Option<String>> {
+    #[cfg(feature = "kube-api")]
+    if use_api() {
+        return crate::kube_api::current_context();
+    }
+    let output = timed(
+        kubectl_base(None)
+            .args(["config", "current-context"])
+            .output(),
+        "config current-context",
+    )
+    .await?;
+    let name = stdout_text(&output.stdout);
+    // kubectl exits non-zero with "current-context is not set" when unset.
+    if !output.status.success() || name.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(name))
+}
+
+/// Namespace configured on `context`, or on the current context when `None`.
+pub async fn get_context_namespace(context: Option<&str>) -> Result<Option<String>> {
+    let context = match context {
+        Some(context) => context.to_string(),
+        None => match current_context().await? {
+            Some(current) => current,
+            None => return Ok(None),
+        },
+    };
+    #[cfg(feature = "kube-api")]
+    if use_api() {
+        return crate::kube_api::context_namespace(&context);
+    }
+    let output = timed(
+        kubectl_base(None)
+            .args([
+                "config",
+                "view",
+                "-o",
+                &format!(
+                    "jsonpath={{.contexts[?(@.name==\"{}\")].context.namespace}}",
+                    context
+                ),
+            ])
+            .output(),
+        "config view",
+    )
+    .await?;
     if !output.status.success() {
-        bail!(
-            "kubectl config view failed: {}",
-            String::from_utf8_lossy(&output.stderr).trim()
-        );
+        return Err(failure("config view", &output.stderr));
     }
-    let ns = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let ns = stdout_text(&output.stdout);
     if ns.is_empty() {
         Ok(None)
     } else {
@@ -283,79 +774,667 @@ pub async fn get_pod_info(context: Option<&str>, namespace: &str, pod: &str) ->
     )
     .await?;
 
+    let os = pod_os(&parsed.spec);
+    let phase = parsed.status.as_ref().and_then(|s| s.phase.clone());
+    let ip = parsed.status.as_ref().and_then(|s| s.pod_ip.clone());
+    let waiting = parsed
+        .status
+        .map(|status| {
+            status
+                .container_statuses
+                .into_iter()
+                .filter_map(|cs| {
+                    let reason = cs.state?.waiting?.reason?;
+                    Some((cs.name, reason))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let images = parsed
+        .spec
+        .containers
+        .iter()
+        .map(|c| (c.name.clone(), c.image.clone()))
+        .collect();
+    let default_container = parsed
+        .metadata
+        .annotations
+        .get("kubectl.kubernetes.io/default-container")
+        .cloned();
     Ok(PodInfo {
         uid: parsed.metadata.uid,
         containers: parsed.spec.containers.into_iter().map(|c| c.name).collect(),
+        os,
+        waiting,
+        phase,
+        images,
+        node: parsed.spec.node_name,
+        ip,
+        default_container,
+    })
+}
+
+fn pod_os(spec: &PodSpec) -> Option<String> {
+    spec.os
+        .as_ref()
+        .map(|os| os.name.clone())
+        .or_else(|| spec.node_selector.get("kubernetes.io/os").cloned())
+}
+
+/// Lists pods in `namespace`, or in every namespace when it is `None`.
+pub async fn list_pods(context: Option<&str>, namespace: Option<&str>) -> Result<Vec<PodSummary>> {
+    let mut args = vec!["get", "pods", "-o", "json"];
+    match namespace {
+        Some(ns) => args.extend(["-n", ns]),
+        None => args.push("--all-namespaces"),
+    }
+    let list: FullPodList = run_kubectl_json(context, &args, "get pods").await?;
+    Ok(list
+        .items
+        .into_iter()
+        .map(|pod| PodSummary {
+            namespace: pod.metadata.namespace,
+            name: pod.metadata.name,
+            uid: pod.metadata.uid,
+            containers: pod.spec.containers.into_iter().map(|c| c.name).collect(),
+            phase: pod.status.and_then(|s| s.phase),
+        })
+        .collect())
+}
+
+/// Creates `copy_name` as a copy of the target pod whose container just
+/// sleeps, then waits for it to become ready.
+pub async fn create_debug_copy(target: &RemoteTarget, copy_name: &str) -> Result<()> {
+    let context = target.context.as_deref();
+    let output = timed(
+        kubectl_base(context)
+            .args([
+                "debug",
+                &format!("pod/{}", target.pod),
+                "-n",
+                &target.namespace,
+                &format!("--copy-to={}", copy_name),
+                &format!("--container={}", target.container),
+                "--attach=false",
+                "--quiet",
+                "--",
+                "sh",
+                "-c",
+                "sleep 86400",
+            ])
+            .output(),
+        "debug",
+    )
+    .await?;
+    if !output.status.success() {
+        return Err(failure("debug --copy-to", &output.stderr));
+    }
+    wait_pod_ready(context, &target.namespace, copy_name).await
+}
+
+/// Adds ephemeral container `name` running `image` to the target pod, sharing
+/// the target container's process namespace. The container only sleeps; sshd
+/// is installed into it like into any other container.
+pub async fn add_debug_container(target: &RemoteTarget, name: &str, image: &str) -> Result<()> {
+    let output = timed(
+        kubectl_base(target.context.as_deref())
+            .args([
+                "debug",
+                &format!("pod/{}", target.pod),
+                "-n",
+                &target.namespace,
+                &format!("--target={}", target.container),
+                &format!("--container={}", name),
+                &format!("--image={}", image),
+                "--attach=false",
+                "--quiet",
+                "--",
+                "sh",
+                "-c",
+                "sleep 86400",
+            ])
+            .output(),
+        "debug",
+    )
+    .await?;
+    if !output.status.success() {
+        return Err(failure("debug", &output.stderr));
+    }
+    Ok(())
+}
+
+pub async fn ephemeral_container_state(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    name: &str,
+) -> Result<EphemeralState> {
+    let parsed: EphemeralStatusPod = run_kubectl_json(
+        context,
+        &["get", "pod", pod, "-n", namespace, "-o", "json"],
+        "get pod",
+    )
+    .await?;
+    Ok(ephemeral_state(&parsed, name))
+}
+
+fn ephemeral_state(pod: &EphemeralStatusPod, name: &str) -> EphemeralState {
+    let Some(status) = pod
+        .status
+        .iter()
+        .flat_map(|s| &s.ephemeral_container_statuses)
+        .find(|s| s.name == name)
+    else {
+        return EphemeralState::Missing;
+    };
+    if status.state.contains_key("running") {
+        EphemeralState::Running
+    } else if status.state.contains_key("terminated") {
+        EphemeralState::Terminated
+    } else {
+        let reason = status
+            .state
+            .get("waiting")
+            .and_then(|w| w.get("reason"))
+            .and_then(|r| r.as_str())
+            .map(str::to_string);
+        EphemeralState::Waiting(reason)
+    }
+}
+
+/// Polls until ephemeral container `name` runs, failing fast when it exits
+/// or its image cannot be pulled.
+pub async fn wait_ephemeral_running(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    name: &str,
+) -> Result<()> {
+    for _ in 0..120 {
+        match ephemeral_container_state(context, namespace, pod, name).await? {
+            EphemeralState::Running => return Ok(()),
+            EphemeralState::Terminated => {
+                bail!("ephemeral container {} in pod {} exited", name, pod)
+            }
+            EphemeralState::Waiting(Some(reason))
+                if reason.contains("ImagePull") || reason.starts_with("Err") =>
+            {
+                bail!(
+                    "ephemeral container {} in pod {} cannot start: {}",
+                    name,
+                    pod,
+                    reason
+                )
+            }
+            EphemeralState::Missing | EphemeralState::Waiting(_) => {}
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    bail!(
+        "ephemeral container {} in pod {} did not start within 120s",
+        name,
+        pod
+    )
+}
+
+/// Creates the object described by `manifest` (JSON or YAML) in `namespace`.
+pub async fn create_object(context: Option<&str>, namespace: &str, manifest: &str) -> Result<()> {
+    let mut cmd = kubectl_base(context);
+    cmd.args(["create", "-n", namespace, "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let create = async {
+        let mut child = cmd.spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(manifest.as_bytes()).await?;
+        }
+        child.wait_with_output().await
+    };
+    let output = timed(create, "create").await?;
+    if !output.status.success() {
+        return Err(failure("create", &output.stderr));
+    }
+    Ok(())
+}
+
+/// Phase of `pod` (`Pending` until reported), or `None` when it does not exist.
+pub async fn pod_phase(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+) -> Result<Option<String>> {
+    let output = with_retries("get pod", || {
+        timed(
+            kubectl_base(context)
+                .args([
+                    "get",
+                    "pod",
+                    pod,
+                    "-n",
+                    namespace,
+                    "--ignore-not-found",
+                    "-o",
+                    "json",
+                ])
+                .output(),
+            "get pod",
+        )
     })
+    .await?;
+    if !output.status.success() {
+        return Err(failure("get pod", &output.stderr));
+    }
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    let pod: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .context("failed to parse kubectl get pod json output")?;
+    Ok(Some(
+        pod["status"]["phase"]
+            .as_str()
+            .unwrap_or("Pending")
+            .to_string(),
+    ))
+}
+
+/// Blocks until `pod` reports Ready (up to two minutes).
+pub async fn wait_pod_ready(context: Option<&str>, namespace: &str, pod: &str) -> Result<()> {
+    let output = timed_at_least(
+        kubectl_base(context)
+            .args([
+                "wait",
+                "--for=condition=Ready",
+                &format!("pod/{}", pod),
+                "-n",
+                namespace,
+                "--timeout=120s",
+            ])
+            .output(),
+        "wait",
+        // kubectl's own --timeout plus time to start and report.
+        Duration::from_secs(130),
+    )
+    .await?;
+    if !output.status.success() {
+        return Err(SshpodError::PodNotReady {
+            pod: pod.to_string(),
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    Ok(())
 }
 
-pub async fn choose_pod_for_deployment(
+pub async fn delete_pod(context: Option<&str>, namespace: &str, pod: &str) -> Result<()> {
+    let output = timed(
+        kubectl_base(context)
+            .args(["delete", "pod", pod, "-n", namespace, "--wait=false"])
+            .output(),
+        "delete pod",
+    )
+    .await?;
+    if !output.status.success() {
+        return Err(failure("delete pod", &output.stderr));
+    }
+    Ok(())
+}
+
+/// Picks a ready pod of any workload whose pods are found through
+/// `.spec.selector`, custom resources included; `kind` is anything
+/// `kubectl get` accepts.
+pub async fn choose_pod_for(
     context: Option<&str>,
     namespace: &str,
-    deployment: &str,
+    kind: &str,
+    name: &str,
+    index: Option<u32>,
+    strategy: SelectStrategy,
+    annotation: Option<(&str, &str)>,
+) -> Result<String> {
+    let selector = workload_selector(context, namespace, kind, name).await?;
+    select_pod(
+        context, namespace, &selector, kind, index, strategy, annotation,
+    )
+    .await
+}
+
+/// Label selector for the pods of `kind`/`name`, failing with the ready
+/// names of that kind when it does not exist.
+async fn workload_selector(
+    context: Option<&str>,
+    namespace: &str,
+    kind: &str,
+    name: &str,
 ) -> Result<String> {
-    let deploy: Deployment = fetch_with_ready_list(
+    let workload: Workload = fetch_with_ready_list(
         context,
         namespace,
-        "deployment",
+        kind,
+        &["get", kind, name, "-n", namespace, "-o", "json"],
+        &format!("get {} {}", kind, name),
+    )
+    .await?;
+    let spec = match (&workload.spec.selector, &workload.spec.workload_ref) {
+        (None, Some(reference)) => {
+            let kind = reference.kind.to_ascii_lowercase();
+            let referenced: Workload = run_kubectl_json(
+                context,
+                &["get", &kind, &reference.name, "-n", namespace, "-o", "json"],
+                &format!("get {} {}", kind, reference.name),
+            )
+            .await?;
+            referenced.spec
+        }
+        _ => workload.spec,
+    };
+    selector_for(kind, name, spec)
+}
+
+fn selector_for(kind: &str, name: &str, spec: WorkloadSpec) -> Result<String> {
+    let labels = |match_labels| LabelSelector {
+        match_labels,
+        match_expressions: Vec::new(),
+    };
+    let selector = match spec.selector {
+        Some(SpecSelector::Selector(selector)) => Some(selector),
+        Some(SpecSelector::Labels(map)) if !map.is_empty() => Some(labels(map)),
+        // Jobs created without a selector still label their pods.
+        _ => spec
+            .template
+            .and_then(|t| t.metadata)
+            .filter(|meta| !meta.labels.is_empty())
+            .map(|meta| labels(meta.labels)),
+    };
+    match (selector, kind) {
+        (Some(selector), _) => to_selector(&selector),
+        (None, "job") => Ok(format!("job-name={}", name)),
+        // Pods of a DeploymentConfig always carry this label.
+        (None, "deploymentconfig") => Ok(format!("deploymentconfig={}", name)),
+        (None, "service") => bail!(
+            "service {} has no selector (headless/external endpoints); target a pod directly",
+            name
+        ),
+        (None, _) => bail!("{} {} has no selector", kind, name),
+    }
+}
+
+/// Picks the pod with the given ordinal, or by default the ready pod with the
+/// lowest ordinal.
+pub async fn choose_pod_for_statefulset(
+    context: Option<&str>,
+    namespace: &str,
+    statefulset: &str,
+    ordinal: Option<u32>,
+) -> Result<String> {
+    let selector = workload_selector(context, namespace, "statefulset", statefulset).await?;
+    let pods: PodList = run_kubectl_json(
+        context,
         &[
-            "get",
-            "deployment",
-            deployment,
-            "-n",
+            "get", "pods", "-n", namespace, "-l", &selector, "-o", "json",
+        ],
+        "get pods",
+    )
+    .await?;
+    let mut replicas: Vec<(u32, &PodListItem)> = pods
+        .items
+        .iter()
+        .filter_map(|pod| Some((statefulset_ordinal(statefulset, &pod.metadata.name)?, pod)))
+        .collect();
+    replicas.sort_by_key(|(ordinal, _)| *ordinal);
+    if let Some(wanted) = ordinal {
+        return match replicas.iter().find(|(ordinal, _)| *ordinal == wanted) {
+            Some((_, pod)) if is_terminating(pod) => bail!(
+                "pod {} is terminating; retry once the statefulset recreates it",
+                pod.metadata.name
+            ),
+            Some((_, pod)) => Ok(pod.metadata.name.clone()),
+            None => bail!(
+                "statefulset {} has no pod with ordinal {}; existing ordinals: {}",
+                statefulset,
+                wanted,
+                replicas
+                    .iter()
+                    .map(|(ordinal, _)| ordinal.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+    }
+    replicas.retain(|(_, pod)| !is_terminating(pod));
+    replicas
+        .iter()
+        .find(|(_, pod)| is_ready(pod))
+        .or_else(|| replicas.iter().find(|(_, pod)| is_running(pod)))
+        .or_else(|| replicas.first())
+        .map(|(_, pod)| pod.metadata.name.clone())
+        .with_context(|| {
+            format!(
+                "no pods found for statefulset {} in namespace {}",
+                statefulset, namespace
+            )
+        })
+}
+
+/// Ordinal of a StatefulSet pod named `<statefulset>-<ordinal>`.
+fn statefulset_ordinal(statefulset: &str, pod: &str) -> Option<u32> {
+    pod.strip_prefix(statefulset)?
+        .strip_prefix('-')?
+        .parse()
+        .ok()
+}
+
+/// Picks a pod of the DaemonSet, restricted to `node` when given.
+pub async fn choose_pod_for_daemonset(
+    context: Option<&str>,
+    namespace: &str,
+    daemonset: &str,
+    node: Option<&str>,
+    strategy: SelectStrategy,
+    annotation: Option<(&str, &str)>,
+) -> Result<String> {
+    let selector = workload_selector(context, namespace, "daemonset", daemonset).await?;
+    let Some(node) = node else {
+        return select_pod(
+            context,
             namespace,
-            "-o",
-            "json",
+            &selector,
+            "daemonset",
+            None,
+            strategy,
+            annotation,
+        )
+        .await;
+    };
+    let pods: PodList = run_kubectl_json(
+        context,
+        &[
+            "get", "pods", "-n", namespace, "-l", &selector, "-o", "json",
         ],
-        &format!("get deployment {}", deployment),
+        "get pods",
     )
     .await?;
-    let selector = to_selector(&deploy.spec.selector)?;
-    select_pod(context, namespace, &selector, "deployment").await
+    let on_node = |pod: &&PodListItem| {
+        !is_terminating(pod)
+            && pod.spec.as_ref().and_then(|s| s.node_name.as_deref()) == Some(node)
+            && annotation.is_none_or(|(key, value)| has_annotation(pod, key, value))
+    };
+    if let Some(pod) = pods
+        .items
+        .iter()
+        .filter(on_node)
+        .find(|p| is_ready(p))
+        .or_else(|| pods.items.iter().find(on_node))
+    {
+        return Ok(pod.metadata.name.clone());
+    }
+    let mut nodes: Vec<&str> = pods
+        .items
+        .iter()
+        .filter_map(|p| p.spec.as_ref()?.node_name.as_deref())
+        .collect();
+    nodes.sort_unstable();
+    bail!(
+        "daemonset {} has no pod on node {}; nodes with a pod: {}",
+        daemonset,
+        node,
+        nodes.join(", ")
+    );
 }
 
-pub async fn choose_pod_for_job(
+/// Picks a pod of the most recently created Job owned by the CronJob.
+pub async fn choose_pod_for_cronjob(
     context: Option<&str>,
     namespace: &str,
-    job: &str,
+    cronjob: &str,
 ) -> Result<String> {
-    let job_spec: Job = fetch_with_ready_list(
+    fetch_with_ready_list::<serde_json::Value>(
+        context,
+        namespace,
+        "cronjob",
+        &["get", "cronjob", cronjob, "-n", namespace, "-o", "json"],
+        &format!("get cronjob {}", cronjob),
+    )
+    .await?;
+    let jobs: OwnedJobList = run_kubectl_json(
+        context,
+        &["get", "jobs", "-n", namespace, "-o", "json"],
+        "get jobs",
+    )
+    .await?;
+    let job = latest_owned_job(&jobs.items, "CronJob", cronjob).with_context(|| {
+        format!(
+            "cronjob {} has no jobs in namespace {}; it has not run yet or its history was cleaned up",
+            cronjob, namespace
+        )
+    })?;
+    info!("[sshpod] cronjob {} resolved to job {}", cronjob, job);
+    choose_pod_for(
         context,
         namespace,
         "job",
-        &["get", "job", job, "-n", namespace, "-o", "json"],
-        &format!("get job {}", job),
+        &job,
+        None,
+        SelectStrategy::First,
+        None,
+    )
+    .await
+}
+
+fn latest_owned_job(jobs: &[OwnedJob], kind: &str, owner: &str) -> Option<String> {
+    jobs.iter()
+        .filter(|job| {
+            job.metadata
+                .owner_references
+                .iter()
+                .any(|r| r.kind == kind && r.name == owner)
+        })
+        // RFC 3339 UTC timestamps order lexicographically.
+        .max_by(|a, b| {
+            a.metadata
+                .creation_timestamp
+                .cmp(&b.metadata.creation_timestamp)
+        })
+        .map(|job| job.metadata.name.clone())
+}
+
+/// Finds the pod whose name matches the glob `pattern`, preferring a single
+/// ready match. Returns `None` when nothing matches and errors with the
+/// candidates when the match is ambiguous.
+pub async fn match_pod_name(
+    context: Option<&str>,
+    namespace: &str,
+    pattern: &str,
+) -> Result<Option<String>> {
+    let pods: PodList = run_kubectl_json(
+        context,
+        &["get", "pods", "-n", namespace, "-o", "json"],
+        "get pods",
     )
     .await?;
-    let selector = if let Some(selector) = job_spec.spec.selector {
-        to_selector(&selector)?
-    } else if let Some(meta) = job_spec.spec.template.metadata {
-        if meta.labels.is_empty() {
-            format!("job-name={}", job)
-        } else {
-            to_selector(&LabelSelector {
-                match_labels: meta.labels,
-                match_expressions: Vec::new(),
-            })?
-        }
-    } else {
-        format!("job-name={}", job)
+    pick_matching_pod(&pods.items, pattern)
+}
+
+/// Non-terminating pods matching the glob `pattern` across all namespaces,
+/// as `(namespace, pod)`.
+pub async fn find_pods_all_namespaces(
+    context: Option<&str>,
+    pattern: &str,
+) -> Result<Vec<(String, String)>> {
+    let pods: PodList = run_kubectl_json(
+        context,
+        &["get", "pods", "--all-namespaces", "-o", "json"],
+        "get pods --all-namespaces",
+    )
+    .await?;
+    Ok(pods
+        .items
+        .into_iter()
+        .filter(|p| glob_match(pattern, &p.metadata.name) && !is_terminating(p))
+        .filter_map(|p| Some((p.metadata.namespace?, p.metadata.name)))
+        .collect())
+}
+
+fn pick_matching_pod(pods: &[PodListItem], pattern: &str) -> Result<Option<String>> {
+    let candidates: Vec<&PodListItem> = pods
+        .iter()
+        .filter(|p| glob_match(pattern, &p.metadata.name) && !is_terminating(p))
+        .collect();
+    let ready: Vec<&&PodListItem> = candidates.iter().filter(|p| is_ready(p)).collect();
+    let names = |pods: &[&PodListItem]| {
+        pods.iter()
+            .map(|p| p.metadata.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
     };
-    select_pod(context, namespace, &selector, "job").await
+    match (ready.as_slice(), candidates.as_slice()) {
+        ([only], _) => Ok(Some(only.metadata.name.clone())),
+        ([], []) => Ok(None),
+        ([], [only]) => Ok(Some(only.metadata.name.clone())),
+        ([], all) => bail!(
+            "`{}` matches several pods and none is ready: {}",
+            pattern,
+            names(all)
+        ),
+        (several, _) => bail!(
+            "`{}` matches several ready pods: {}; use a more specific name",
+            pattern,
+            names(&several.iter().map(|p| **p).collect::<Vec<_>>())
+        ),
+    }
 }
 
+/// Picks a ready pod matching `selector` by `strategy`, or with `index` the
+/// pod at that position when sorted by name, so repeated connections hit the
+/// same replica.
 async fn select_pod(
     context: Option<&str>,
     namespace: &str,
     selector: &str,
     kind: &str,
+    index: Option<u32>,
+    strategy: SelectStrategy,
+    annotation: Option<(&str, &str)>,
 ) -> Result<String> {
-    let pods: PodList = run_kubectl_json(
-        context,
-        &["get", "pods", "-n", namespace, "-l", selector, "-o", "json"],
-        "get pods",
-    )
-    .await?;
+    let mut extra = vec!["-l", selector];
+    // Completed and evicted pods can never be picked (job pods excepted), so
+    // the API server leaves them out of the reply.
+    if kind != "job" {
+        extra.push("--field-selector=status.phase!=Succeeded,status.phase!=Failed");
+    }
+    let annotation_field =
+        annotation.map(|(key, _)| format!(".metadata.annotations.{}", key.replace('.', "\\.")));
+    let mut fields = SELECT_FIELDS.to_vec();
+    // Last, so a multi-line value only truncates its own column.
+    fields.extend(annotation_field.as_deref());
+    let rows = list_rows(context, namespace, "pods", &fields, &extra).await?;
+    let pods = PodList {
+        items: rows
+            .into_iter()
+            .filter(|row| row.len() > SELECT_FIELDS.len())
+            .map(|row| pod_from_row(row, annotation.map(|(key, _)| key)))
+            .collect(),
+    };
     if pods.items.is_empty() {
         bail!(
             "no pods found for {} selector `{}` in namespace {}",
@@ -364,21 +1443,153 @@ async fn select_pod(
             namespace
         );
     }
-    if let Some(p) = pods
-        .items
-        .iter()
-        .find(|p| is_ready(p))
-        .or_else(|| pods.items.iter().find(|p| is_running(p)))
-        .or_else(|| pods.items.first())
-    {
-        return Ok(p.metadata.name.clone());
+    let live: Vec<&PodListItem> = pods.items.iter().filter(|p| !is_terminating(p)).collect();
+    if live.is_empty() {
+        bail!(
+            "all {} pods for {} selector `{}` in namespace {} are terminating",
+            pods.items.len(),
+            kind,
+            selector,
+            namespace
+        );
+    }
+    let live: Vec<&PodListItem> = match annotation {
+        Some((key, value)) => {
+            let annotated: Vec<&PodListItem> = live
+                .into_iter()
+                .filter(|p| has_annotation(p, key, value))
+                .collect();
+            if annotated.is_empty() {
+                bail!(
+                    "no pod for {} selector `{}` in namespace {} has annotation {}={}",
+                    kind,
+                    selector,
+                    namespace,
+                    key,
+                    value
+                );
+            }
+            annotated
+        }
+        None => live,
+    };
+    if let Some(index) = index {
+        let mut names: Vec<&str> = live.iter().map(|p| p.metadata.name.as_str()).collect();
+        names.sort_unstable();
+        return match names.get(index as usize) {
+            Some(name) => Ok(name.to_string()),
+            None => bail!(
+                "replica index {} is out of range: {} selector `{}` matches {} pods ({})",
+                index,
+                kind,
+                selector,
+                names.len(),
+                names.join(", ")
+            ),
+        };
+    }
+    // Prefer ready pods, then running ones, then anything.
+    let ready: Vec<&PodListItem> = live.iter().copied().filter(|p| is_ready(p)).collect();
+    let running: Vec<&PodListItem> = live.iter().copied().filter(|p| is_running(p)).collect();
+    let candidates = if !ready.is_empty() {
+        ready
+    } else if !running.is_empty() {
+        running
+    } else {
+        live
+    };
+    match pick_by_strategy(&candidates, strategy) {
+        Some(p) => Ok(p.metadata.name.clone()),
+        None => bail!(
+            "no suitable pods found for {} selector `{}` in namespace {}",
+            kind,
+            selector,
+            namespace
+        ),
+    }
+}
+
+/// Pod fields `select_pod` needs, after the name.
+const SELECT_FIELDS: [&str; 5] = [
+    ".metadata.creationTimestamp",
+    ".metadata.deletionTimestamp",
+    ".status.phase",
+    ".status.conditions[?(@.type==\"Ready\")].status",
+    ".status.containerStatuses[*].restartCount",
+];
+
+/// Rebuilds the parts of a pod `select_pod` looks at from a `SELECT_FIELDS`
+/// row, followed by the value of annotation `annotation` when one was asked for.
+fn pod_from_row(row: Vec<String>, annotation: Option<&str>) -> PodListItem {
+    let mut row = row.into_iter();
+    let mut next = || row.next().filter(|value| !value.is_empty());
+    let name = next().unwrap_or_default();
+    let creation_timestamp = next();
+    let deletion_timestamp = next();
+    let phase = next();
+    let ready = next();
+    let restarts = next().unwrap_or_default();
+    let annotations = match (annotation, next()) {
+        (Some(key), Some(value)) => HashMap::from([(key.to_string(), value)]),
+        _ => HashMap::new(),
+    };
+    PodListItem {
+        metadata: PodMetadataName {
+            name,
+            annotations,
+            namespace: None,
+            creation_timestamp,
+            deletion_timestamp,
+        },
+        spec: None,
+        status: Some(PodStatus {
+            phase,
+            pod_ip: None,
+            conditions: ready.map(|status| {
+                vec![PodCondition {
+                    type_name: "Ready".to_string(),
+                    status,
+                }]
+            }),
+            container_statuses: restarts
+                .split_whitespace()
+                .map(|count| ContainerStatus {
+                    name: String::new(),
+                    restart_count: count.parse().unwrap_or(0),
+                    state: None,
+                })
+                .collect(),
+        }),
+    }
+}
+
+fn pick_by_strategy<'a>(
+    pods: &[&'a PodListItem],
+    strategy: SelectStrategy,
+) -> Option<&'a PodListItem> {
+    let restarts = |p: &PodListItem| -> u32 {
+        p.status
+            .as_ref()
+            .map(|s| s.container_statuses.iter().map(|c| c.restart_count).sum())
+            .unwrap_or(0)
+    };
+    // RFC 3339 UTC timestamps order lexicographically.
+    let created = |p: &PodListItem| p.metadata.creation_timestamp.clone();
+    let pods = pods.iter().copied();
+    match strategy {
+        SelectStrategy::First => pods.into_iter().next(),
+        SelectStrategy::Random => {
+            let all: Vec<_> = pods.collect();
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.subsec_nanos() as usize)
+                .unwrap_or(0);
+            all.get(nanos.checked_rem(all.len())?).copied()
+        }
+        SelectStrategy::Newest => pods.max_by_key(|p| created(p)),
+        SelectStrategy::Oldest => pods.min_by_key(|p| created(p)),
+        SelectStrategy::LeastRestarts => pods.min_by_key(|p| restarts(p)),
     }
-    bail!(
-        "no suitable pods found for {} selector `{}` in namespace {}",
-        kind,
-        selector,
-        namespace
-    );
 }
 
 fn to_selector(sel: &LabelSelector) -> Result<String> {
@@ -433,6 +1644,15 @@ fn is_ready(pod: &PodListItem) -> bool {
     false
 }
 
+fn has_annotation(pod: &PodListItem, key: &str, value: &str) -> bool {
+    pod.metadata.annotations.get(key).map(String::as_str) == Some(value)
+}
+
+/// Pods with a deletion timestamp are draining and about to drop connections.
+fn is_terminating(pod: &PodListItem) -> bool {
+    pod.metadata.deletion_timestamp.is_some()
+}
+
 fn is_running(pod: &PodListItem) -> bool {
     pod.status
         .as_ref()
@@ -441,77 +1661,208 @@ fn is_running(pod: &PodListItem) -> bool {
         .unwrap_or(false)
 }
 
-async fn list_from_json<T, F>(
+/// `-o jsonpath` template printing each item's name and `fields` as one
+/// tab-separated line.
+fn row_template(fields: &[&str]) -> String {
+    let mut template = String::from("jsonpath={range .items[*]}{.metadata.name}");
+    for field in fields {
+        template.push_str("{\"\\t\"}{");
+        template.push_str(field);
+        template.push('}');
+    }
+    template.push_str("{\"\\n\"}{end}");
+    template
+}
+
+/// Lists `resource` as rows of name plus `fields`, so kubectl prints only
+/// those instead of every object in full. The API backend fetches the list
+/// itself and picks the same fields out of it.
+async fn list_rows(
     context: Option<&str>,
     namespace: &str,
     resource: &str,
-    mapper: F,
-) -> Result<Vec<String>>
-where
-    T: DeserializeOwned,
-    F: FnOnce(T) -> Vec<String>,
-{
+    fields: &[&str],
+    extra: &[&str],
+) -> Result<Vec<Vec<String>>> {
     let action = format!("get {}", resource);
-    let list: T = run_kubectl_json(
-        context,
-        &["get", resource, "-n", namespace, "-o", "json"],
-        &action,
-    )
+    #[cfg(feature = "kube-api")]
+    if use_api() {
+        let mut args = vec!["get", resource, "-n", namespace, "-o", "json"];
+        args.extend(extra);
+        if let Some(path) = crate::kube_api::api_path(&args) {
+            let list: serde_json::Value =
+                crate::kube_api::get_json(context, &path, &action).await?;
+            return Ok(project_rows(&list, fields));
+        }
+    }
+    let template = row_template(fields);
+    let mut args = vec!["get", resource, "-n", namespace, "-o", template.as_str()];
+    args.extend(extra);
+    let output = with_retries(&action, || {
+        timed(kubectl_base(context).args(&args).output(), &action)
+    })
     .await?;
-    Ok(mapper(list))
+    if !output.status.success() {
+        return Err(failure(&action, &output.stderr));
+    }
+    Ok(parse_rows(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The rows `row_template(fields)` would print for `list`.
+#[cfg_attr(not(feature = "kube-api"), allow(dead_code))]
+fn project_rows(list: &serde_json::Value, fields: &[&str]) -> Vec<Vec<String>> {
+    let items = list["items"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    items
+        .iter()
+        .map(|item| {
+            std::iter::once(".metadata.name")
+                .chain(fields.iter().copied())
+                .map(|field| project_field(item, field))
+                .collect()
+        })
+        .collect()
+}
+
+/// Evaluates the jsonpath subset `row_template` fields use: `.a.b` (with
+/// `\.` inside keys), `[*]` and `[?(@.key=="value")]`. Several matches are
+/// joined with spaces, as kubectl prints them.
+#[cfg_attr(not(feature = "kube-api"), allow(dead_code))]
+fn project_field(item: &serde_json::Value, field: &str) -> String {
+    let mut current = vec![item];
+    for segment in field_segments(field) {
+        let (key, filter) = match segment.split_once('[') {
+            Some((key, filter)) => (key, Some(filter.trim_end_matches(']'))),
+            None => (segment.as_str(), None),
+        };
+        current = current
+            .into_iter()
+            .filter_map(|value| value.get(key))
+            .collect();
+        if let Some(filter) = filter {
+            let wanted = filter
+                .strip_prefix("?(@.")
+                .and_then(|f| f.strip_suffix(')'))
+                .and_then(|f| f.split_once("=="))
+                .map(|(key, value)| (key, value.trim_matches('"')));
+            current = current
+                .into_iter()
+                .filter_map(serde_json::Value::as_array)
+                .flatten()
+                .filter(|element| match wanted {
+                    Some((key, value)) => element[key].as_str() == Some(value),
+                    None => filter == "*",
+                })
+                .collect();
+        }
+    }
+    current
+        .into_iter()
+        .filter(|value| !value.is_null())
+        .map(|value| match value {
+            serde_json::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits `.a.b\.c[?(@.x=="y")]` into keys, honouring `\.` and brackets.
+#[cfg_attr(not(feature = "kube-api"), allow(dead_code))]
+fn field_segments(field: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut chars = field.trim_start_matches('.').chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            '[' => {
+                depth += 1;
+                current.push(c);
+            }
+            ']' => {
+                depth -= 1;
+                current.push(c);
+            }
+            '.' if depth == 0 => segments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current);
+    segments
+}
+
+fn parse_rows(output: &str) -> Vec<Vec<String>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split('\t').map(str::to_string).collect())
+        .collect()
+}
+
+/// Names from `rows` where any of the numeric columns is positive.
+fn names_with_count(rows: Vec<Vec<String>>) -> Vec<String> {
+    rows.into_iter()
+        .filter(|row| {
+            row[1..]
+                .iter()
+                .any(|count| count.parse::<u32>().unwrap_or(0) > 0)
+        })
+        .map(|mut row| row.swap_remove(0))
+        .collect()
 }
 
 async fn list_resources(context: Option<&str>, namespace: &str, kind: &str) -> Result<Vec<String>> {
     match kind {
         "pod" => {
-            list_from_json(context, namespace, "pods", |pods: PodList| {
-                pods.items
-                    .into_iter()
-                    .filter(is_ready)
-                    .map(|p| p.metadata.name)
-                    .collect()
-            })
-            .await
+            let rows = list_rows(
+                context,
+                namespace,
+                "pods",
+                &[".status.conditions[?(@.type==\"Ready\")].status"],
+                &["--field-selector=status.phase=Running"],
+            )
+            .await?;
+            Ok(rows
+                .into_iter()
+                .filter(|row| row.get(1).map(String::as_str) == Some("True"))
+                .map(|mut row| row.swap_remove(0))
+                .collect())
         }
-        "deployment" => {
-            list_from_json(context, namespace, "deployments", |list: DeploymentList| {
-                list.items
-                    .into_iter()
-                    .filter(|d| {
-                        if let Some(status) = &d.status {
-                            status
-                                .available_replicas
-                                .unwrap_or(0)
-                                .saturating_add(status.ready_replicas.unwrap_or(0))
-                                > 0
-                        } else {
-                            false
-                        }
-                    })
-                    .map(|d| d.metadata.name)
-                    .collect()
-            })
-            .await
+        // StatefulSet, Rollout and DeploymentConfig status carry the same
+        // replica counters.
+        "deployment" | "statefulset" | "rollout" | "deploymentconfig" => {
+            let resource = format!("{}s", kind);
+            let fields = [".status.availableReplicas", ".status.readyReplicas"];
+            Ok(names_with_count(
+                list_rows(context, namespace, &resource, &fields, &[]).await?,
+            ))
         }
+        "daemonset" => Ok(names_with_count(
+            list_rows(
+                context,
+                namespace,
+                "daemonsets",
+                &[".status.numberReady"],
+                &[],
+            )
+            .await?,
+        )),
         "job" => {
-            list_from_json(context, namespace, "jobs", |list: JobList| {
-                list.items
-                    .into_iter()
-                    .filter(|j| {
-                        if let Some(status) = &j.status {
-                            status.succeeded.unwrap_or(0) > 0
-                                || status.ready.unwrap_or(0) > 0
-                                || status.active.unwrap_or(0) > 0
-                        } else {
-                            false
-                        }
-                    })
-                    .map(|j| j.metadata.name)
-                    .collect()
-            })
-            .await
+            let fields = [".status.succeeded", ".status.ready", ".status.active"];
+            Ok(names_with_count(
+                list_rows(context, namespace, "jobs", &fields, &[]).await?,
+            ))
         }
-        _ => Ok(Vec::new()),
+        // Other kinds (custom resources) have no common readiness field.
+        other => Ok(list_rows(context, namespace, other, &[], &[])
+            .await?
+            .into_iter()
+            .map(|mut row| row.swap_remove(0))
+            .collect()),
     }
 }
 
@@ -531,24 +1882,43 @@ fn build_exec_command(
     cmd
 }
 
-pub async fn exec_capture(
+/// Runs `command` and returns its stdout byte for byte, for output that is
+/// not text (file contents, binary digests).
+pub async fn exec_capture_bytes(
     context: Option<&str>,
     namespace: &str,
     pod: &str,
     container: &str,
     command: &[&str],
-) -> Result<String> {
+) -> Result<Vec<u8>> {
     let output = exec(context, namespace, pod, container, command, None).await?;
     if !output.status.success() {
-        bail!(
-            "kubectl exec failed: {}",
-            String::from_utf8_lossy(&output.stderr).trim()
-        );
+        return Err(failure("exec", &output.stderr));
     }
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    Ok(output.stdout)
+}
+
+/// Like [`exec_capture_bytes`], for commands that print a line or two of text.
+pub async fn exec_capture(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    container: &str,
+    command: &[&str],
+) -> Result<String> {
+    let stdout = exec_capture_bytes(context, namespace, pod, container, command).await?;
+    Ok(stdout_text(&stdout))
+}
+
+fn stdout_text(stdout: &[u8]) -> String {
+    String::from_utf8_lossy(stdout).trim().to_string()
 }
 
 pub async fn exec_capture_target(target: &RemoteTarget, command: &[&str]) -> Result<String> {
+    // On failure, fall through to a direct exec to report its stderr.
+    if let Some((true, output)) = exec_channel::run(target, command).await {
+        return Ok(output);
+    }
     exec_capture(
         target.context.as_deref(),
         target.namespace.as_str(),
@@ -570,15 +1940,16 @@ pub async fn exec_capture_optional(
     if !output.status.success() {
         return Ok(None);
     }
-    Ok(Some(
-        String::from_utf8_lossy(&output.stdout).trim().to_string(),
-    ))
+    Ok(Some(stdout_text(&output.stdout)))
 }
 
 pub async fn exec_capture_optional_target(
     target: &RemoteTarget,
     command: &[&str],
 ) -> Result<Option<String>> {
+    if let Some((success, output)) = exec_channel::run(target, command).await {
+        return Ok(success.then_some(output));
+    }
     exec_capture_optional(
         target.context.as_deref(),
         target.namespace.as_str(),
@@ -589,6 +1960,51 @@ pub async fn exec_capture_optional_target(
     .await
 }
 
+/// What an exec reads on stdin: a buffer, or a reader that is pumped in
+/// chunks so large uploads never sit in memory whole.
+enum ExecInput<'a> {
+    Bytes(&'a [u8]),
+    Stream(Box<dyn Read + Send>),
+}
+
+const STREAM_CHUNK: usize = 64 * 1024;
+
+impl ExecInput<'_> {
+    async fn write_to(self, stdin: &mut ChildStdin) -> std::io::Result<()> {
+        match self {
+            ExecInput::Bytes(data) => stdin.write_all(data).await,
+            ExecInput::Stream(mut reader) => {
+                // Reading may decompress or compress, so it runs off the
+                // runtime and hands chunks over a short queue.
+                let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+                let producer = tokio::task::spawn_blocking(move || loop {
+                    let mut chunk = vec![0; STREAM_CHUNK];
+                    match reader.read(&mut chunk) {
+                        Ok(0) => return Ok(()),
+                        Ok(n) => {
+                            chunk.truncate(n);
+                            if tx.blocking_send(chunk).is_err() {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                        Err(err) => return Err(err),
+                    }
+                });
+                while let Some(chunk) = rx.recv().await {
+                    if let Err(err) = stdin.write_all(&chunk).await {
+                        // Unblocks the producer before waiting on it.
+                        drop(rx);
+                        let _ = producer.await;
+                        return Err(err);
+                    }
+                }
+                producer.await.map_err(std::io::Error::other)?
+            }
+        }
+    }
+}
+
 pub async fn exec_with_input(
     context: Option<&str>,
     namespace: &str,
@@ -597,17 +2013,79 @@ pub async fn exec_with_input(
     command: &[&str],
     input: &[u8],
 ) -> Result<String> {
+    exec_with_stdin(
+        context,
+        namespace,
+        pod,
+        container,
+        command,
+        ExecInput::Bytes(input),
+    )
+    .await
+}
+
+async fn exec_with_stdin(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    container: &str,
+    command: &[&str],
+    input: ExecInput<'_>,
+) -> Result<String> {
+    #[cfg(feature = "kube-api")]
+    if use_api() {
+        // The API client takes the whole buffer; only the kubectl path
+        // streams.
+        let buffered;
+        let input = match input {
+            ExecInput::Bytes(data) => data,
+            ExecInput::Stream(mut reader) => {
+                buffered = tokio::task::spawn_blocking(move || {
+                    let mut data = Vec::new();
+                    reader.read_to_end(&mut data).map(|_| data)
+                })
+                .await
+                .context("stdin reader panicked")?
+                .context("failed to read exec input")?;
+                &buffered[..]
+            }
+        };
+        let output = exec(context, namespace, pod, container, command, Some(input)).await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        for line in stderr.lines() {
+            trace!("[remote] {}", line);
+        }
+        if !output.status.success() {
+            bail!("exec failed: {}", stderr.trim());
+        }
+        return Ok(stdout_text(&output.stdout));
+    }
     let mut cmd = build_exec_command(context, namespace, pod, container, true);
     cmd.args(command);
     cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::inherit());
+    cmd.stderr(Stdio::piped());
     cmd.stdin(Stdio::piped());
 
+    let _slot = call_slot().await;
     let mut child = cmd.spawn().context("failed to spawn kubectl exec")?;
 
+    // Remote scripts report progress on stderr: stream it live at trace level,
+    // otherwise keep it for the error message.
+    let stderr_task = child.stderr.take().map(|stderr| {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            let mut collected = Vec::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                trace!("[remote] {}", line);
+                collected.push(line);
+            }
+            collected
+        })
+    });
+
     let mut input_err = None;
     if let Some(mut stdin) = child.stdin.take() {
-        if let Err(err) = stdin.write_all(input).await {
+        if let Err(err) = input.write_to(&mut stdin).await {
             input_err = Some(err);
         }
     }
@@ -616,12 +2094,21 @@ pub async fn exec_with_input(
         .wait_with_output()
         .await
         .context("failed to wait for kubectl exec")?;
+    let stderr_lines = match stderr_task {
+        Some(task) => task.await.unwrap_or_default(),
+        None => Vec::new(),
+    };
 
     if !output.status.success() {
+        let stderr = stderr_lines.join("\n");
         if let Some(err) = input_err {
-            bail!("kubectl exec failed (stdin error: {})", err);
+            bail!(
+                "kubectl exec failed (stdin error: {}): {}",
+                err,
+                stderr.trim()
+            );
         } else {
-            bail!("kubectl exec failed");
+            return Err(failure("exec", stderr.as_bytes()));
         }
     }
 
@@ -629,7 +2116,7 @@ pub async fn exec_with_input(
         bail!("kubectl exec stdin error: {}", err);
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    Ok(stdout_text(&output.stdout))
 }
 
 pub async fn exec_with_input_target(
@@ -648,6 +2135,74 @@ pub async fn exec_with_input_target(
     .await
 }
 
+/// Like [`exec_with_input_target`], streaming stdin from `reader`.
+pub async fn exec_with_reader_target(
+    target: &RemoteTarget,
+    command: &[&str],
+    reader: Box<dyn Read + Send>,
+) -> Result<String> {
+    exec_with_stdin(
+        target.context.as_deref(),
+        target.namespace.as_str(),
+        target.pod.as_str(),
+        target.container.as_str(),
+        command,
+        ExecInput::Stream(reader),
+    )
+    .await
+}
+
+/// Runs `command` in the target container wired to our own stdin/stdout.
+pub async fn exec_stdio_target(target: &RemoteTarget, command: &[&str]) -> Result<ExitStatus> {
+    let mut cmd = build_exec_command(
+        target.context.as_deref(),
+        &target.namespace,
+        &target.pod,
+        &target.container,
+        true,
+    );
+    cmd.args(command);
+    cmd.stdin(Stdio::inherit());
+    cmd.stdout(Stdio::inherit());
+    cmd.stderr(Stdio::inherit());
+    cmd.status().await.context("failed to run kubectl exec")
+}
+
+/// Spawns an interactive `sh` in the target container with stdin and stdout
+/// piped, for [`exec_channel`].
+pub fn spawn_shell_target(target: &RemoteTarget) -> Result<Child> {
+    let mut cmd = build_exec_command(
+        target.context.as_deref(),
+        &target.namespace,
+        &target.pod,
+        &target.container,
+        true,
+    );
+    cmd.arg("sh");
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::null());
+    cmd.spawn().context("failed to spawn kubectl exec")
+}
+
+/// Spawns `command` in the target container with stdout piped; the process
+/// is killed when the returned child is dropped.
+pub fn spawn_exec_target(target: &RemoteTarget, command: &[&str]) -> Result<Child> {
+    let mut cmd = build_exec_command(
+        target.context.as_deref(),
+        &target.namespace,
+        &target.pod,
+        &target.container,
+        false,
+    );
+    cmd.args(command);
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::null());
+    cmd.kill_on_drop(true);
+    cmd.spawn().context("failed to spawn kubectl exec")
+}
+
 async fn exec(
     context: Option<&str>,
     namespace: &str,
@@ -655,6 +2210,24 @@ async fn exec(
     container: &str,
     command: &[&str],
     input: Option<&[u8]>,
+) -> Result<Output> {
+    #[cfg(feature = "kube-api")]
+    if use_api() {
+        return crate::kube_api::exec(context, namespace, pod, container, command, input).await;
+    }
+    with_retries("exec", || {
+        exec_once(context, namespace, pod, container, command, input)
+    })
+    .await
+}
+
+async fn exec_once(
+    context: Option<&str>,
+    namespace: &str,
+    pod: &str,
+    container: &str,
+    command: &[&str],
+    input: Option<&[u8]>,
 ) -> Result<Output> {
     let mut cmd = build_exec_command(context, namespace, pod, container, input.is_some());
     cmd.args(command);
@@ -664,37 +2237,332 @@ async fn exec(
         cmd.stdin(Stdio::piped());
     }
 
-    let mut child = cmd.spawn().context("failed to spawn kubectl exec")?;
-    if let Some(data) = input {
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin
-                .write_all(data)
-                .await
-                .context("failed to write to kubectl exec stdin")?;
+    let exec = async {
+        let mut child = cmd.spawn()?;
+        if let Some(data) = input {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(data).await?;
+            }
         }
-    }
-
-    let output = child
-        .wait_with_output()
-        .await
-        .context("failed to wait for kubectl exec")?;
-    Ok(output)
+        child.wait_with_output().await
+    };
+    timed(exec, "exec").await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn suggests_close_names() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        let names: Vec<String> = ["api-server-7f9c-abcde", "api-worker", "web", "apx"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            closest_names("api-server", &names),
+            vec!["api-server-7f9c-abcde", "api-worker"]
+        );
+        assert_eq!(closest_names("api-wroker", &names), vec!["api-worker"]);
+        assert_eq!(
+            closest_names("ap", &names),
+            vec!["api-server-7f9c-abcde", "api-worker", "apx"]
+        );
+        assert!(closest_names("database", &names).is_empty());
+    }
+
+    #[test]
+    fn row_listing() {
+        assert_eq!(
+            row_template(&[".status.numberReady"]),
+            r#"jsonpath={range .items[*]}{.metadata.name}{"\t"}{.status.numberReady}{"\n"}{end}"#
+        );
+        let rows = parse_rows("api\t2\t\nidle\t\t\nworker\t\t1\n");
+        assert_eq!(rows[1], vec!["idle", "", ""]);
+        assert_eq!(names_with_count(rows), vec!["api", "worker"]);
+    }
+
+    #[test]
+    fn rebuilds_pods_from_select_rows() {
+        let rows = parse_rows(
+            "api-1\t2024-01-02T00:00:00Z\t\tRunning\tTrue\t0 3\tdb\n\
+             api-2\t2024-01-01T00:00:00Z\t2024-01-03T00:00:00Z\tRunning\tFalse\t\t\n",
+        );
+        let pods: Vec<PodListItem> = rows
+            .into_iter()
+            .map(|row| pod_from_row(row, Some("example.com/role")))
+            .collect();
+        assert!(is_ready(&pods[0]) && !is_terminating(&pods[0]));
+        assert!(has_annotation(&pods[0], "example.com/role", "db"));
+        assert!(!is_ready(&pods[1]) && is_terminating(&pods[1]));
+        let refs: Vec<&PodListItem> = pods.iter().collect();
+        let pick = |strategy| pick_by_strategy(&refs, strategy).map(|p| p.metadata.name.as_str());
+        assert_eq!(pick(SelectStrategy::Oldest), Some("api-2"));
+        assert_eq!(pick(SelectStrategy::LeastRestarts), Some("api-2"));
+    }
+
+    #[test]
+    fn projects_rows_like_jsonpath() {
+        let list = serde_json::json!({"items": [
+            {
+                "metadata": {"name": "a", "annotations": {"example.com/role": "db"}},
+                "status": {
+                    "conditions": [
+                        {"type": "Scheduled", "status": "True"},
+                        {"type": "Ready", "status": "False"}
+                    ],
+                    "containerStatuses": [{"restartCount": 1}, {"restartCount": 2}]
+                }
+            },
+            {"metadata": {"name": "b"}, "status": {"readyReplicas": 3}}
+        ]});
+        let fields = [
+            ".status.conditions[?(@.type==\"Ready\")].status",
+            ".status.containerStatuses[*].restartCount",
+            ".metadata.annotations.example\\.com/role",
+            ".status.readyReplicas",
+        ];
+        assert_eq!(
+            project_rows(&list, &fields),
+            vec![
+                vec!["a", "False", "1 2", "db", ""],
+                vec!["b", "", "", "", "3"],
+            ]
+        );
+    }
+
+    #[test]
+    fn classifies_transient_errors() {
+        assert!(is_transient(
+            "Unable to connect to the server: net/http: TLS handshake timeout"
+        ));
+        assert!(is_transient(
+            "E0501 12:00:00.000000   1234 memcache.go:265] couldn't get current server API group list: Get \"https://10.0.0.1/api\": dial tcp 10.0.0.1:443: connect: connection refused"
+        ));
+        assert!(is_transient(
+            "Error from server (TooManyRequests): the server has received too many requests"
+        ));
+        assert!(is_transient(
+            "Error from server: etcdserver: leader changed"
+        ));
+        assert!(!is_transient(
+            "Error from server (NotFound): pods \"web-0\" not found"
+        ));
+        // Output of the remote command itself is never retried.
+        assert!(!is_transient(
+            "curl: (7) Failed to connect: connection refused"
+        ));
+    }
+
+    #[test]
+    fn explains_expired_credentials() {
+        let gke = "E0501 12:00:00.000000 1 memcache.go:265] couldn't get current server API group list: Get \"https://10.0.0.1/api\": getting credentials: exec: executable gke-gcloud-auth-plugin failed with exit code 1";
+        assert!(credentials_hint(gke).unwrap().contains("gcloud auth login"));
+        let eks = "error: You must be logged in to the server (the server has asked for the client to provide credentials)\nhint: aws sso session expired";
+        assert!(credentials_hint(eks).unwrap().contains("aws sso login"));
+        let aks = "Error from server (Unauthorized): kubelogin failed to get token";
+        assert!(credentials_hint(aks).unwrap().contains("az login"));
+        assert!(
+            credentials_hint("error: You must be logged in to the server (Unauthorized)").is_some()
+        );
+        assert_eq!(
+            credentials_hint("Error from server (NotFound): pods \"web\" not found"),
+            None
+        );
+        let err = failure(
+            "get pod",
+            b"error: You must be logged in to the server (Unauthorized)",
+        );
+        assert!(err
+            .to_string()
+            .starts_with("cluster credentials are expired"));
+    }
+
+    #[test]
+    fn selectors_from_any_workload() {
+        let selector = |kind: &str, json: &str| {
+            let workload: Workload = serde_json::from_str(json).unwrap();
+            selector_for(kind, "web", workload.spec)
+        };
+        assert_eq!(
+            selector(
+                "deployment",
+                r#"{"spec":{"selector":{"matchLabels":{"app":"web"}}}}"#
+            )
+            .unwrap(),
+            "app=web"
+        );
+        assert_eq!(
+            selector("service", r#"{"spec":{"selector":{"app":"web"}}}"#).unwrap(),
+            "app=web"
+        );
+        // A custom resource exposing a standard selector.
+        assert_eq!(
+            selector(
+                "clonesets.apps.kruise.io",
+                r#"{"spec":{"selector":{"matchExpressions":[{"key":"tier","operator":"Exists"}]}}}"#
+            )
+            .unwrap(),
+            "tier"
+        );
+        assert_eq!(
+            selector(
+                "job",
+                r#"{"spec":{"template":{"metadata":{"labels":{"batch":"nightly"}}}}}"#
+            )
+            .unwrap(),
+            "batch=nightly"
+        );
+        assert_eq!(
+            selector("deploymentconfig", r#"{"spec":{"selector":{}}}"#).unwrap(),
+            "deploymentconfig=web"
+        );
+        assert!(selector("service", r#"{"spec":{}}"#).is_err());
+    }
+
+    #[test]
+    fn latest_owned_job_by_creation() {
+        let jobs: OwnedJobList = serde_json::from_str(
+            r#"{"items":[
+              {"metadata":{"name":"nightly-1","creationTimestamp":"2024-05-01T00:00:00Z","ownerReferences":[{"kind":"CronJob","name":"nightly"}]}},
+              {"metadata":{"name":"nightly-2","creationTimestamp":"2024-05-02T00:00:00Z","ownerReferences":[{"kind":"CronJob","name":"nightly"}]}},
+              {"metadata":{"name":"manual","creationTimestamp":"2024-05-03T00:00:00Z"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            latest_owned_job(&jobs.items, "CronJob", "nightly").as_deref(),
+            Some("nightly-2")
+        );
+        assert_eq!(latest_owned_job(&jobs.items, "CronJob", "weekly"), None);
+    }
+
+    #[test]
+    fn pod_name_globs_prefer_single_ready_match() {
+        let pod = |name: &str, ready: bool| PodListItem {
+            metadata: PodMetadataName {
+                name: name.into(),
+                namespace: None,
+                annotations: HashMap::new(),
+                creation_timestamp: None,
+                deletion_timestamp: None,
+            },
+            spec: None,
+            status: Some(PodStatus {
+                phase: Some("Running".into()),
+                pod_ip: None,
+                conditions: Some(vec![PodCondition {
+                    type_name: "Ready".into(),
+                    status: if ready { "True" } else { "False" }.into(),
+                }]),
+                container_statuses: Vec::new(),
+            }),
+        };
+        let mut evicted = pod("web-0", true);
+        evicted.metadata.deletion_timestamp = Some("2024-05-01T00:00:00Z".into());
+        let pods = vec![
+            pod("api-7d9-abc", true),
+            pod("api-7d9-def", false),
+            evicted,
+            pod("web-1", true),
+        ];
+        assert_eq!(
+            pick_matching_pod(&pods, "api-*").unwrap().as_deref(),
+            Some("api-7d9-abc")
+        );
+        assert_eq!(
+            pick_matching_pod(&pods, "web-*").unwrap().as_deref(),
+            Some("web-1")
+        );
+        assert_eq!(pick_matching_pod(&pods, "db-*").unwrap(), None);
+        assert!(pick_matching_pod(&pods, "*").is_err());
+    }
+
+    #[test]
+    fn selection_strategies() {
+        let pod = |name: &str, created: &str, restarts: u32| PodListItem {
+            metadata: PodMetadataName {
+                name: name.into(),
+                namespace: None,
+                annotations: HashMap::new(),
+                creation_timestamp: Some(created.into()),
+                deletion_timestamp: None,
+            },
+            spec: None,
+            status: Some(PodStatus {
+                phase: Some("Running".into()),
+                pod_ip: None,
+                conditions: None,
+                container_statuses: vec![ContainerStatus {
+                    name: "app".into(),
+                    restart_count: restarts,
+                    state: None,
+                }],
+            }),
+        };
+        let pods = [
+            pod("a", "2024-05-02T00:00:00Z", 3),
+            pod("b", "2024-05-03T00:00:00Z", 5),
+            pod("c", "2024-05-01T00:00:00Z", 0),
+        ];
+        let refs: Vec<&PodListItem> = pods.iter().collect();
+        let pick = |strategy| pick_by_strategy(&refs, strategy).map(|p| p.metadata.name.as_str());
+        assert_eq!(pick(SelectStrategy::First), Some("a"));
+        assert_eq!(pick(SelectStrategy::Newest), Some("b"));
+        assert_eq!(pick(SelectStrategy::Oldest), Some("c"));
+        assert_eq!(pick(SelectStrategy::LeastRestarts), Some("c"));
+        assert!(pick(SelectStrategy::Random).is_some());
+        assert!(pick_by_strategy(&[], SelectStrategy::Random).is_none());
+    }
+
+    #[test]
+    fn ephemeral_container_states() {
+        let pod: EphemeralStatusPod = serde_json::from_str(
+            r#"{"status":{"ephemeralContainerStatuses":[
+              {"name":"a","state":{"running":{"startedAt":"2024-05-01T00:00:00Z"}}},
+              {"name":"b","state":{"waiting":{"reason":"ErrImagePull"}}},
+              {"name":"c","state":{"terminated":{"exitCode":0}}}
+            ]}}"#,
+        )
+        .unwrap();
+        assert_eq!(ephemeral_state(&pod, "a"), EphemeralState::Running);
+        assert_eq!(
+            ephemeral_state(&pod, "b"),
+            EphemeralState::Waiting(Some("ErrImagePull".into()))
+        );
+        assert_eq!(ephemeral_state(&pod, "c"), EphemeralState::Terminated);
+        assert_eq!(ephemeral_state(&pod, "d"), EphemeralState::Missing);
+    }
+
+    #[test]
+    fn statefulset_ordinals() {
+        assert_eq!(statefulset_ordinal("db", "db-0"), Some(0));
+        assert_eq!(statefulset_ordinal("db", "db-12"), Some(12));
+        assert_eq!(statefulset_ordinal("db", "db-backup-0"), None);
+        assert_eq!(statefulset_ordinal("db", "dbx-1"), None);
+    }
+
     #[test]
     fn test_is_ready_true() {
         let pod = PodListItem {
-            metadata: PodMetadataName { name: "p".into() },
+            metadata: PodMetadataName {
+                name: "p".into(),
+                namespace: None,
+                annotations: HashMap::new(),
+                creation_timestamp: None,
+                deletion_timestamp: None,
+            },
+            spec: None,
             status: Some(PodStatus {
                 phase: Some("Running".into()),
+                pod_ip: None,
                 conditions: Some(vec![PodCondition {
                     type_name: "Ready".into(),
                     status: "True".into(),
                 }]),
+                container_statuses: Vec::new(),
             }),
         };
         assert!(is_ready(&pod));
@@ -703,12 +2571,39 @@ mod tests {
     #[test]
     fn test_is_ready_false_when_not_running() {
         let pod = PodListItem {
-            metadata: PodMetadataName { name: "p".into() },
+            metadata: PodMetadataName {
+                name: "p".into(),
+                namespace: None,
+                annotations: HashMap::new(),
+                creation_timestamp: None,
+                deletion_timestamp: None,
+            },
+            spec: None,
             status: Some(PodStatus {
                 phase: Some("Pending".into()),
+                pod_ip: None,
                 conditions: None,
+                container_statuses: Vec::new(),
             }),
         };
         assert!(!is_ready(&pod));
     }
+
+    #[test]
+    fn test_pod_os_prefers_spec_os() {
+        let spec: PodSpec = serde_json::from_str(
+            r#"{"containers":[],"os":{"name":"windows"},"nodeSelector":{"kubernetes.io/os":"linux"}}"#,
+        )
+        .unwrap();
+        assert_eq!(pod_os(&spec).as_deref(), Some("windows"));
+
+        let spec: PodSpec = serde_json::from_str(
+            r#"{"containers":[],"nodeSelector":{"kubernetes.io/os":"windows"}}"#,
+        )
+        .unwrap();
+        assert_eq!(pod_os(&spec).as_deref(), Some("windows"));
+
+        let spec: PodSpec = serde_json::from_str(r#"{"containers":[]}"#).unwrap();
+        assert_eq!(pod_os(&spec), None);
+    }
 }
@@ -0,0 +1,112 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Log files larger than this are rotated to `<path>.1` on startup.
+const MAX_LOG_FILE_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Target for the fatal error `main` also prints itself; it only goes to the
+/// log file.
+pub const FATAL_TARGET: &str = "sshpod::fatal";
+
+/// Forwards records to stderr at the requested level and, when configured,
+/// appends every record to a log file regardless of verbosity.
+struct TeeLogger {
+    stderr: env_logger::Logger,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.file.is_some() || self.stderr.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.stderr.matches(record) && record.target() != FATAL_TARGET {
+            self.stderr.log(record);
+        }
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    file,
+                    "{}.{:03} {:<5} {}",
+                    now.as_secs(),
+                    now.subsec_millis(),
+                    record.level(),
+                    record.args()
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+pub fn init_logger(level_arg: &str, log_file: Option<&Path>) {
+    let mut builder = env_logger::Builder::new();
+    builder.format(|buf, record| writeln!(buf, "{}", record.args()));
+    builder.parse_filters(level_arg);
+    let stderr = builder.build();
+
+    let file = log_file.and_then(|path| match open_log_file(path) {
+        Ok(file) => Some(Mutex::new(file)),
+        Err(err) => {
+            eprintln!(
+                "[sshpod] failed to open log file {}: {}",
+                path.display(),
+                err
+            );
+            None
+        }
+    });
+    let max_level = if file.is_some() {
+        LevelFilter::Trace
+    } else {
+        stderr.filter()
+    };
+    if log::set_boxed_logger(Box::new(TeeLogger { stderr, file })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+fn open_log_file(path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::metadata(path).map(|m| m.len()).unwrap_or(0) > MAX_LOG_FILE_BYTES {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        fs::rename(path, rotated)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_log_file_rotates_large_files() {
+        let dir = std::env::temp_dir().join(format!("sshpod-log-{}", std::process::id()));
+        let path = dir.join("sshpod.log");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, vec![b'x'; MAX_LOG_FILE_BYTES as usize + 1]).unwrap();
+
+        let file = open_log_file(&path).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        assert!(dir.join("sshpod.log.1").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,20 +1,53 @@
+mod activation;
+mod audit;
 mod bundle;
+mod clean;
 mod cli;
+mod config;
+mod cp;
+mod crypto;
+mod dns;
+mod docker;
+mod download;
 mod embedded;
+mod error;
+mod events;
+mod exec_channel;
+mod git_remote;
+mod helper_pod;
 mod hostspec;
 mod install;
+mod inventory;
+mod invite;
+mod jump;
 mod keys;
+#[cfg(feature = "kube-api")]
+mod kube_api;
 mod kubectl;
+mod logging;
+mod manifest;
+mod mount;
+mod node;
 mod paths;
 mod port_forward;
 mod proxy;
 mod proxy_io;
 mod remote;
+mod self_test;
+mod signature;
+mod state;
+mod status;
+mod timings;
+mod top;
+mod tunnel;
+mod volume;
 
 #[tokio::main]
 async fn main() {
     if let Err(err) = cli::run().await {
+        // Also recorded in --log-file, which is all there is when ssh hides stderr.
+        log::error!(target: logging::FATAL_TARGET, "{:#}", err);
         eprintln!("error: {:#}", err);
-        std::process::exit(1);
+        std::process::exit(error::exit_code(&err));
     }
 }
@@ -0,0 +1,137 @@
+use crate::cli::ManifestArgs;
+use anyhow::Result;
+use std::fmt::Write;
+
+pub async fn run(args: ManifestArgs) -> Result<()> {
+    print!("{}", render(&args));
+    Ok(())
+}
+
+/// Renders the minimal Role (and RoleBinding when subjects are given) that
+/// sshpod needs in a namespace: reading every workload kind a hostname can
+/// target, exec/port-forward, and creating the helper, node, and volume pods.
+/// `node--` targets additionally need `get` on nodes, which only a
+/// ClusterRole can grant.
+fn render(args: &ManifestArgs) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        r#"apiVersion: rbac.authorization.k8s.io/v1
+kind: Role
+metadata:
+  name: {name}
+  namespace: {namespace}
+rules:
+  - apiGroups: [""]
+    resources: ["pods"]
+    verbs: ["get", "list", "create", "delete"]
+  - apiGroups: [""]
+    resources: ["pods/exec", "pods/portforward"]
+    verbs: ["get", "create"]
+  - apiGroups: [""]
+    resources: ["services"]
+    verbs: ["get", "list"]
+  - apiGroups: ["apps"]
+    resources: ["deployments", "statefulsets", "daemonsets"]
+    verbs: ["get", "list"]
+  - apiGroups: ["batch"]
+    resources: ["jobs", "cronjobs"]
+    verbs: ["get", "list"]
+  - apiGroups: ["argoproj.io"]
+    resources: ["rollouts"]
+    verbs: ["get", "list"]
+  - apiGroups: ["apps.openshift.io"]
+    resources: ["deploymentconfigs"]
+    verbs: ["get", "list"]
+"#,
+        name = args.name,
+        namespace = args.namespace
+    );
+    if !args.no_ephemeral_containers {
+        out.push_str(
+            r#"  - apiGroups: [""]
+    resources: ["pods/ephemeralcontainers"]
+    verbs: ["get", "patch", "update"]
+"#,
+        );
+    }
+
+    let subjects: Vec<(&str, &String)> = args
+        .user
+        .iter()
+        .map(|u| ("User", u))
+        .chain(args.group.iter().map(|g| ("Group", g)))
+        .collect();
+    if subjects.is_empty() {
+        return out;
+    }
+    let _ = write!(
+        out,
+        r#"---
+apiVersion: rbac.authorization.k8s.io/v1
+kind: RoleBinding
+metadata:
+  name: {name}
+  namespace: {namespace}
+roleRef:
+  apiGroup: rbac.authorization.k8s.io
+  kind: Role
+  name: {name}
+subjects:
+"#,
+        name = args.name,
+        namespace = args.namespace
+    );
+    for (kind, name) in subjects {
+        let _ = write!(
+            out,
+            "  - apiGroup: rbac.authorization.k8s.io\n    kind: {}\n    name: {}\n",
+            kind, name
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> ManifestArgs {
+        ManifestArgs {
+            namespace: "team".into(),
+            name: "sshpod".into(),
+            user: Vec::new(),
+            group: Vec::new(),
+            no_ephemeral_containers: false,
+        }
+    }
+
+    #[test]
+    fn role_only_without_subjects() {
+        let out = render(&args());
+        assert!(out.contains("kind: Role\n"));
+        assert!(out.contains("namespace: team"));
+        assert!(!out.contains("RoleBinding"));
+        assert!(out.contains(
+            "resources: [\"pods\"]\n    verbs: [\"get\", \"list\", \"create\", \"delete\"]"
+        ));
+        assert!(out.contains("\"statefulsets\", \"daemonsets\""));
+        assert!(out.contains("\"cronjobs\""));
+        assert!(out.contains("resources: [\"rollouts\"]"));
+        assert!(out.contains("resources: [\"deploymentconfigs\"]"));
+        assert!(out.contains("pods/ephemeralcontainers"));
+    }
+
+    #[test]
+    fn binding_lists_subjects() {
+        let mut args = args();
+        args.user.push("alice@example.com".into());
+        args.group.push("oncall".into());
+        args.no_ephemeral_containers = true;
+        let out = render(&args);
+        assert!(!out.contains("pods/ephemeralcontainers"));
+        assert!(out.contains("kind: RoleBinding"));
+        assert!(out.contains("kind: User\n    name: alice@example.com"));
+        assert!(out.contains("kind: Group\n    name: oncall"));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cli::MountArgs;
+use crate::crypto::CryptoPreset;
+use crate::hostspec;
+use crate::keys;
+use crate::paths;
+use crate::remote::shell_quote;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use std::path::Path;
+use tokio::process::Command;
+
+pub async fn run(args: MountArgs) -> Result<()> {
+    let Some((host, remote_path)) = args.source.split_once(':') else {
+        bail!("source must be <host>:<path>");
+    };
+    let host = hostspec::with_suffix(host);
+    let remote_path = if remote_path.is_empty() {
+        "."
+    } else {
+        remote_path
+    };
+    if !args.mountpoint.is_dir() {
+        bail!(
+            "mountpoint {} is not a directory",
+            args.mountpoint.display()
+        );
+    }
+
+    keys::ensure_key(args.crypto.client_key_name(), args.crypto.key_type()).await?;
+    let target = match &args.user {
+        Some(user) => format!("{}@{}:{}", user, host, remote_path),
+        None => format!("{}:{}", host, remote_path),
+    };
+    let mut child = Command::new("sshfs")
+        .arg(&target)
+        .arg(&args.mountpoint)
+        .arg("-f")
+        .args(
+            ssh_options(args.crypto)?
+                .iter()
+                .flat_map(|opt| ["-o", opt.as_str()]),
+        )
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to run sshfs; is it installed?")?;
+    eprintln!(
+        "[sshpod] mounted {} at {}; press Ctrl-C to unmount",
+        target,
+        args.mountpoint.display()
+    );
+
+    tokio::select! {
+        status = child.wait() => {
+            let status = status.context("failed to wait for sshfs")?;
+            if !status.success() {
+                bail!("sshfs exited with {}", status);
+            }
+            return Ok(());
+        }
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    if let Err(err) = unmount(&args.mountpoint).await {
+        warn!("[sshpod] {:#}; stopping sshfs instead", err);
+        let _ = child.kill().await;
+    }
+    let _ = child.wait().await;
+    Ok(())
+}
+
+/// ssh options sshfs needs to reach a pod without the `sshpod configure` block.
+fn ssh_options(crypto: CryptoPreset) -> Result<Vec<String>> {
+    let exe = std::env::current_exe().context("failed to locate the sshpod binary")?;
+    let mut proxy = format!(
+        "ProxyCommand={} proxy --host %h --user %r --port %p",
+        shell_quote(&exe.to_string_lossy())
+    );
+    if crypto != CryptoPreset::Default {
+        proxy.push_str(&format!(" --crypto {}", crypto.name()));
+    }
+    let identity = paths::home_dir()?
+        .join(".cache/sshpod")
+        .join(crypto.client_key_name());
+    Ok(vec![
+        proxy,
+        format!("IdentityFile={}", identity.display()),
+        "IdentitiesOnly=yes".to_string(),
+        "StrictHostKeyChecking=no".to_string(),
+        "UserKnownHostsFile=/dev/null".to_string(),
+        "reconnect".to_string(),
+    ])
+}
+
+async fn unmount(mountpoint: &Path) -> Result<()> {
+    let commands: &[&[&str]] = if cfg!(target_os = "linux") {
+        &[&["fusermount3", "-u"], &["fusermount", "-u"], &["umount"]]
+    } else {
+        &[&["umount"], &["diskutil", "unmount"]]
+    };
+    for command in commands {
+        let status = Command::new(command[0])
+            .args(&command[1..])
+            .arg(mountpoint)
+            .status()
+            .await;
+        if matches!(status, Ok(s) if s.success()) {
+            return Ok(());
+        }
+    }
+    bail!("failed to unmount {}", mountpoint.display())
+}
@@ -0,0 +1,120 @@
+use crate::helper_pod::HelperPodOptions;
+use crate::kubectl;
+use anyhow::{bail, Result};
+use log::info;
+use serde_json::json;
+use tokio::time::{sleep, Duration};
+
+/// Where the node's root filesystem is mounted inside the node pod.
+pub const HOST_ROOT: &str = "/host";
+
+/// Returns the name of a running privileged pod on `node`, reusing the one a
+/// previous session created. The pod mirrors `kubectl debug node/<node>
+/// --profile=sysadmin` but has a stable name so later sessions find it.
+pub async fn ensure_node_pod(
+    context: Option<&str>,
+    namespace: &str,
+    node: &str,
+    options: &HelperPodOptions,
+) -> Result<String> {
+    let name = pod_name(node);
+    match kubectl::pod_phase(context, namespace, &name)
+        .await?
+        .as_deref()
+    {
+        Some("Running") => {
+            info!("[sshpod] reusing node pod {}", name);
+            return Ok(name);
+        }
+        Some("Pending") => {}
+        Some(phase) => {
+            info!("[sshpod] replacing node pod {} ({})", name, phase);
+            kubectl::delete_pod(context, namespace, &name).await?;
+            wait_gone(context, namespace, &name).await?;
+            create(context, namespace, node, &name, options).await?;
+        }
+        None => create(context, namespace, node, &name, options).await?,
+    }
+    kubectl::wait_pod_ready(context, namespace, &name).await?;
+    Ok(name)
+}
+
+async fn create(
+    context: Option<&str>,
+    namespace: &str,
+    node: &str,
+    name: &str,
+    options: &HelperPodOptions,
+) -> Result<()> {
+    eprintln!("[sshpod] starting privileged pod {} on node {}", name, node);
+    kubectl::create_object(context, namespace, &manifest(name, node, options)).await
+}
+
+async fn wait_gone(context: Option<&str>, namespace: &str, name: &str) -> Result<()> {
+    for _ in 0..60 {
+        if kubectl::pod_phase(context, namespace, name)
+            .await?
+            .is_none()
+        {
+            return Ok(());
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+    bail!("pod {} was not deleted within 60s", name)
+}
+
+fn pod_name(node: &str) -> String {
+    let name: String = format!("sshpod-node-{}", node).chars().take(253).collect();
+    name.trim_end_matches(['-', '.']).to_string()
+}
+
+fn manifest(name: &str, node: &str, options: &HelperPodOptions) -> String {
+    let mut manifest = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {
+            "name": name,
+            "labels": { "app.kubernetes.io/managed-by": "sshpod" }
+        },
+        "spec": {
+            "nodeName": node,
+            "hostPID": true,
+            "hostIPC": true,
+            "hostNetwork": true,
+            "restartPolicy": "Never",
+            "terminationGracePeriodSeconds": 1,
+            "tolerations": [{ "operator": "Exists" }],
+            "containers": [{
+                "name": "node",
+                "image": options.image(),
+                "command": ["sh", "-c", "sleep 86400"],
+                "securityContext": { "privileged": true },
+                "volumeMounts": [{ "name": "host-root", "mountPath": HOST_ROOT }]
+            }],
+            "volumes": [{ "name": "host-root", "hostPath": { "path": "/" } }]
+        }
+    });
+    options.apply(&mut manifest["spec"]);
+    manifest.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_pins_privileged_pod_to_node() {
+        let manifest: serde_json::Value = serde_json::from_str(&manifest(
+            &pod_name("worker-1"),
+            "worker-1",
+            &HelperPodOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(manifest["metadata"]["name"], "sshpod-node-worker-1");
+        let spec = &manifest["spec"];
+        assert_eq!(spec["nodeName"], "worker-1");
+        assert_eq!(spec["hostPID"], true);
+        assert_eq!(spec["containers"][0]["securityContext"]["privileged"], true);
+        assert_eq!(spec["volumes"][0]["hostPath"]["path"], "/");
+    }
+}
@@ -7,3 +7,11 @@ pub fn home_dir() -> Result<PathBuf> {
         .map(PathBuf::from)
         .context("failed to determine home directory; set HOME")
 }
+
+/// `$XDG_STATE_HOME/sshpod`, falling back to `~/.local/state/sshpod`.
+pub fn state_dir() -> Result<PathBuf> {
+    match std::env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("sshpod")),
+        _ => Ok(home_dir()?.join(".local/state/sshpod")),
+    }
+}
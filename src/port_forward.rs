@@ -1,28 +1,43 @@
+use crate::error::SshpodError;
 use anyhow::{anyhow, Context, Result};
 use log::debug;
 use std::process::Stdio;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
 use tokio::task::JoinHandle;
 use tokio::time::{timeout, Duration};
 
 pub struct PortForward {
-    child: tokio::process::Child,
-    stdout_task: Option<JoinHandle<Result<()>>>,
-    stderr_task: Option<JoinHandle<Result<()>>>,
+    /// The kubectl process; `None` when the API backend forwards in-process.
+    child: Option<tokio::process::Child>,
+    tasks: Vec<JoinHandle<Result<()>>>,
 }
 
 impl PortForward {
+    /// Forwards `local_port` (or a free port when `None`) to `remote_port`
+    /// and returns the bound local port.
     pub async fn start(
         context: Option<&str>,
         namespace: &str,
         pod: &str,
+        local_port: Option<u16>,
         remote_port: u16,
     ) -> Result<(PortForward, u16)> {
-        let mut cmd = Command::new("kubectl");
-        if let Some(ctx) = context {
-            cmd.arg("--context").arg(ctx);
+        #[cfg(feature = "kube-api")]
+        if crate::kubectl::use_api() {
+            let (task, port) =
+                crate::kube_api::port_forward(context, namespace, pod, local_port, remote_port)
+                    .await?;
+            debug!("[port-forward] listening on localhost:{}", port);
+            return Ok((
+                PortForward {
+                    child: None,
+                    tasks: vec![task],
+                },
+                port,
+            ));
         }
+        let local = local_port.map(|p| p.to_string()).unwrap_or_default();
+        let mut cmd = crate::kubectl::kubectl_base(context);
         cmd.args([
             "port-forward",
             "--address",
@@ -30,10 +45,15 @@ impl PortForward {
             "-n",
             namespace,
             &format!("pod/{}", pod),
-            &format!(":{}", remote_port),
+            &format!("{}:{}", local, remote_port),
         ]);
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        // Own process group so stop() also reaches kubectl's children (exec
+        // credential plugins) and a terminal Ctrl-C is handled by sshpod.
+        #[cfg(unix)]
+        cmd.process_group(0);
 
         let mut child = cmd
             .spawn()
@@ -73,7 +93,10 @@ impl PortForward {
                     }
                     status = child.wait() => {
                         let status = status.context("failed to wait for port-forward process")?;
-                        break Err(anyhow!("kubectl port-forward exited early with status {}", status));
+                        break Err(SshpodError::PortForwardDropped {
+                            pod: pod.to_string(),
+                            reason: format!("kubectl port-forward exited early with status {}", status),
+                        }.into());
                     }
                 }
             }
@@ -96,24 +119,34 @@ impl PortForward {
 
         Ok((
             PortForward {
-                child,
-                stdout_task: Some(stdout_task),
-                stderr_task: Some(stderr_task),
+                child: Some(child),
+                tasks: vec![stdout_task, stderr_task],
             },
             port,
         ))
     }
 
     pub async fn stop(&mut self) -> Result<()> {
-        if self.child.id().is_some() {
-            let _ = self.child.start_kill();
+        let Some(child) = self.child.as_mut() else {
+            for handle in self.tasks.drain(..) {
+                handle.abort();
+            }
+            return Ok(());
+        };
+        #[cfg(unix)]
+        if let Some(pid) = child.id() {
+            // SAFETY: signals the process group created for this child in start().
+            unsafe {
+                libc::kill(-(pid as i32), libc::SIGTERM);
+            }
+            let _ = timeout(Duration::from_secs(2), child.wait()).await;
         }
-        let _ = self.child.wait().await;
-
-        if let Some(handle) = self.stdout_task.take() {
-            let _ = handle.await;
+        if child.id().is_some() {
+            let _ = child.start_kill();
         }
-        if let Some(handle) = self.stderr_task.take() {
+        let _ = child.wait().await;
+
+        for handle in self.tasks.drain(..) {
             let _ = handle.await;
         }
         Ok(())
@@ -1,53 +1,396 @@
 use crate::bundle;
 use crate::cli::ProxyArgs;
-use crate::hostspec::{self, Target};
+use crate::config::{Config, HostSettings};
+use crate::download::{self, BundleOrigin};
+use crate::error::{self, SshpodError};
+use crate::events;
+use crate::exec_channel;
+use crate::helper_pod;
+use crate::hostspec::{self, Target, TargetKind};
+use crate::jump::JumpProxy;
 use crate::keys;
-use crate::kubectl::{self, RemoteTarget};
+use crate::kubectl::{self, EphemeralState, PodInfo, RemoteTarget};
+use crate::logging;
+use crate::node;
 use crate::port_forward::PortForward;
 use crate::proxy_io;
-use crate::remote;
+use crate::remote::{self, SshdOptions};
+use crate::state::{self, InstallRecord, SessionRecord, State, StateStore};
+use crate::timings::Timings;
+use crate::tunnel;
 use anyhow::{bail, Context, Result};
-use log::info;
-use std::io::Write;
-use tokio::net::TcpStream;
+use log::{debug, info, warn};
+use serde_json::json;
+use tokio::time::{sleep, Duration, Instant};
 
-fn init_logger(level_arg: &str) {
-    let mut builder = env_logger::Builder::new();
-    builder.format(|buf, record| writeln!(buf, "{}", record.args()));
-    builder.parse_filters(level_arg);
-    let _ = builder.try_init();
+/// Connects through a single-use sshd on the pod IP and closes its listener
+/// as soon as the connection is made (or has failed).
+async fn connect_direct(
+    target: &RemoteTarget,
+    base: &str,
+    ip: &str,
+) -> Result<(tokio::net::TcpStream, u16)> {
+    let listener = remote::start_direct_listener(target, base, ip).await?;
+    let stream = proxy_io::connect_direct(ip, listener.port).await;
+    if let Err(err) = remote::stop_direct_listener(target, base, &listener).await {
+        warn!("[sshpod] {:#}; it closes by itself within 30s", err);
+    }
+    Ok((stream?, listener.port))
+}
+
+/// Best-effort state update; a broken state file must never block a connection.
+async fn update_state(f: impl FnOnce(&mut State)) {
+    let result = match StateStore::open() {
+        Ok(store) => store.update(f).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        warn!("[sshpod] failed to update local state: {:#}", err);
+    }
+}
+
+async fn load_state() -> Result<State> {
+    StateStore::open()?.load().await
+}
+
+async fn load_lookups() -> Result<State> {
+    StateStore::lookup_cache()?.load().await
+}
+
+/// Best-effort lookup cache write; a miss next time only costs a kubectl call.
+async fn record_lookup(key: &str, value: &str, ttl: Duration) {
+    let result = match StateStore::lookup_cache() {
+        Ok(store) => {
+            store
+                .update(|state| state.record_lookup(key, value, ttl, state::now_secs()))
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        debug!("[sshpod] failed to update the lookup cache: {:#}", err);
+    }
+}
+
+/// How long a workload hostname keeps resolving to the pod picked first, so
+/// `scp` followed by `ssh` lands on the same replica.
+const STICKY_TTL: Duration = Duration::from_secs(300);
+
+/// Lookup cache key for targets that pick one of several pods. `context` is
+/// the effective context, so clusters reached through the current context
+/// do not share entries.
+fn sticky_key(host: &hostspec::HostSpec, context: &str, namespace: &str) -> Option<String> {
+    let target = match &host.target {
+        Target::Deployment(name) if host.index.is_none() => format!("deployment/{}", name),
+        Target::Job(name) => format!("job/{}", name),
+        Target::StatefulSet(name, None) => format!("statefulset/{}", name),
+        Target::DaemonSet(name) => {
+            format!("daemonset/{}@{}", name, host.node.as_deref().unwrap_or(""))
+        }
+        Target::CronJob(name) => format!("cronjob/{}", name),
+        Target::Service(name) => format!("service/{}", name),
+        Target::Rollout(name) => format!("rollout/{}", name),
+        Target::DeploymentConfig(name) => format!("deploymentconfig/{}", name),
+        _ => return None,
+    };
+    let annotation = host
+        .annotation
+        .as_ref()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .unwrap_or_default();
+    Some(format!(
+        "pod:{}/{}/{}/{:?}/{}",
+        context,
+        namespace,
+        target,
+        host.select.unwrap_or_default(),
+        annotation
+    ))
+}
+
+/// How long a healthy pod's metadata is reused without asking the API
+/// server, so back-to-back `scp` runs skip the lookup.
+const POD_INFO_TTL: Duration = Duration::from_secs(30);
+
+fn pod_info_key(context: &str, namespace: &str, pod: &str) -> String {
+    format!("podinfo:{}/{}/{}", context, namespace, pod)
+}
+
+/// Pod metadata from the lookup cache when fresh, otherwise from the API
+/// server. Only running pods with no waiting containers are cached, and only
+/// when the effective context (`scope`) is known.
+async fn pod_info(
+    context: Option<&str>,
+    scope: Option<&str>,
+    namespace: &str,
+    pod: &str,
+) -> Result<PodInfo> {
+    let key = scope.map(|scope| pod_info_key(scope, namespace, pod));
+    if let Some(key) = &key {
+        let cached = load_lookups().await.ok().and_then(|state| {
+            let value = state.lookup(key, POD_INFO_TTL, state::now_secs())?;
+            serde_json::from_str::<PodInfo>(value).ok()
+        });
+        if let Some(info) = cached {
+            debug!("[sshpod] using cached metadata for pod {}", pod);
+            return Ok(info);
+        }
+    }
+    let info = kubectl::get_pod_info(context, namespace, pod).await?;
+    if let Some(key) = &key {
+        if info.phase.as_deref() == Some("Running") && info.waiting.is_empty() {
+            if let Ok(value) = serde_json::to_string(&info) {
+                record_lookup(key, &value, POD_INFO_TTL).await;
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// Pod cached under `key` if it is recent and the pod is still running.
+async fn cached_pod(
+    context: Option<&str>,
+    scope: &str,
+    namespace: &str,
+    key: &str,
+) -> Option<String> {
+    let state = load_lookups().await.ok()?;
+    let now = state::now_secs();
+    let pod = state.lookup(key, STICKY_TTL, now)?.to_string();
+    // Fresh cached metadata already says the pod was running.
+    if state
+        .lookup(&pod_info_key(scope, namespace, &pod), POD_INFO_TTL, now)
+        .is_some()
+    {
+        return Some(pod);
+    }
+    match kubectl::pod_phase(context, namespace, &pod).await {
+        Ok(Some(phase)) if phase == "Running" => Some(pod),
+        _ => None,
+    }
+}
+
+/// Replaces a context alias from the config with the context it names.
+fn resolve_context_alias(host: &mut hostspec::HostSpec, config: &Config) {
+    let Some(alias) = host.context.as_deref() else {
+        return;
+    };
+    if let Some(context) = config.context_alias(alias) {
+        info!("[sshpod] context alias {} -> {}", alias, context);
+        host.context = Some(context.to_string());
+    }
+}
+
+/// Fills hostspec pieces the hostname left out from matching config sections.
+fn apply_host_settings(host: &mut hostspec::HostSpec, settings: &HostSettings) {
+    if host.container.is_none() {
+        host.container = settings.container.clone();
+    }
+    if host.namespace.is_none() {
+        host.namespace = settings.namespace.clone();
+    }
+    if host.context.is_none() {
+        host.context = settings.context.clone();
+    }
+    if host.select.is_none() {
+        host.select = settings.select;
+    }
+    // A rolling update keeps the old ReplicaSet's pods ready for a while; the
+    // newest pod belongs to the latest rollout.
+    if host.select.is_none() && matches!(host.target, Target::Deployment(_)) {
+        host.select = Some(hostspec::SelectStrategy::Newest);
+    }
+}
+
+/// Resolves when sshpod is asked to stop, naming the signal.
+pub async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let (Ok(mut term), Ok(mut hup)) = (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::hangup()),
+        ) else {
+            let _ = tokio::signal::ctrl_c().await;
+            return "SIGINT";
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = term.recv() => "SIGTERM",
+            _ = hup.recv() => "SIGHUP",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
+
+/// "Where am I" text shown by sshd before each login.
+fn session_banner(target: &RemoteTarget, pod_info: &kubectl::PodInfo) -> String {
+    let mut lines = vec![
+        format!(
+            "sshpod: pod {} (namespace {}, context {})",
+            target.pod,
+            target.namespace,
+            target.context.as_deref().unwrap_or("default")
+        ),
+        format!("container: {}", target.container),
+    ];
+    if let Some(image) = pod_info.images.get(&target.container) {
+        lines.push(format!("image: {}", image));
+    }
+    if let Some(node) = &pod_info.node {
+        lines.push(format!("node: {}", node));
+    }
+    lines.join("\n")
 }
 
 async fn resolve_remote_target(
     host: &hostspec::HostSpec,
+    settings: &HostSettings,
+    timings: &mut Timings,
+    wait_running: Option<Duration>,
+    all_namespaces: bool,
+    debug_container: bool,
 ) -> Result<(RemoteTarget, kubectl::PodInfo)> {
-    if let Some(ctx) = &host.context {
-        kubectl::ensure_context_exists(ctx).await?;
-    }
-    let namespace = if let Some(ns) = host.namespace.clone() {
-        ns
-    } else if let Some(ctx) = &host.context {
-        kubectl::get_context_namespace(ctx)
-            .await?
-            .unwrap_or_default()
-    } else {
-        kubectl::get_context_namespace("default")
-            .await?
-            .unwrap_or_default()
+    // Only search elsewhere when nothing chose the namespace.
+    let search_all = all_namespaces && host.namespace.is_none();
+    let context_check = async {
+        match &host.context {
+            Some(ctx) => kubectl::ensure_context_exists(ctx).await,
+            None => Ok(()),
+        }
     };
+    let namespace_lookup = async {
+        match host.namespace.clone() {
+            Some(ns) => Ok(ns),
+            None => kubectl::get_context_namespace(host.context.as_deref())
+                .await
+                .map(|ns| ns.unwrap_or_else(|| "default".to_string())),
+        }
+    };
+    // Cached lookups are keyed by the context actually used; without one
+    // in the hostname that is whatever kubectl currently points at.
+    let scope_lookup = async {
+        match &host.context {
+            Some(ctx) => Some(ctx.clone()),
+            None => kubectl::current_context().await.ok().flatten(),
+        }
+    };
+    let (checked, namespace, scope) = tokio::join!(context_check, namespace_lookup, scope_lookup);
+    // A missing context explains a failed namespace lookup, so report it first.
+    checked?;
+    let namespace = namespace?;
+    let scope = scope.as_deref();
+    timings.lap("context check");
     let ns_str = namespace.as_str();
 
-    let pod_name = match &host.target {
-        Target::Pod(pod) => pod.clone(),
-        Target::Deployment(dep) => {
-            kubectl::choose_pod_for_deployment(host.context.as_deref(), ns_str, dep)
+    if host.node.is_some() && !matches!(host.target, Target::DaemonSet(_)) {
+        bail!("node-- only applies to daemonset-- targets");
+    }
+    if host.index.is_some() && !matches!(host.target, Target::Deployment(_)) {
+        bail!("a replica index only applies to deployment-- targets (use statefulset--<name>--<ordinal> for StatefulSets)");
+    }
+    if host.annotation.is_some()
+        && !matches!(
+            host.target,
+            Target::Deployment(_)
+                | Target::DaemonSet(_)
+                | Target::Service(_)
+                | Target::Rollout(_)
+                | Target::DeploymentConfig(_)
+        )
+    {
+        bail!("annotation-- only applies to deployment--, daemonset--, service--, rollout-- and deploymentconfig-- targets");
+    }
+    let annotation = host
+        .annotation
+        .as_ref()
+        .map(|(key, value)| (key.as_str(), value.as_str()));
+    let sticky = scope.and_then(|scope| sticky_key(host, scope, ns_str));
+    let cached = match (&sticky, scope) {
+        (Some(key), Some(scope)) => cached_pod(host.context.as_deref(), scope, ns_str, key).await,
+        _ => None,
+    };
+    let mut pod_name = match (&host.target, cached) {
+        (_, Some(pod)) => {
+            info!(
+                "[sshpod] reusing pod {} selected earlier for this host",
+                pod
+            );
+            pod
+        }
+        (Target::Pod(pod), None) if is_glob(pod) => {
+            match kubectl::match_pod_name(host.context.as_deref(), ns_str, pod).await? {
+                Some(matched) => matched,
+                None if search_all => {
+                    return resolve_in_any_namespace(
+                        host,
+                        pod,
+                        settings,
+                        timings,
+                        wait_running,
+                        debug_container,
+                    )
+                    .await
+                }
+                None => bail!("no pod matches `{}` in namespace {}", pod, ns_str),
+            }
+        }
+        (Target::Pod(pod), None) => pod.clone(),
+        (
+            Target::Deployment(name)
+            | Target::Job(name)
+            | Target::Service(name)
+            | Target::Rollout(name)
+            | Target::DeploymentConfig(name),
+            None,
+        ) => kubectl::choose_pod_for(
+            host.context.as_deref(),
+            ns_str,
+            host.target.kind(),
+            name,
+            host.index,
+            host.select.unwrap_or_default(),
+            annotation,
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "failed to select pod from {} `{}`",
+                host.target.kind(),
+                name
+            )
+        })?,
+        (Target::StatefulSet(sts, ordinal), None) => {
+            kubectl::choose_pod_for_statefulset(host.context.as_deref(), ns_str, sts, *ordinal)
                 .await
-                .with_context(|| format!("failed to select pod from deployment `{}`", dep))?
+                .with_context(|| format!("failed to select pod from statefulset `{}`", sts))?
         }
-        Target::Job(job) => kubectl::choose_pod_for_job(host.context.as_deref(), ns_str, job)
-            .await
-            .with_context(|| format!("failed to select pod from job `{}`", job))?,
+        (Target::Node(node), None) => {
+            node::ensure_node_pod(host.context.as_deref(), ns_str, node, &settings.helper_pod)
+                .await
+                .with_context(|| format!("failed to start a pod on node `{}`", node))?
+        }
+        (Target::CronJob(cronjob), None) => {
+            kubectl::choose_pod_for_cronjob(host.context.as_deref(), ns_str, cronjob)
+                .await
+                .with_context(|| format!("failed to select pod from cronjob `{}`", cronjob))?
+        }
+        (Target::DaemonSet(ds), None) => kubectl::choose_pod_for_daemonset(
+            host.context.as_deref(),
+            ns_str,
+            ds,
+            host.node.as_deref(),
+            host.select.unwrap_or_default(),
+            annotation,
+        )
+        .await
+        .with_context(|| format!("failed to select pod from daemonset `{}`", ds))?,
     };
+    timings.lap("pod selection");
     info!(
         "[sshpod] resolved pod: {} (namespace={}, context={})",
         pod_name,
@@ -55,9 +398,51 @@ async fn resolve_remote_target(
         host.context.as_deref().unwrap_or("default")
     );
 
-    let pod_info = kubectl::get_pod_info(host.context.as_deref(), ns_str, &pod_name)
-        .await
-        .with_context(|| format!("failed to inspect pod {}.{}", pod_name, ns_str))?;
+    let mut pod_info = match pod_info(host.context.as_deref(), scope, ns_str, &pod_name).await {
+        Ok(info) => info,
+        // Not an exact pod name: try it as a prefix (random suffixes are hard to type).
+        Err(err) if matches!(&host.target, Target::Pod(p) if !is_glob(p)) => {
+            let prefix = format!("{}*", pod_name);
+            let matched =
+                match kubectl::match_pod_name(host.context.as_deref(), ns_str, &prefix).await? {
+                    Some(matched) => matched,
+                    None if search_all => {
+                        return resolve_in_any_namespace(
+                            host,
+                            &pod_name,
+                            settings,
+                            timings,
+                            wait_running,
+                            debug_container,
+                        )
+                        .await
+                    }
+                    None => {
+                        return Err(
+                            err.context(format!("failed to inspect pod {}.{}", pod_name, ns_str))
+                        )
+                    }
+                };
+            info!("[sshpod] {} matched pod {} by prefix", pod_name, matched);
+            pod_name = matched;
+            pod_info(host.context.as_deref(), scope, ns_str, &pod_name)
+                .await
+                .with_context(|| format!("failed to inspect pod {}.{}", pod_name, ns_str))?
+        }
+        Err(err) => {
+            return Err(err.context(format!("failed to inspect pod {}.{}", pod_name, ns_str)))
+        }
+    };
+    timings.lap("pod get");
+    if let Some(key) = &sticky {
+        record_lookup(key, &pod_name, STICKY_TTL).await;
+    }
+    if pod_info.os.as_deref() == Some("windows") {
+        bail!(
+            "pod {} is scheduled on a Windows node; sshpod only supports Linux containers",
+            pod_name
+        );
+    }
 
     let container = match host.container.as_ref() {
         Some(c) => {
@@ -68,14 +453,83 @@ async fn resolve_remote_target(
             }
         }
         None => {
+            let candidates: Vec<&String> = pod_info
+                .containers
+                .iter()
+                .filter(|name| !settings.skip_containers.contains(name))
+                .collect();
             if pod_info.containers.len() == 1 {
                 pod_info.containers[0].clone()
+            } else if let [only] = candidates.as_slice() {
+                info!("[sshpod] skipping sidecars; using container {}", only);
+                (*only).clone()
+            } else if let Some(default) = pod_info
+                .default_container
+                .as_ref()
+                .filter(|name| pod_info.containers.contains(name))
+            {
+                info!(
+                    "[sshpod] using default container {} from the pod annotation",
+                    default
+                );
+                default.clone()
+            } else if kubectl::client() == kubectl::ClientKind::Oc {
+                // Same fallback as `oc rsh`.
+                info!(
+                    "[sshpod] using the first container {} as oc rsh does",
+                    pod_info.containers[0]
+                );
+                pod_info.containers[0].clone()
             } else {
                 bail!("This Pod has multiple containers. Use container--<container>.pod--<pod>.namespace--<namespace>[.context--<context>].sshpod to specify the target container.");
             }
         }
     };
     info!("[sshpod] resolved container: {}", container);
+    events::emit(
+        "target",
+        json!({
+            "context": host.context,
+            "namespace": ns_str,
+            "pod": pod_name,
+            "container": container,
+        }),
+    );
+
+    // A debug container runs beside the crashing one, so it can still connect.
+    if !debug_container
+        && pod_info.waiting.get(&container).map(String::as_str) == Some("CrashLoopBackOff")
+    {
+        let Some(wait) = wait_running else {
+            bail!(
+                "container `{}` in pod {} is in CrashLoopBackOff. Retry with --wait-running <duration> to connect during its next Running window, or with --debug-container to connect through an ephemeral container beside it.",
+                container,
+                pod_name
+            );
+        };
+        info!(
+            "[sshpod] container {} is in CrashLoopBackOff; waiting up to {}s for it to run",
+            container,
+            wait.as_secs()
+        );
+        let deadline = Instant::now() + wait;
+        while pod_info.waiting.contains_key(&container) {
+            if Instant::now() > deadline {
+                return Err(SshpodError::PodNotReady {
+                    pod: pod_name,
+                    reason: format!(
+                        "container `{}` did not start running within {}s",
+                        container,
+                        wait.as_secs()
+                    ),
+                }
+                .into());
+            }
+            sleep(Duration::from_secs(1)).await;
+            pod_info = kubectl::get_pod_info(host.context.as_deref(), ns_str, &pod_name).await?;
+        }
+        timings.lap("crash-loop wait");
+    }
 
     let target = RemoteTarget {
         context: host.context.clone(),
@@ -87,64 +541,652 @@ async fn resolve_remote_target(
     Ok((target, pod_info))
 }
 
-pub async fn run(args: ProxyArgs) -> Result<()> {
-    init_logger(&args.log_level);
-    let host = hostspec::parse(&args.host).context("failed to parse hostspec")?;
+/// Looks for `pod` (a name, prefix or glob) in every namespace and resolves
+/// the target there when exactly one namespace has it.
+async fn resolve_in_any_namespace(
+    host: &hostspec::HostSpec,
+    pod: &str,
+    settings: &HostSettings,
+    timings: &mut Timings,
+    wait_running: Option<Duration>,
+    debug_container: bool,
+) -> Result<(RemoteTarget, kubectl::PodInfo)> {
+    let pattern = if is_glob(pod) {
+        pod.to_string()
+    } else {
+        format!("{}*", pod)
+    };
+    let mut found = kubectl::find_pods_all_namespaces(host.context.as_deref(), &pattern).await?;
+    if found.iter().any(|(_, name)| name == pod) {
+        found.retain(|(_, name)| name == pod);
+    }
+    timings.lap("all-namespaces search");
+    match found.as_slice() {
+        [] => bail!("no pod matches `{}` in any namespace", pod),
+        [(namespace, name)] => {
+            info!("[sshpod] found pod {} in namespace {}", name, namespace);
+            let mut host = host.clone();
+            host.namespace = Some(namespace.clone());
+            host.target = Target::Pod(name.clone());
+            Box::pin(resolve_remote_target(
+                &host,
+                settings,
+                timings,
+                wait_running,
+                false,
+                debug_container,
+            ))
+            .await
+        }
+        _ => bail!(
+            "`{}` matches pods in several namespaces: {}; add namespace--<namespace>",
+            pod,
+            found
+                .iter()
+                .map(|(namespace, name)| format!("{}/{}", namespace, name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Resolves a hostname to its target container for commands other than
+/// `proxy`, applying the same config defaults.
+pub async fn resolve_host(host_arg: &str) -> Result<(RemoteTarget, kubectl::PodInfo)> {
+    let config = Config::load()?;
+    let expanded = config.expand_alias(host_arg);
+    let host_arg = expanded.as_deref().unwrap_or(host_arg);
+    let settings = config.host_settings(host_arg)?;
+    let mut host = hostspec::parse(host_arg).context("failed to parse hostspec")?;
+    apply_host_settings(&mut host, &settings);
+    resolve_context_alias(&mut host, &config);
+    resolve_remote_target(
+        &host,
+        &settings,
+        &mut Timings::new(false),
+        None,
+        settings.all_namespaces,
+        settings.debug_container,
+    )
+    .await
+}
+
+/// Parses `--host`, or with targeting flags and no suffix takes it as a plain
+/// name (pods named `a.b` need no escaping then). Flags override the hostname.
+pub(crate) fn host_from_args(args: &ProxyArgs) -> Result<hostspec::HostSpec> {
+    let flags = args.target_kind.is_some()
+        || args.namespace.is_some()
+        || args.context.is_some()
+        || args.container.is_some();
+    let mut host = match (hostspec::strip_suffix(&args.host), args.target_kind) {
+        (Some(name), Some(kind)) => hostspec::from_name(kind, name),
+        (None, _) if flags => {
+            let name = args.host.trim_end_matches('.');
+            if name.is_empty() {
+                bail!("--host must name the target");
+            }
+            hostspec::from_name(args.target_kind.unwrap_or(TargetKind::Pod), name)
+        }
+        _ => hostspec::parse(&args.host).context("failed to parse hostspec")?,
+    };
+    if args.namespace.is_some() {
+        host.namespace = args.namespace.clone();
+    }
+    if args.context.is_some() {
+        host.context = args.context.clone();
+    }
+    if args.container.is_some() {
+        host.container = args.container.clone();
+    }
+    Ok(host)
+}
+
+pub async fn run(mut args: ProxyArgs) -> Result<()> {
+    let level = match args.verbose {
+        0 => args.log_level.as_str(),
+        1 => "debug",
+        _ => "trace",
+    };
+    // Claim the inherited descriptor before the log file could reuse its number.
+    if let Some(fd) = args.events_fd {
+        events::init_fd(fd)?;
+    } else if let Some(path) = &args.events_socket {
+        events::init_socket(path)?;
+    }
+    logging::init_logger(level, args.log_file.as_deref());
+    events::emit("started", json!({"host": args.host}));
+    if let Some(path) = &args.kubeconfig {
+        kubectl::set_kubeconfig(path.clone());
+    }
+    if args.exec_channel {
+        exec_channel::enable();
+    }
+    if args.offline {
+        info!("[sshpod] offline mode: only the Kubernetes API will be contacted");
+        if args.bundle_source == BundleOrigin::Url {
+            bail!("--bundle-source url needs network access; drop --offline");
+        }
+    }
+    download::configure(args.bundle_source, args.bundle_url.clone());
+    if let Some(path) = &args.bundle_path {
+        bundle::set_bundle_path(path.clone());
+    }
+    let config = Config::load()?;
+    if let Some(expanded) = config.expand_alias(&args.host) {
+        info!("[sshpod] host alias {} -> {}", args.host, expanded);
+        args.host = expanded;
+    }
+    let settings = config.host_settings(&args.host)?;
+    let mut host = host_from_args(&args)?;
+    if host.select.is_none() {
+        host.select = args.select;
+    }
+    apply_host_settings(&mut host, &settings);
+    resolve_context_alias(&mut host, &config);
     let login_user = args
         .user
+        .clone()
         .filter(|u| !u.is_empty())
+        .or_else(|| settings.user.clone())
         .unwrap_or_else(whoami::username);
 
-    let (target, pod_info) = resolve_remote_target(&host).await?;
+    let mut jump = match args.jump.as_deref().or(settings.jump.as_deref()) {
+        Some(jump_host) => Some(JumpProxy::start(jump_host).await?),
+        None => None,
+    };
+    let result = connect(args, settings, host, login_user).await;
+    match &result {
+        Ok(()) => events::emit("finished", json!({})),
+        Err(err) => {
+            let mut event = json!({"message": format!("{:#}", err)});
+            if let Some(typed) = error::find(err) {
+                event["error"] = json!(typed);
+                event["retryable"] = json!(typed.is_retryable());
+            }
+            events::emit("error", event)
+        }
+    }
+    if let Some(jump) = jump.as_mut() {
+        jump.stop().await;
+    }
+    result
+}
+
+async fn connect(
+    args: ProxyArgs,
+    settings: HostSettings,
+    host: hostspec::HostSpec,
+    login_user: String,
+) -> Result<()> {
+    let mut timings = Timings::new(args.timings);
+    let debug_container = args.debug_container || settings.debug_container;
+    let (target, pod_info) = resolve_remote_target(
+        &host,
+        &settings,
+        &mut timings,
+        args.wait_running,
+        args.all_namespaces || settings.all_namespaces,
+        debug_container,
+    )
+    .await?;
+    if let Some(endpoint) = &host.tunnel {
+        return tunnel::run(&target, endpoint).await;
+    }
+
+    if matches!(pod_info.phase.as_deref(), Some("Succeeded" | "Failed")) {
+        if !args.debug_copy {
+            bail!(
+                "pod {} has completed (phase {}); use --debug-copy to connect to a copy of it that mounts the same volumes",
+                target.pod,
+                pod_info.phase.as_deref().unwrap_or_default()
+            );
+        }
+        // Ephemeral containers never start in terminated pods, so run a
+        // sleeping copy of the pod (same volumes) and connect to that instead.
+        let copy_name = debug_copy_name(&target.pod);
+        info!(
+            "[sshpod] pod {} has completed; creating copy {}",
+            target.pod, copy_name
+        );
+        kubectl::create_debug_copy(&target, &copy_name).await?;
+        let copy_target = RemoteTarget {
+            pod: copy_name.clone(),
+            ..target.clone()
+        };
+        let result = async {
+            let copy_info =
+                kubectl::get_pod_info(target.context.as_deref(), &target.namespace, &copy_name)
+                    .await?;
+            timings.lap("debug copy");
+            session(
+                args,
+                settings,
+                copy_target,
+                copy_info,
+                login_user,
+                None,
+                timings,
+            )
+            .await
+        }
+        .await;
+        if let Err(err) =
+            kubectl::delete_pod(target.context.as_deref(), &target.namespace, &copy_name).await
+        {
+            warn!(
+                "[sshpod] failed to delete debug copy {}: {:#}",
+                copy_name, err
+            );
+        }
+        return result;
+    }
+
+    let target = if debug_container {
+        let image = args
+            .debug_image
+            .as_deref()
+            .or(settings.debug_image.as_deref())
+            .unwrap_or(helper_pod::DEFAULT_IMAGE);
+        let target = attach_debug_container(target, image).await?;
+        timings.lap("debug container");
+        target
+    } else {
+        target
+    };
+
+    let chroot = (matches!(host.target, Target::Node(_))
+        && (args.chroot_host || settings.chroot_host))
+        .then_some(node::HOST_ROOT);
+    session(
+        args, settings, target, pod_info, login_user, chroot, timings,
+    )
+    .await
+}
+
+/// Starts (or reuses) an ephemeral container beside the target container and
+/// retargets the session at it, for distroless or shell-less images.
+async fn attach_debug_container(target: RemoteTarget, image: &str) -> Result<RemoteTarget> {
+    let context = target.context.as_deref();
+    let mut name = debug_container_name(&target.container);
+    let state =
+        kubectl::ephemeral_container_state(context, &target.namespace, &target.pod, &name).await?;
+    match state {
+        EphemeralState::Running | EphemeralState::Waiting(_) => {
+            info!("[sshpod] reusing ephemeral container {}", name);
+        }
+        EphemeralState::Missing | EphemeralState::Terminated => {
+            // Ephemeral containers can be neither restarted nor removed.
+            if state == EphemeralState::Terminated {
+                name = format!("{}-{}", name, std::process::id());
+            }
+            eprintln!(
+                "[sshpod] adding ephemeral container {} ({}) to pod {}",
+                name, image, target.pod
+            );
+            kubectl::add_debug_container(&target, &name, image).await?;
+        }
+    }
+    kubectl::wait_ephemeral_running(context, &target.namespace, &target.pod, &name).await?;
+    Ok(RemoteTarget {
+        container: name,
+        ..target
+    })
+}
+
+fn debug_container_name(container: &str) -> String {
+    let name: String = format!("sshpod-{}", container).chars().take(50).collect();
+    name.trim_end_matches('-').to_string()
+}
+
+fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+fn debug_copy_name(pod: &str) -> String {
+    let prefix: String = pod.chars().take(40).collect();
+    format!(
+        "{}-sshpod-{}",
+        prefix.trim_end_matches('-'),
+        std::process::id()
+    )
+}
+
+async fn session(
+    args: ProxyArgs,
+    settings: HostSettings,
+    target: RemoteTarget,
+    pod_info: kubectl::PodInfo,
+    login_user: String,
+    chroot: Option<&str>,
+    mut timings: Timings,
+) -> Result<()> {
     let ns_str = target.namespace.as_str();
     let pod_name = target.pod.clone();
     let container = target.container.clone();
-    let base = format!("/tmp/sshpod/{}/{}", pod_info.uid, container);
+    let base = remote::base_dir(&pod_info.uid, &container);
 
-    let local_key = keys::ensure_key("id_ed25519")
+    let crypto = args.crypto;
+    let local_key = keys::ensure_key(crypto.client_key_name(), crypto.key_type())
         .await
-        .context("failed to ensure ~/.cache/sshpod/id_ed25519 exists")?;
-    let host_keys = keys::ensure_key("ssh_host_ed25519_key")
+        .with_context(|| {
+            format!(
+                "failed to ensure ~/.cache/sshpod/{} exists",
+                crypto.client_key_name()
+            )
+        })?;
+    let authorized_key = match &args.authorize_key {
+        Some(path) => tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?,
+        None => local_key.public.clone(),
+    };
+    let host_keys = keys::ensure_key(crypto.host_key_name(), crypto.key_type())
         .await
         .context("failed to create host keys")?;
+    timings.lap("local keys");
 
-    remote::try_acquire_lock(&target, &base).await;
-    remote::assert_login_user_allowed(&target, &login_user).await?;
+    // Bursts of connections (parallel rsync, IDEs) queue here so only the
+    // first one uploads the bundle; the rest reuse its fresh install.
+    let setup_lock = match StateStore::open() {
+        Ok(store) => match store
+            .setup_lock(&format!("{}-{}", pod_info.uid, container))
+            .await
+        {
+            Ok(lock) => Some(lock),
+            Err(err) => {
+                warn!("[sshpod] continuing without the setup lock: {:#}", err);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    let reuse_install = setup_lock.as_ref().is_some_and(|lock| lock.waited)
+        && load_state()
+            .await
+            .map(|state| {
+                state.installs.iter().any(|r| {
+                    r.pod_uid == pod_info.uid
+                        && r.container == container
+                        && r.version == bundle::BUNDLE_VERSION
+                        && state::now_secs().saturating_sub(r.updated_at) < 300
+                }) && state
+                    .host_keys
+                    .get(crypto.host_key_name())
+                    .map(String::as_str)
+                    == Some(host_keys.public.trim())
+            })
+            .unwrap_or(false);
+    timings.lap("setup queue");
 
-    let arch = bundle::detect_remote_arch(&target)
-        .await
-        .context("failed to detect remote arch")?;
-    info!("[sshpod] remote architecture: {}", arch);
-    bundle::ensure_bundle(&target, &base, &arch).await?;
-    info!("[sshpod] sshd bundle ready for pod {}", pod_name);
-    remote::install_host_keys(&target, &base, &host_keys).await?;
+    let with_rsync = args.with_rsync || settings.rsync;
+    let sftp_umask = args
+        .sftp_umask
+        .clone()
+        .or_else(|| settings.sftp_umask.clone());
+    let sftp_server = args.sftp_server || settings.sftp_server || sftp_umask.is_some();
+    let shell = args.shell.clone().or_else(|| settings.shell.clone());
+    let probe = remote::probe(&target, &base, !reuse_install).await?;
+    timings.lap("bootstrap probe");
+    if let Some(account) = settings.map_user(&login_user) {
+        let account = if account == "@container" {
+            if probe.user.is_empty() {
+                bail!("failed to read the container user");
+            }
+            probe.user.clone()
+        } else {
+            account.to_string()
+        };
+        if account == login_user {
+            // Nothing to alias.
+        } else if probe.uid != "0" {
+            // Only root can add the alias to /etc/passwd; the login-user check
+            // below then asks for the container user.
+            warn!(
+                "[sshpod] not mapping ssh user {} to {}: the container does not run as root",
+                login_user, account
+            );
+        } else {
+            info!("[sshpod] mapping ssh user {} to {}", login_user, account);
+            remote::alias_login_user(&target, &login_user, &account).await?;
+        }
+    }
+    if args.create_user || settings.create_user {
+        remote::provision_login_user(&target, &login_user).await?;
+    }
+    remote::assert_login_user_allowed(&probe, &login_user)?;
+    timings.lap("user check");
+
+    if reuse_install {
+        info!("[sshpod] reusing the install another sshpod process just finished");
+        timings.lap("install reuse");
+    } else {
+        let arch = bundle::remote_arch(&probe.machine).context("failed to detect remote arch")?;
+        info!("[sshpod] remote architecture: {}", arch);
+        bundle::ensure_bundle(&target, &base, &arch, &probe, args.offline, crypto)
+            .await
+            .map_err(|err| SshpodError::BundleInstallFailed {
+                pod: pod_name.clone(),
+                reason: format!("{:#}", err),
+            })?;
+        info!("[sshpod] sshd bundle ready for pod {}", pod_name);
+        if with_rsync && bundle::ensure_tool(&target, &base, &arch, "rsync").await? {
+            let path = format!("{}/bundle/rsync", base);
+            info!(
+                "[sshpod] rsync is on the session PATH; pass --rsync-path={} if a login shell resets PATH",
+                path
+            );
+            events::emit("rsync", json!({"path": path}));
+        }
+        // Falling back to internal-sftp would defeat the point of asking.
+        if sftp_server && !bundle::ensure_tool(&target, &base, &arch, "sftp-server").await? {
+            bail!("--sftp-server needs an sftp-server_<arch>.xz bundle (make bundles-sftp-server)");
+        }
+        // With a login shell ForceCommand also covers the sftp subsystem, so
+        // images without sftp-server need the bundled one for sftp and scp.
+        if shell.is_some() && !sftp_server && chroot.is_none() {
+            match bundle::ensure_tool(&target, &base, &arch, "sftp-server").await {
+                Ok(true) => {}
+                Ok(false) => warn!(
+                    "[sshpod] no sftp-server bundle; sftp and scp need an sftp-server in the container when --shell is set"
+                ),
+                Err(err) => warn!("[sshpod] failed to install sftp-server: {:#}", err),
+            }
+        }
+        timings.lap("bundle check/upload");
+        remote::install_host_keys(&target, &base, &host_keys, crypto.host_key_name()).await?;
+        timings.lap("host keys");
+    }
 
     info!("[sshpod] starting/ensuring sshd in pod {}", pod_name);
-    let remote_port =
-        remote::ensure_sshd_running(&target, &base, &login_user, &local_key.public).await?;
+    let sshd_options = SshdOptions {
+        crypto,
+        disk_cap_kib: args.max_remote_disk_mib.saturating_mul(1024),
+        nice: args.remote_nice,
+        max_processes: args.remote_max_processes,
+        ttl_secs: args.remote_ttl.map(|ttl| ttl.as_secs()).unwrap_or(0),
+        sftp_only: settings.sftp_only,
+        sftp_server,
+        sftp_umask,
+        env_patterns: settings.env.clone(),
+        shell,
+        preferred_port: load_state()
+            .await
+            .ok()
+            .and_then(|state| state.sshd_port(&pod_info.uid, &container)),
+        banner: settings.banner.then(|| session_banner(&target, &pod_info)),
+        allow_commands: settings.allow_commands.clone(),
+        deny_commands: settings.deny_commands.clone(),
+        bundle_path: with_rsync,
+        chroot: chroot.map(str::to_string),
+    };
+    let remote_port = remote::ensure_sshd_running(
+        &target,
+        &base,
+        &login_user,
+        authorized_key.trim(),
+        &sshd_options,
+    )
+    .await?;
+    timings.lap("sshd start");
+    exec_channel::close_all().await;
     info!(
         "[sshpod] sshd is listening on 127.0.0.1:{} (pod {})",
         remote_port, pod_name
     );
+    let session = SessionRecord {
+        pid: std::process::id(),
+        pod_uid: pod_info.uid.clone(),
+        container: container.clone(),
+        started_at: state::now_secs(),
+    };
+    update_state(|state| {
+        state.record_install(InstallRecord {
+            context: target.context.clone(),
+            namespace: target.namespace.clone(),
+            pod: pod_name.clone(),
+            pod_uid: pod_info.uid.clone(),
+            container: container.clone(),
+            base: base.clone(),
+            version: bundle::BUNDLE_VERSION.to_string(),
+            updated_at: state::now_secs(),
+            sshd_port: Some(remote_port),
+        });
+        state.host_keys.insert(
+            crypto.host_key_name().to_string(),
+            host_keys.public.trim().to_string(),
+        );
+        state.sessions.push(session.clone());
+    })
+    .await;
+    drop(setup_lock);
 
-    info!(
-        "[sshpod] starting port-forward to {}:{}",
-        pod_name, remote_port
-    );
-    let (mut forward, local_port) =
-        PortForward::start(host.context.as_deref(), ns_str, &pod_name, remote_port).await?;
-    info!(
-        "[sshpod] port-forward established: localhost:{} -> {}:{}",
-        local_port, pod_name, remote_port
-    );
+    let mut direct = None;
+    let direct_ip = pod_info
+        .ip
+        .as_deref()
+        .filter(|_| args.direct || settings.direct);
+    if let Some(ip) = direct_ip {
+        match connect_direct(&target, &base, ip).await {
+            Ok((stream, port)) => {
+                info!("[sshpod] connected directly to {}:{}", ip, port);
+                events::emit("port", json!({"remote": port, "direct": ip}));
+                direct = Some(stream);
+            }
+            Err(err) => warn!(
+                "[sshpod] direct connection to {} failed, using port-forward: {:#}",
+                ip, err
+            ),
+        }
+    }
+    let mut forward = None;
+    let stream = match direct {
+        Some(stream) => stream,
+        None => {
+            info!(
+                "[sshpod] starting port-forward to {}:{}",
+                pod_name, remote_port
+            );
+            let (port_forward, local_port) = PortForward::start(
+                target.context.as_deref(),
+                ns_str,
+                &pod_name,
+                None,
+                remote_port,
+            )
+            .await?;
+            forward = Some(port_forward);
+            timings.lap("port-forward");
+            events::emit("port", json!({"remote": remote_port, "local": local_port}));
+            info!(
+                "[sshpod] port-forward established: localhost:{} -> {}:{}",
+                local_port, pod_name, remote_port
+            );
+            proxy_io::connect_with_banner(local_port).await?
+        }
+    };
 
-    let stream = TcpStream::connect(("127.0.0.1", local_port))
+    let mut presets = Vec::new();
+    for &(local, remote) in &settings.forwards {
+        match PortForward::start(
+            target.context.as_deref(),
+            ns_str,
+            &pod_name,
+            Some(local),
+            remote,
+        )
         .await
-        .context("failed to connect to forwarded sshd port")?;
+        {
+            Ok((preset, _)) => {
+                info!(
+                    "[sshpod] forwarding localhost:{} -> {}:{}",
+                    local, pod_name, remote
+                );
+                presets.push(preset);
+            }
+            // Usually another session to the same host already holds the port.
+            Err(err) => warn!(
+                "[sshpod] skipping forward localhost:{} -> {}: {:#}",
+                local, remote, err
+            ),
+        }
+    }
+
+    let log_follower = if args.follow || settings.follow {
+        match remote::follow_sshd_log(&target, &base) {
+            Ok(task) => Some(task),
+            Err(err) => {
+                warn!("[sshpod] cannot follow the sshd log: {:#}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-    let pump_result = proxy_io::pump(stream).await;
-    let stop_result = forward.stop().await;
+    timings.lap("first byte");
+    events::emit("ready", json!({}));
+    timings.report();
 
+    let pump_result = tokio::select! {
+        result = proxy_io::pump(stream) => result,
+        signal = shutdown_signal() => {
+            info!("[sshpod] received {}, shutting down", signal);
+            events::emit("signal", json!({"signal": signal}));
+            Ok(())
+        }
+    };
+    if let Some(task) = log_follower {
+        task.abort();
+    }
+    let stop_result = match &mut forward {
+        Some(forward) => forward.stop().await,
+        None => Ok(()),
+    };
+    for preset in &mut presets {
+        let _ = preset.stop().await;
+    }
+    update_state(|state| state.sessions.retain(|s| s != &session)).await;
+    if args.ephemeral_key || settings.ephemeral_key {
+        // Other local sessions to this container still need the shared client key
+        // to reconnect; a key passed with --authorize-key is ours alone.
+        let shared = load_state()
+            .await
+            .map(|state| state.has_live_session(&session.pod_uid, &session.container))
+            .unwrap_or(false);
+        if !shared || args.authorize_key.is_some() {
+            if let Err(err) = remote::remove_authorized_key(&target, &base, &authorized_key).await {
+                warn!("[sshpod] failed to remove ephemeral key: {:#}", err);
+            }
+        }
+    }
+
+    if let (Err(err), Some(_)) = (&pump_result, &forward) {
+        return Err(SshpodError::PortForwardDropped {
+            pod: pod_name,
+            reason: format!("{:#}", err),
+        }
+        .into());
+    }
     pump_result?;
     stop_result?;
     Ok(())
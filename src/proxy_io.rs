@@ -1,6 +1,84 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
 use tokio::io::{self, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+
+const BANNER_ATTEMPTS: u32 = 6;
+const BANNER_TIMEOUT: Duration = Duration::from_secs(5);
+const DIRECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Connects to the forwarded port and waits until sshd has sent its
+/// `SSH-2.0-` banner, retrying with backoff while sshd or the port-forward
+/// are still settling. The banner is only peeked so ssh still receives it.
+pub async fn connect_with_banner(port: u16) -> Result<TcpStream> {
+    connect_with_banner_attempts(port, BANNER_ATTEMPTS, Duration::from_millis(200)).await
+}
+
+/// Connects straight to sshd on the pod network, giving up quickly so the
+/// caller can fall back to port-forward.
+pub async fn connect_direct(ip: &str, port: u16) -> Result<TcpStream> {
+    timeout(DIRECT_TIMEOUT, try_banner(ip, port))
+        .await
+        .with_context(|| format!("timed out connecting to {}:{}", ip, port))?
+}
+
+async fn connect_with_banner_attempts(
+    port: u16,
+    attempts: u32,
+    initial_backoff: Duration,
+) -> Result<TcpStream> {
+    let mut backoff = initial_backoff;
+    let mut last_err = anyhow!("no connection attempt made");
+    for attempt in 1..=attempts {
+        match try_banner("127.0.0.1", port).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => {
+                debug!(
+                    "[sshpod] banner check attempt {} failed: {:#}",
+                    attempt, err
+                );
+                last_err = err;
+            }
+        }
+        if attempt < attempts {
+            sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    Err(last_err.context(format!(
+        "sshd did not send an SSH banner after {} attempts",
+        attempts
+    )))
+}
+
+async fn try_banner(host: &str, port: u16) -> Result<TcpStream> {
+    let stream = TcpStream::connect((host, port))
+        .await
+        .context("failed to connect to sshd port")?;
+    let mut buf = [0u8; 64];
+    let banner = timeout(BANNER_TIMEOUT, async {
+        loop {
+            let n = stream.peek(&mut buf).await?;
+            if n == 0 {
+                bail!("connection closed before the SSH banner");
+            }
+            if n >= 8 || buf[..n].contains(&b'\n') {
+                break Ok(String::from_utf8_lossy(&buf[..n]).to_string());
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .context("timed out waiting for the SSH banner")??;
+    if !banner.starts_with("SSH-2.0-") && !banner.starts_with("SSH-1.99-") {
+        bail!(
+            "unexpected data instead of an SSH banner: {:?}",
+            banner.trim_end()
+        );
+    }
+    Ok(stream)
+}
 
 pub async fn pump(stream: TcpStream) -> Result<()> {
     let (mut reader, mut writer) = stream.into_split();
@@ -29,3 +107,41 @@ pub async fn pump(stream: TcpStream) -> Result<()> {
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn banner_is_peeked_not_consumed() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            sock.write_all(b"SSH-2.0-OpenSSH_9.7\r\n").await.unwrap();
+            sleep(Duration::from_millis(200)).await;
+        });
+        let mut stream = connect_with_banner(port).await.expect("banner");
+        let mut line = [0u8; 8];
+        tokio::io::AsyncReadExt::read_exact(&mut stream, &mut line)
+            .await
+            .unwrap();
+        assert_eq!(&line, b"SSH-2.0-");
+    }
+
+    #[tokio::test]
+    async fn closed_connections_fail_after_retries() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                drop(sock);
+            }
+        });
+        let err = connect_with_banner_attempts(port, 2, Duration::from_millis(1))
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("after 2 attempts"));
+    }
+}
@@ -1,74 +1,546 @@
+use crate::crypto::CryptoPreset;
 use crate::keys::Key;
 use crate::kubectl::{self, RemoteTarget};
 use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::JoinHandle;
 use tokio::time::{timeout, Duration};
+use zeroize::Zeroizing;
 
-pub async fn try_acquire_lock(target: &RemoteTarget, base: &str) {
-    let lock_cmd = format!("umask 077; mkdir \"{}/lock\"", base);
-    let _ = kubectl::exec_capture_optional_target(target, &["sh", "-c", &lock_cmd]).await;
+/// What the bootstrap exec reports about a container before setup.
+#[derive(Debug, Default, PartialEq)]
+pub struct Probe {
+    pub uid: String,
+    pub user: String,
+    /// `uname -m` output.
+    pub machine: String,
+    pub bundle_version: Option<String>,
+    pub bundle_arch: Option<String>,
 }
 
-pub async fn assert_login_user_allowed(target: &RemoteTarget, login_user: &str) -> Result<()> {
-    let uid = kubectl::exec_capture_target(target, &["id", "-u"])
-        .await
-        .context("failed to read remote uid")?;
-    if uid.trim() == "0" {
+const PROBE_SCRIPT: &str = r#"BASE="$1"
+if [ "$2" = lock ]; then
+  (umask 077; mkdir "$BASE/lock") 2>/dev/null
+fi
+echo "uid=$(id -u)"
+echo "user=$(id -un 2>/dev/null)"
+echo "machine=$(uname -m)"
+if [ -f "$BASE/bundle/VERSION" ]; then echo "version=$(cat "$BASE/bundle/VERSION")"; fi
+if [ -f "$BASE/bundle/ARCH" ]; then echo "arch=$(cat "$BASE/bundle/ARCH")"; fi
+exit 0
+"#;
+
+/// Gathers uid, user, architecture and the installed bundle's version in
+/// one exec, taking the setup lock first when `lock` is set.
+pub async fn probe(target: &RemoteTarget, base: &str, lock: bool) -> Result<Probe> {
+    let lock = if lock { "lock" } else { "" };
+    let output =
+        kubectl::exec_capture_target(target, &["sh", "-c", PROBE_SCRIPT, "sh", base, lock])
+            .await
+            .context("failed to probe the container")?;
+    parse_probe(&output)
+}
+
+fn parse_probe(output: &str) -> Result<Probe> {
+    let mut probe = Probe::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().to_string();
+        match key {
+            "uid" => probe.uid = value,
+            "user" => probe.user = value,
+            "machine" => probe.machine = value,
+            "version" => probe.bundle_version = Some(value),
+            "arch" => probe.bundle_arch = Some(value),
+            _ => {}
+        }
+    }
+    if probe.uid.is_empty() || probe.machine.is_empty() {
+        bail!("unexpected probe output: {}", output.trim());
+    }
+    Ok(probe)
+}
+
+pub fn assert_login_user_allowed(probe: &Probe, login_user: &str) -> Result<()> {
+    if probe.uid == "0" {
         return Ok(());
     }
-    let remote_user = kubectl::exec_capture_target(target, &["id", "-un"])
-        .await
-        .context("failed to read remote user")?;
-    if remote_user.trim() != login_user {
+    if probe.user.is_empty() {
+        bail!("failed to read remote user (uid {})", probe.uid);
+    }
+    if probe.user != login_user {
         bail!(
             "This Pod runs as non-root. Use the container user for login (requested: {}, required: {}).",
             login_user,
-            remote_user.trim()
+            probe.user
         );
     }
     Ok(())
 }
 
-pub async fn install_host_keys(target: &RemoteTarget, base: &str, host_keys: &Key) -> Result<()> {
-    let private = &host_keys.private;
-    let public = &host_keys.public;
+/// Directory holding everything sshpod installs for one container.
+pub fn base_dir(pod_uid: &str, container: &str) -> String {
+    format!("/tmp/sshpod/{}/{}", pod_uid, container)
+}
+
+/// Stops sshd and the TTL watchdog and removes `base` from the container.
+pub async fn uninstall(target: &RemoteTarget, base: &str) -> Result<()> {
     let script = format!(
+        r#"BASE="{base}"
+for f in "$BASE/sshd.pid" "$BASE/watchdog.pid" "$BASE"/direct.*.pid; do
+  [ -f "$f" ] && kill "$(cat "$f")" 2>/dev/null
+done
+rm -rf "$BASE"
+rmdir "$(dirname "$BASE")" /tmp/sshpod 2>/dev/null
+exit 0
+"#
+    );
+    kubectl::exec_capture_target(target, &["sh", "-c", &script])
+        .await
+        .with_context(|| format!("failed to remove {} from pod {}", base, target.pod))?;
+    Ok(())
+}
+
+/// Copies auth failures and errors from `$BASE/logs/sshd.log` to our stderr
+/// as they happen, until the returned task is aborted.
+pub fn follow_sshd_log(target: &RemoteTarget, base: &str) -> Result<JoinHandle<()>> {
+    let log = format!("{}/logs/sshd.log", base);
+    let mut child = kubectl::spawn_exec_target(
+        target,
+        &[
+            "sh",
+            "-c",
+            "tail -n 0 -F \"$1\" 2>/dev/null || tail -n 0 -f \"$1\"",
+            "sh",
+            &log,
+        ],
+    )?;
+    let stdout = child.stdout.take().context("kubectl exec has no stdout")?;
+    Ok(tokio::spawn(async move {
+        let _child = child;
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if is_notable_log_line(&line) {
+                eprintln!("[sshpod] sshd: {}", line.trim_end());
+            }
+        }
+    }))
+}
+
+/// Whether an sshd log line explains a failed or refused login.
+fn is_notable_log_line(line: &str) -> bool {
+    const MARKERS: &[&str] = &[
+        "Failed ",
+        "Invalid user",
+        "Authentication refused",
+        "not allowed because",
+        "Connection closed by authenticating user",
+        "maximum authentication attempts",
+        "error:",
+        "fatal:",
+    ];
+    MARKERS.iter().any(|marker| line.contains(marker))
+}
+
+/// Settings that shape the sshd_config written by the start script.
+#[derive(Debug, Clone, Default)]
+pub struct SshdOptions {
+    pub crypto: CryptoPreset,
+    /// Upper bound for the size of `$BASE` in KiB; 0 disables the guard.
+    pub disk_cap_kib: u64,
+    /// Niceness for sshd and its sessions; also lowers I/O priority when positive.
+    pub nice: i32,
+    /// `ulimit -u` applied to sshd; 0 leaves the limit untouched.
+    pub max_processes: u32,
+    /// Seconds after which an in-pod watchdog stops sshd and removes `$BASE`; 0 disables it.
+    pub ttl_secs: u64,
+    /// Restrict sessions to SFTP via `ForceCommand internal-sftp`.
+    pub sftp_only: bool,
+    /// Serve SFTP with the bundled `$BASE/bundle/sftp-server` (logging to
+    /// `$BASE/logs/sftp.log`) instead of internal-sftp.
+    pub sftp_server: bool,
+    /// `-u` umask forced on files created over the external sftp-server.
+    pub sftp_umask: Option<String>,
+    /// Extra environment variable globs (e.g. `AWS_*`) exported into sessions.
+    pub env_patterns: Vec<String>,
+    /// Interactive login shell (name or path); falls back to `sh` when missing.
+    pub shell: Option<String>,
+    /// Port to try first, normally the one used by the previous session.
+    pub preferred_port: Option<u16>,
+    /// Text shown by sshd before authentication; `None` disables the banner.
+    pub banner: Option<String>,
+    /// Command names a restricted session may run; empty allows all not denied.
+    pub allow_commands: Vec<String>,
+    pub deny_commands: Vec<String>,
+    /// Prepend `$BASE/bundle` to the session PATH so bundled tools such as
+    /// rsync are found.
+    pub bundle_path: bool,
+    /// Directory sessions are chrooted into (node targets mount the host at `/host`).
+    pub chroot: Option<String>,
+}
+
+impl SshdOptions {
+    /// Summary of everything baked into sshd_config; a running sshd started
+    /// with a different profile is restarted.
+    fn profile(&self) -> String {
+        format!(
+            "crypto={};sftp-only={};sftp-server={}:{};env={};shell={};banner={};allow={};deny={};bundle-path={};chroot={}",
+            self.crypto.name(),
+            self.sftp_only,
+            self.sftp_server,
+            self.sftp_umask.as_deref().unwrap_or(""),
+            self.env_patterns.join(","),
+            self.shell.as_deref().unwrap_or(""),
+            self.banner.is_some(),
+            self.allow_commands.join(","),
+            self.deny_commands.join(","),
+            self.bundle_path,
+            self.chroot.as_deref().unwrap_or("")
+        )
+    }
+
+    /// Shell variable assignments prepended to the start script.
+    fn script_preamble(&self) -> String {
+        let profile = self.profile();
+        let disk_cap = self.disk_cap_kib.to_string();
+        let nice = self.nice.to_string();
+        let max_processes = self.max_processes.to_string();
+        let ttl = self.ttl_secs.to_string();
+        let sftp_only = if self.sftp_only { "1" } else { "0" };
+        let sftp_server = if self.sftp_server { "1" } else { "0" };
+        let bundle_path = if self.bundle_path { "1" } else { "0" };
+        let env_patterns = self.env_patterns.join(" ");
+        let preferred_port = self.preferred_port.unwrap_or(0).to_string();
+        let allow_commands = self.allow_commands.join(" ");
+        let deny_commands = self.deny_commands.join(" ");
+        let vars = [
+            ("SSHD_PROFILE", profile.as_str()),
+            ("HOST_KEY_NAME", self.crypto.host_key_name()),
+            ("CRYPTO_CONFIG", self.crypto.sshd_config()),
+            ("DISK_CAP_KIB", disk_cap.as_str()),
+            ("SSHD_NICE", nice.as_str()),
+            ("SSHD_MAX_PROCS", max_processes.as_str()),
+            ("TTL_SECS", ttl.as_str()),
+            ("SFTP_ONLY", sftp_only),
+            ("SFTP_SERVER", sftp_server),
+            ("SFTP_UMASK", self.sftp_umask.as_deref().unwrap_or("")),
+            ("ENV_PATTERNS", env_patterns.as_str()),
+            ("SHELL_REQUEST", self.shell.as_deref().unwrap_or("")),
+            ("PREFERRED_PORT", preferred_port.as_str()),
+            ("BANNER_TEXT", self.banner.as_deref().unwrap_or("")),
+            ("ALLOW_COMMANDS", allow_commands.as_str()),
+            ("DENY_COMMANDS", deny_commands.as_str()),
+            ("BUNDLE_PATH", bundle_path),
+            ("CHROOT_DIR", self.chroot.as_deref().unwrap_or("")),
+        ];
+        vars.iter()
+            .map(|(key, value)| format!("{}={}\n", key, shell_quote(value)))
+            .collect()
+    }
+}
+
+/// Validates an octal umask such as `022` or `0027`.
+pub fn parse_umask(value: &str) -> Result<String, String> {
+    if (3..=4).contains(&value.len()) && value.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "invalid umask `{}`: expected octal like 022",
+            value
+        ))
+    }
+}
+
+/// Quotes a value for safe interpolation into a POSIX shell script.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Creates `login_user` (with a home directory) when the container runs as
+/// root and the user does not exist yet.
+pub async fn provision_login_user(target: &RemoteTarget, login_user: &str) -> Result<()> {
+    if !is_valid_username(login_user) {
+        bail!(
+            "refusing to create user with unsupported name `{}`",
+            login_user
+        );
+    }
+    kubectl::exec_with_input_target(
+        target,
+        &["sh", "-s", "--", login_user],
+        PROVISION_USER_SCRIPT.as_bytes(),
+    )
+    .await
+    .with_context(|| format!("failed to create user {} in the container", login_user))?;
+    Ok(())
+}
+
+/// Adds `login_user` to /etc/passwd as an alias sharing `account`'s uid, gid,
+/// home, and shell, so sshd accepts the ssh user name for that account.
+/// Needs root; the entry stays until the container is recreated.
+pub async fn alias_login_user(
+    target: &RemoteTarget,
+    login_user: &str,
+    account: &str,
+) -> Result<()> {
+    if !is_valid_username(login_user) {
+        bail!("refusing to alias unsupported user name `{}`", login_user);
+    }
+    kubectl::exec_with_input_target(
+        target,
+        &["sh", "-s", "--", login_user, account],
+        ALIAS_USER_SCRIPT.as_bytes(),
+    )
+    .await
+    .with_context(|| format!("failed to map user {} to {}", login_user, account))?;
+    Ok(())
+}
+
+const ALIAS_USER_SCRIPT: &str = r#"set -eu
+U="$1"
+T="$2"
+grep -q "^$U:" /etc/passwd && exit 0
+if [ "$(id -u)" != 0 ]; then
+  echo "cannot map $U to $T: container does not run as root; log in as $T instead" >&2
+  exit 1
+fi
+LINE="$(grep "^$T:" /etc/passwd)" || {
+  echo "account $T does not exist in the container" >&2
+  exit 1
+}
+printf '%s:%s\n' "$U" "${LINE#*:}" >> /etc/passwd
+if [ -f /etc/shadow ]; then
+  printf '%s:*:19000:0:99999:7:::\n' "$U" >> /etc/shadow
+fi
+"#;
+
+fn is_valid_username(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+        && name.len() <= 32
+}
+
+const PROVISION_USER_SCRIPT: &str = r#"set -eu
+U="$1"
+if id "$U" >/dev/null 2>&1; then
+  exit 0
+fi
+if [ "$(id -u)" != 0 ]; then
+  echo "cannot create user $U: container does not run as root" >&2
+  exit 1
+fi
+printf '[sshpod] creating user %s\n' "$U" >&2
+if command -v useradd >/dev/null 2>&1; then
+  useradd -m -s /bin/sh "$U"
+elif command -v adduser >/dev/null 2>&1; then
+  adduser -D -s /bin/sh "$U"
+else
+  ID="$(awk -F: 'BEGIN {m = 999} $3 > m && $3 < 60000 {m = $3} END {print m + 1}' /etc/passwd)"
+  printf '%s:x:%s:%s::/home/%s:/bin/sh\n' "$U" "$ID" "$ID" "$U" >> /etc/passwd
+  grep -q "^$U:" /etc/group 2>/dev/null || printf '%s:x:%s:\n' "$U" "$ID" >> /etc/group
+  if [ -f /etc/shadow ]; then
+    printf '%s:*:19000:0:99999:7:::\n' "$U" >> /etc/shadow
+  fi
+  mkdir -p "/home/$U"
+  chown "$ID:$ID" "/home/$U"
+  chmod 755 "/home/$U"
+fi
+# sshd without PAM treats "!" passwords as locked accounts, even for pubkey auth.
+if [ -f /etc/shadow ] && grep -q "^$U:!" /etc/shadow; then
+  sed -i "s/^$U:![^:]*:/$U:*:/" /etc/shadow
+fi
+"#;
+
+pub async fn install_host_keys(
+    target: &RemoteTarget,
+    base: &str,
+    host_keys: &Key,
+    key_name: &str,
+) -> Result<()> {
+    let private = host_keys.private.as_str();
+    let public = host_keys.public.trim();
+    // The matching public key identifies an installed private key, so the
+    // private key is only written (directly, without a temp copy) on change.
+    let script = Zeroizing::new(format!(
         r#"set -eu
 BASE="{base}"
-PRIV="$BASE/hostkeys/ssh_host_ed25519_key"
-PUB="$BASE/hostkeys/ssh_host_ed25519_key.pub"
-TMP_PRIV="$BASE/hostkeys/.tmp_priv"
-TMP_PUB="$BASE/hostkeys/.tmp_pub"
+PRIV="$BASE/hostkeys/{key_name}"
+PUB="$BASE/hostkeys/{key_name}.pub"
 umask 077
 mkdir -p "$BASE" "$BASE/hostkeys" "$BASE/logs"
 chmod 700 "$BASE" "$BASE/hostkeys"
-cat > "$TMP_PRIV" <<'__SSH_PKEY__'
-{private}
-__SSH_PKEY__
-cat > "$TMP_PUB" <<'__SSH_PUB__'
+if [ -f "$PRIV" ] && [ -f "$PUB" ] && cmp -s "$PUB" - <<'__SSH_PUB__'
 {public}
 __SSH_PUB__
-if [ -f "$PRIV" ] && [ -f "$PUB" ] && cmp -s "$PRIV" "$TMP_PRIV" && cmp -s "$PUB" "$TMP_PUB"; then
-  rm -f "$TMP_PRIV" "$TMP_PUB"
+then
   exit 0
 fi
-mv "$TMP_PRIV" "$PRIV"
-mv "$TMP_PUB" "$PUB"
+rm -f "$PRIV" "$PUB"
+cat > "$PRIV" <<'__SSH_PKEY__'
+{private}
+__SSH_PKEY__
+cat > "$PUB" <<'__SSH_PUB__'
+{public}
+__SSH_PUB__
 chmod 600 "$PRIV" "$PUB"
 "#
-    );
+    ));
     kubectl::exec_with_input_target(target, &["sh", "-s"], script.as_bytes())
         .await
         .with_context(|| format!("failed to install host keys into {}", base))?;
     Ok(())
 }
 
+/// Appends `key_line` to `$BASE/authorized_keys` as a restricted guest key
+/// (no forwarding); with a non-zero TTL a detached job removes it again once
+/// the TTL elapses. Returns the line as written, for [`remove_authorized_key`].
+pub async fn add_authorized_key(
+    target: &RemoteTarget,
+    base: &str,
+    key_line: &str,
+    ttl_secs: u64,
+) -> Result<String> {
+    let ttl = ttl_secs.to_string();
+    let output = kubectl::exec_with_input_target(
+        target,
+        &["sh", "-s", "--", base, key_line, &ttl],
+        ADD_AUTHORIZED_KEY_SCRIPT.as_bytes(),
+    )
+    .await
+    .with_context(|| format!("failed to add key to {}/authorized_keys", base))?;
+    Ok(output.trim().to_string())
+}
+
+/// Drops `key_line` from `$BASE/authorized_keys`, keeping the file's owner.
+pub async fn remove_authorized_key(
+    target: &RemoteTarget,
+    base: &str,
+    key_line: &str,
+) -> Result<()> {
+    let script = r#"AK="$1/authorized_keys"
+[ -f "$AK" ] || exit 0
+grep -vxF "$2" "$AK" > "$AK.tmp" || true
+cat "$AK.tmp" > "$AK"
+rm -f "$AK.tmp"
+"#;
+    kubectl::exec_capture_target(target, &["sh", "-c", script, "sh", base, key_line.trim()])
+        .await
+        .with_context(|| format!("failed to remove key from {}/authorized_keys", base))?;
+    Ok(())
+}
+
+/// A single-use sshd listening on the pod IP for a `--direct` connection.
+pub struct DirectListener {
+    pub port: u16,
+}
+
+/// Starts a second sshd with the running one's config on `addr` and a random
+/// port. It only lives until [`stop_direct_listener`] (30 seconds at most):
+/// the connection it accepted stays with its session process, but nothing
+/// else in the cluster can connect afterwards.
+pub async fn start_direct_listener(
+    target: &RemoteTarget,
+    base: &str,
+    addr: &str,
+) -> Result<DirectListener> {
+    let output =
+        kubectl::exec_capture_target(target, &["sh", "-c", START_DIRECT_SCRIPT, "sh", base, addr])
+            .await
+            .context("failed to start the direct sshd listener")?;
+    let port = output
+        .trim()
+        .parse()
+        .with_context(|| format!("unexpected direct listener output: {:?}", output.trim()))?;
+    Ok(DirectListener { port })
+}
+
+pub async fn stop_direct_listener(
+    target: &RemoteTarget,
+    base: &str,
+    listener: &DirectListener,
+) -> Result<()> {
+    let script = r#"PID_FILE="$1/direct.$2.pid"
+[ -f "$PID_FILE" ] && kill "$(cat "$PID_FILE")" 2>/dev/null
+rm -f "$PID_FILE"
+exit 0
+"#;
+    let port = listener.port.to_string();
+    kubectl::exec_capture_target(target, &["sh", "-c", script, "sh", base, &port])
+        .await
+        .context("failed to stop the direct sshd listener")?;
+    Ok(())
+}
+
+const START_DIRECT_SCRIPT: &str = r#"BASE="$1"
+case "$2" in
+  *:*) ADDR="[$2]" ;;
+  *) ADDR="$2" ;;
+esac
+CONFIG="$BASE/sshd_direct_config"
+i=0
+while [ $i -lt 10 ]; do
+  i=$((i+1))
+  val="$(od -An -N2 -tu2 /dev/urandom 2>/dev/null | tr -d ' ' || true)"
+  [ -n "$val" ] || val=$(( ($$ * 7919 + $(date +%s) + i * 104729) % 65536 ))
+  PORT=$((20000 + (val % 45000)))
+  PID_FILE="$BASE/direct.$PORT.pid"
+  {
+    printf 'ListenAddress %s:%s\nPidFile %s\nMaxStartups 1\n' "$ADDR" "$PORT" "$PID_FILE"
+    grep -v -e '^ListenAddress ' -e '^Port ' -e '^PidFile ' "$BASE/sshd_config"
+  } > "$CONFIG"
+  chmod 600 "$CONFIG"
+  rm -f "$PID_FILE"
+  # sshd exits non-zero when the port is taken; try another one.
+  "$BASE/bundle/sshd" -f "$CONFIG" -E "$BASE/logs/sshd-direct.log" </dev/null || continue
+  j=0
+  while [ $j -lt 5 ]; do
+    if [ -f "$PID_FILE" ] && kill -0 "$(cat "$PID_FILE")" 2>/dev/null; then
+      # Closes the listener even if the client never gets to stop it.
+      nohup sh -c 'sleep 30; [ -f "$1" ] && kill "$(cat "$1")" 2>/dev/null; rm -f "$1"' \
+        sh "$PID_FILE" >/dev/null 2>&1 &
+      echo "$PORT"
+      exit 0
+    fi
+    j=$((j+1))
+    sleep 1
+  done
+done
+echo "direct sshd did not start on $2" >&2
+exit 1
+"#;
+
+const ADD_AUTHORIZED_KEY_SCRIPT: &str = r#"set -eu
+BASE="$1"
+KEY="$2"
+TTL="$3"
+# Guest keys get a shell and sftp but no port, agent or X11 forwarding, so
+# they cannot tunnel into the cluster network.
+LINE="restrict,pty $KEY"
+AK="$BASE/authorized_keys"
+if [ ! -f "$AK" ]; then
+  echo "sshpod is not set up in this container yet; connect once before inviting" >&2
+  exit 1
+fi
+grep -qxF "$LINE" "$AK" || printf '%s\n' "$LINE" >> "$AK"
+if [ "$TTL" -gt 0 ]; then
+  nohup sh -c 'sleep "$1"; [ -f "$3" ] || exit 0; grep -vxF "$2" "$3" > "$3.tmp" || true; cat "$3.tmp" > "$3"; rm -f "$3.tmp"' \
+    sh "$TTL" "$LINE" "$AK" >/dev/null 2>&1 &
+fi
+printf '%s\n' "$LINE"
+"#;
+
 pub async fn ensure_sshd_running(
     target: &RemoteTarget,
     base: &str,
     login_user: &str,
     pubkey_line: &str,
+    options: &SshdOptions,
 ) -> Result<u16> {
-    let script = START_SSHD_SCRIPT.as_bytes();
+    let script = format!("{}{}", options.script_preamble(), START_SSHD_SCRIPT);
+    let script = script.as_bytes();
     let output = timeout(Duration::from_secs(40), {
         kubectl::exec_with_input_target(
             target,
@@ -109,8 +581,29 @@ chmod 700 "$BASE" "$BASE/hostkeys" "$BASE/logs"
 BASE_PARENT="$(dirname "$BASE")"
 TOP_DIR="$(dirname "$BASE_PARENT")"
 chmod 711 "$TOP_DIR" "$BASE_PARENT"
+# rsync and sftp-server run as the login user; older installs left them 0700.
+chmod 755 "$BASE/bundle" "$BASE/bundle/rsync" "$BASE/bundle/sftp-server" 2>/dev/null || true
 debug_log "start script begin (base=$BASE user=$LOGIN_USER)"
 
+usage_kib() {
+  du -sk "$BASE" 2>/dev/null | awk '{print $1}'
+}
+
+if [ "$DISK_CAP_KIB" -gt 0 ]; then
+  USED_KIB="$(usage_kib)"
+  if [ "${USED_KIB:-0}" -gt "$DISK_CAP_KIB" ]; then
+    debug_log "sshpod files use ${USED_KIB}KiB (cap ${DISK_CAP_KIB}KiB), trimming logs"
+    for f in "$BASE/logs/"*; do
+      [ -f "$f" ] && : > "$f"
+    done
+    USED_KIB="$(usage_kib)"
+    if [ "${USED_KIB:-0}" -gt "$DISK_CAP_KIB" ]; then
+      echo "sshpod files in $BASE use ${USED_KIB}KiB, exceeding the ${DISK_CAP_KIB}KiB cap" >&2
+      exit 1
+    fi
+  fi
+fi
+
 get_home() {
   if command -v getent >/dev/null 2>&1; then
     getent passwd "$1" | awk -F: '{print $6}'
@@ -149,37 +642,243 @@ if ! have_user sshd; then
   fi
 fi
 
-if [ ! -f "$BASE/hostkeys/ssh_host_ed25519_key" ]; then
-  echo "host key missing at $BASE/hostkeys/ssh_host_ed25519_key" >&2
+if [ ! -f "$BASE/hostkeys/$HOST_KEY_NAME" ]; then
+  echo "host key missing at $BASE/hostkeys/$HOST_KEY_NAME" >&2
   exit 1
 fi
 chmod 600 "$BASE/hostkeys/"*
 
+arm_watchdog() {
+  if [ "$TTL_SECS" -le 0 ]; then
+    return 0
+  fi
+  if [ -f "$BASE/watchdog.pid" ] && kill -0 "$(cat "$BASE/watchdog.pid")" 2>/dev/null; then
+    return 0
+  fi
+  debug_log "arming ttl watchdog (${TTL_SECS}s)"
+  nohup sh -c '
+    sleep "$1"
+    [ -f "$2/sshd.pid" ] && kill "$(cat "$2/sshd.pid")" 2>/dev/null
+    rm -rf "$2"
+  ' sshpod-ttl "$TTL_SECS" "$BASE" </dev/null >/dev/null 2>&1 &
+  echo $! > "$BASE/watchdog.pid"
+  chmod 600 "$BASE/watchdog.pid"
+}
+
+# sshd reads the banner on every connection, so refresh it even when running.
+if [ -n "$BANNER_TEXT" ]; then
+  printf '%s\n' "$BANNER_TEXT" > "$BASE/banner"
+  chmod 644 "$BASE/banner"
+fi
+
 if [ -f "$BASE/sshd.pid" ] && kill -0 "$(cat "$BASE/sshd.pid")" && [ -f "$BASE/sshd.port" ]; then
-  debug_log "sshd already running"
-  cat "$BASE/sshd.port" >&3
-  exit 0
+  if [ "$(cat "$BASE/sshd.profile" 2>/dev/null || true)" = "$SSHD_PROFILE" ]; then
+    debug_log "sshd already running"
+    arm_watchdog
+    cat "$BASE/sshd.port" >&3
+    exit 0
+  fi
+  debug_log "sshd running with a different profile, restarting"
+  kill "$(cat "$BASE/sshd.pid")" || true
 fi
 debug_log "sshd not running, starting new instance"
 
+detect_runtime() {
+  if grep -qi gvisor /proc/version 2>/dev/null || dmesg 2>/dev/null | grep -qi gvisor; then
+    echo gvisor
+  elif grep -qi kata /proc/cmdline 2>/dev/null; then
+    echo kata
+  else
+    echo default
+  fi
+}
+RUNTIME="$(detect_runtime)"
+if [ "$RUNTIME" != default ]; then
+  debug_log "detected sandboxed runtime: $RUNTIME"
+fi
+
 rand_port() {
-  val="$(od -An -N2 -tu2 /dev/urandom | tr -d ' ')"
+  val="$(od -An -N2 -tu2 /dev/urandom 2>/dev/null | tr -d ' ' || true)"
+  if [ -z "$val" ]; then
+    # Sandboxes may restrict /dev/urandom or lack od; mix pid, time, and attempt.
+    val=$(( ($$ * 7919 + $(date +%s) + i * 104729) % 65536 ))
+  fi
   echo $((20000 + (val % 45000)))
 }
 
+port_in_use() {
+  [ -r /proc/net/tcp ] || return 1
+  files=/proc/net/tcp
+  [ -r /proc/net/tcp6 ] && files="$files /proc/net/tcp6"
+  awk -v p="$(printf '%04X' "$1")" '
+    $4 == "0A" { n = split($2, a, ":"); if (a[n] == p) found = 1 }
+    END { exit !found }
+  ' $files
+}
+
+LAUNCH_PREFIX=""
+if [ "$SSHD_NICE" -ne 0 ] && command -v nice >/dev/null 2>&1; then
+  LAUNCH_PREFIX="nice -n $SSHD_NICE"
+  if [ "$SSHD_NICE" -gt 0 ] && command -v ionice >/dev/null 2>&1 && ionice -c 2 -n 7 true 2>/dev/null; then
+    LAUNCH_PREFIX="ionice -c 2 -n 7 $LAUNCH_PREFIX"
+  fi
+fi
+
+SFTP_SUBSYSTEM=internal-sftp
+if [ "$SFTP_SERVER" = 1 ]; then
+  # sftp-server logs to stderr (-e), which the wrapper keeps out of the
+  # client's session.
+  SFTP_SUBSYSTEM="$BASE/sftp-server"
+  cat > "$SFTP_SUBSYSTEM" <<EOF
+#!/bin/sh
+exec "$BASE/bundle/sftp-server" -e -l INFO -f AUTH ${SFTP_UMASK:+-u $SFTP_UMASK} "\$@" 2>>"$BASE/logs/sftp.log"
+EOF
+  chmod 755 "$SFTP_SUBSYSTEM"
+else
+  rm -f "$BASE/sftp-server"
+fi
+
+LOGIN_SHELL=""
+if [ -n "$SHELL_REQUEST" ]; then
+  LOGIN_SHELL="$(command -v "$SHELL_REQUEST" 2>/dev/null || true)"
+  if [ -z "$LOGIN_SHELL" ]; then
+    debug_log "shell $SHELL_REQUEST not found, falling back to sh"
+    LOGIN_SHELL="$(command -v sh)"
+  fi
+  # Interactive sessions get the requested shell; commands keep POSIX sh
+  # semantics so scp/rsync work, and sftp (which scp also uses since
+  # OpenSSH 9) needs an external sftp-server because ForceCommand also
+  # applies to subsystems: the container's own, else the bundled one.
+  cat > "$BASE/login-shell" <<EOF
+#!/bin/sh
+case "\${SSH_ORIGINAL_COMMAND:-}" in
+  "") exec "$LOGIN_SHELL" -l ;;
+  sftp|internal-sftp)
+    for p in "$BASE/sftp-server" /usr/lib/openssh/sftp-server /usr/libexec/openssh/sftp-server /usr/lib/ssh/sftp-server /usr/libexec/sftp-server "$BASE/bundle/sftp-server"; do
+      [ -x "\$p" ] && exec "\$p"
+    done
+    echo "sftp is unavailable with a custom shell: no sftp-server in this container or bundle" >&2
+    exit 1 ;;
+  *) exec /bin/sh -c "\$SSH_ORIGINAL_COMMAND" ;;
+esac
+EOF
+  chmod 755 "$BASE/login-shell"
+  if [ -n "$LOGIN_USER" ]; then
+    chown "$LOGIN_USER":"$LOGIN_USER" "$BASE/login-shell" || true
+  fi
+fi
+
+if [ -n "$CHROOT_DIR" ]; then
+  # Every session (and sftp, via the host's sftp-server) runs inside the chroot.
+  LOGIN_SHELL="$CHROOT_DIR"
+  cat > "$BASE/login-shell" <<EOF
+#!/bin/sh
+case "\${SSH_ORIGINAL_COMMAND:-}" in
+  "") exec chroot "$CHROOT_DIR" ${SHELL_REQUEST:-/bin/sh} -l ;;
+  sftp|internal-sftp)
+    for p in /usr/lib/openssh/sftp-server /usr/libexec/openssh/sftp-server /usr/lib/ssh/sftp-server /usr/libexec/sftp-server; do
+      [ -x "$CHROOT_DIR\$p" ] && exec chroot "$CHROOT_DIR" "\$p"
+    done
+    echo "sftp is unavailable: the node has no sftp-server" >&2
+    exit 1 ;;
+  *) exec chroot "$CHROOT_DIR" /bin/sh -c "\$SSH_ORIGINAL_COMMAND" ;;
+esac
+EOF
+  chmod 755 "$BASE/login-shell"
+fi
+
+FORWARDING=yes
+if [ -n "$ALLOW_COMMANDS$DENY_COMMANDS" ]; then
+  # Restricted sessions: every command line (and each line typed into the
+  # minimal interactive loop) is checked by name; shell operators, paths,
+  # forwarding, and sftp are refused so the checks cannot be sidestepped.
+  FORWARDING=no
+  {
+    echo '#!/bin/sh'
+    echo "ALLOW='$ALLOW_COMMANDS'"
+    echo "DENY='$DENY_COMMANDS'"
+    cat <<'FILTER'
+NL='
+'
+check() {
+  case "$1" in
+    *"$NL"*|*[\;\&\|\`\$\(\)\<\>]*)
+      echo "sshpod: shell operators are not permitted by policy" >&2
+      return 1 ;;
+  esac
+  set -f
+  set -- $1
+  set +f
+  [ $# -gt 0 ] || return 1
+  case "$1" in
+    *\'*|*\"*|*\\*|*/*|*=*|*\**|*\?*|*\[*|!|\{)
+      echo "sshpod: $1 is not permitted by policy (quoting, paths, globs and assignments are refused)" >&2
+      return 1 ;;
+  esac
+  ok=1
+  if [ -n "$ALLOW" ]; then
+    ok=0
+    for a in $ALLOW; do [ "$1" = "$a" ] && ok=1; done
+  fi
+  for d in $DENY; do [ "$1" = "$d" ] && ok=0; done
+  if [ "$ok" != 1 ]; then
+    echo "sshpod: $1 is not permitted by policy" >&2
+    return 1
+  fi
+}
+if [ -n "${SSH_ORIGINAL_COMMAND:-}" ]; then
+  check "$SSH_ORIGINAL_COMMAND" || exit 126
+  exec /bin/sh -c "$SSH_ORIGINAL_COMMAND"
+fi
+if [ -n "$ALLOW" ]; then
+  echo "sshpod restricted session; allowed commands: $ALLOW"
+else
+  echo "sshpod restricted session; denied commands: $DENY"
+fi
+while printf 'sshpod$ ' && IFS= read -r line; do
+  case "$line" in
+    exit|logout) break ;;
+    "") continue ;;
+  esac
+  check "$line" && /bin/sh -c "$line"
+done
+FILTER
+  } > "$BASE/command-filter"
+  chmod 755 "$BASE/command-filter"
+fi
+
 REMOTE_PATH="${PATH:-/usr/bin:/bin}"
+if [ "$BUNDLE_PATH" = "1" ]; then
+  REMOTE_PATH="$BASE/bundle:$REMOTE_PATH"
+fi
 ENV_EXPORTS="$(env | awk -F= '/^KUBERNETES_/ {print $1}')"
+if [ -n "$ENV_PATTERNS" ]; then
+  set -f
+  for name in $(env | awk -F= '/^[A-Za-z_][A-Za-z0-9_]*=/ {print $1}'); do
+    for pattern in $ENV_PATTERNS; do
+      case "$name" in
+        KUBERNETES_*) ;;
+        $pattern) ENV_EXPORTS="$ENV_EXPORTS $name" ;;
+      esac
+    done
+  done
+  set +f
+fi
 USER_HOME="$(get_home "$LOGIN_USER")"
 
 i=0
 while [ $i -lt 30 ]; do
   i=$((i+1))
-  PORT="$(rand_port)"
+  if [ $i -eq 1 ] && [ "$PREFERRED_PORT" -gt 0 ] && ! port_in_use "$PREFERRED_PORT"; then
+    PORT="$PREFERRED_PORT"
+  else
+    PORT="$(rand_port)"
+  fi
 
   cat > "$BASE/sshd_config" <<EOF
 ListenAddress 127.0.0.1
 Port $PORT
-HostKey $BASE/hostkeys/ssh_host_ed25519_key
+HostKey $BASE/hostkeys/$HOST_KEY_NAME
 PidFile $BASE/sshd.pid
 AuthorizedKeysFile $BASE/authorized_keys
 PubkeyAuthentication yes
@@ -188,15 +887,36 @@ PasswordAuthentication no
 KbdInteractiveAuthentication no
 ChallengeResponseAuthentication no
 PermitEmptyPasswords no
-AllowAgentForwarding yes
-AllowTcpForwarding yes
+AllowAgentForwarding $FORWARDING
+AllowTcpForwarding $FORWARDING
+AllowStreamLocalForwarding $FORWARDING
 X11Forwarding no
-Subsystem sftp internal-sftp
+Subsystem sftp $SFTP_SUBSYSTEM
 LogLevel VERBOSE
 PermitUserEnvironment yes
 EOF
 
+  if [ -n "$CRYPTO_CONFIG" ]; then
+    printf '%s' "$CRYPTO_CONFIG" >> "$BASE/sshd_config"
+  fi
+  if [ -n "$BANNER_TEXT" ]; then
+    printf 'Banner %s\n' "$BASE/banner" >> "$BASE/sshd_config"
+  fi
+  if [ "$RUNTIME" != default ]; then
+    printf 'UseDNS no\nPrintLastLog no\n' >> "$BASE/sshd_config"
+  fi
+  if [ "$SFTP_ONLY" = 1 ]; then
+    printf 'ForceCommand %s\n' "$SFTP_SUBSYSTEM" >> "$BASE/sshd_config"
+  elif [ -f "$BASE/command-filter" ] && [ -n "$ALLOW_COMMANDS$DENY_COMMANDS" ]; then
+    printf 'ForceCommand %s\nDisableForwarding yes\n' "$BASE/command-filter" >> "$BASE/sshd_config"
+  elif [ -n "$LOGIN_SHELL" ]; then
+    printf 'ForceCommand %s\n' "$BASE/login-shell" >> "$BASE/sshd_config"
+  fi
   printf 'SetEnv PATH=%s\n' "$REMOTE_PATH" >> "$BASE/sshd_config"
+  if [ "$BUNDLE_PATH" = "1" ] && [ -x "$BASE/bundle/rsync" ]; then
+    # For --rsync-path when a login shell resets PATH.
+    printf 'SetEnv SSHPOD_RSYNC_PATH=%s\n' "$BASE/bundle/rsync" >> "$BASE/sshd_config"
+  fi
   for key in $ENV_EXPORTS; do
     val="$(printenv "$key" || true)"
     printf 'SetEnv %s=%s\n' "$key" "$val" >> "$BASE/sshd_config"
@@ -241,12 +961,19 @@ EOF
   chmod 600 "$BASE/sshd_config"
   rm -f "$BASE/sshd.pid"
   debug_log "launching sshd on $PORT"
-  "$SSHD" -f "$BASE/sshd_config" -E "$BASE/logs/sshd.log" </dev/null || true
+  (
+    if [ "$SSHD_MAX_PROCS" -gt 0 ]; then
+      ulimit -u "$SSHD_MAX_PROCS" 2>/dev/null || debug_log "could not apply process limit"
+    fi
+    $LAUNCH_PREFIX "$SSHD" -f "$BASE/sshd_config" -E "$BASE/logs/sshd.log" </dev/null
+  ) || true
   j=0
   while [ $j -lt 10 ]; do
     if [ -f "$BASE/sshd.pid" ] && kill -0 "$(cat "$BASE/sshd.pid")"; then
       echo "$PORT" > "$BASE/sshd.port"
-      chmod 600 "$BASE/sshd.pid" "$BASE/sshd.port"
+      printf '%s\n' "$SSHD_PROFILE" > "$BASE/sshd.profile"
+      chmod 600 "$BASE/sshd.pid" "$BASE/sshd.port" "$BASE/sshd.profile"
+      arm_watchdog
       echo "$PORT" >&3
       exit 0
     fi
@@ -256,9 +983,90 @@ EOF
   debug_log "retrying sshd start (attempt $i)"
 done
 
-echo "sshd did not start" >&2
+echo "sshd did not start (runtime=$RUNTIME)" >&2
+if [ -f "$BASE/logs/sshd.log" ]; then
+  echo "last sshd log lines:" >&2
+  tail -n 5 "$BASE/logs/sshd.log" >&2 || true
+fi
+if [ "$RUNTIME" = gvisor ]; then
+  echo "gVisor may block the syscalls sshd needs (setgroups, privilege separation); try a runc-based pod" >&2
+fi
 exit 1
 "#;
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_probe_report() {
+        let probe = parse_probe(
+            "uid=1000\nuser=app\nmachine=aarch64\nversion=0.1.1+sshd1\narch=linux/arm64\n",
+        )
+        .unwrap();
+        assert_eq!(
+            probe,
+            Probe {
+                uid: "1000".into(),
+                user: "app".into(),
+                machine: "aarch64".into(),
+                bundle_version: Some("0.1.1+sshd1".into()),
+                bundle_arch: Some("linux/arm64".into()),
+            }
+        );
+        let fresh = parse_probe("uid=0\nuser=\nmachine=x86_64\n").unwrap();
+        assert_eq!(fresh.bundle_version, None);
+        assert!(assert_login_user_allowed(&fresh, "alice").is_ok());
+        assert!(assert_login_user_allowed(&probe, "alice").is_err());
+        assert!(parse_probe("sh: id: not found").is_err());
+    }
+
+    #[test]
+    fn username_validation() {
+        assert!(is_valid_username("alice"));
+        assert!(is_valid_username("_svc-1"));
+        assert!(!is_valid_username("Alice"));
+        assert!(!is_valid_username("bob;rm"));
+        assert!(!is_valid_username("1abc"));
+        assert!(!is_valid_username(""));
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn notable_log_lines_are_auth_problems() {
+        assert!(is_notable_log_line(
+            "Authentication refused: bad ownership or modes for directory /root"
+        ));
+        assert!(is_notable_log_line(
+            "Connection closed by authenticating user root 127.0.0.1 port 5000 [preauth]"
+        ));
+        assert!(!is_notable_log_line(
+            "Accepted publickey for root from 127.0.0.1 port 5000 ssh2"
+        ));
+    }
+
+    #[test]
+    fn preamble_selects_host_key_for_preset() {
+        let options = SshdOptions {
+            crypto: CryptoPreset::Fips,
+            ..SshdOptions::default()
+        };
+        let preamble = options.script_preamble();
+        assert!(preamble.contains("HOST_KEY_NAME='ssh_host_ecdsa_key'\n"));
+        assert!(preamble.contains("SSHD_PROFILE='crypto=fips;"));
+    }
+
+    #[test]
+    fn validates_sftp_umask() {
+        assert_eq!(parse_umask("022").as_deref(), Ok("022"));
+        assert_eq!(parse_umask("0027").as_deref(), Ok("0027"));
+        assert!(parse_umask("28").is_err());
+        assert!(parse_umask("099").is_err());
+        assert!(parse_umask("022; rm").is_err());
+    }
+}
@@ -0,0 +1,443 @@
+use crate::cli::TestArgs;
+use crate::crypto::CryptoPreset;
+use crate::hostspec;
+use crate::keys;
+use crate::kubectl;
+use crate::proxy;
+use crate::remote::shell_quote;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+const MARKER: &str = "sshpod-test-ok";
+
+/// Proxy timing laps (`phase` events) grouped into the phases `sshpod test`
+/// reports; `done` lists the laps that mark a group as finished. Resolution has
+/// optional trailing laps, so it only counts as finished once install starts.
+struct ProxyPhase {
+    name: &'static str,
+    laps: &'static [&'static str],
+    done: &'static [&'static str],
+}
+
+const PROXY_PHASES: &[ProxyPhase] = &[
+    ProxyPhase {
+        name: "proxy resolve",
+        laps: &[
+            "context check",
+            "all-namespaces search",
+            "pod selection",
+            "pod get",
+            "crash-loop wait",
+            "debug copy",
+            "debug container",
+        ],
+        done: &[],
+    },
+    ProxyPhase {
+        name: "install",
+        laps: &[
+            "local keys",
+            "setup queue",
+            "bootstrap probe",
+            "user check",
+            "bundle check/upload",
+            "host keys",
+            "install reuse",
+        ],
+        done: &["host keys", "install reuse"],
+    },
+    ProxyPhase {
+        name: "sshd start",
+        laps: &["sshd start"],
+        done: &["sshd start"],
+    },
+    ProxyPhase {
+        name: "port-forward",
+        laps: &["port-forward"],
+        done: &["port-forward"],
+    },
+];
+
+/// Everything after the port-forward: ssh's key exchange, authentication and
+/// the test command.
+const HANDSHAKE: &str = "handshake + command";
+
+struct Phase {
+    name: &'static str,
+    elapsed: Duration,
+    error: Option<String>,
+}
+
+/// What the proxy reported over `--events-socket` during the ssh login.
+#[derive(Default)]
+struct ProxyReport {
+    laps: Vec<(String, Duration)>,
+    ready: bool,
+    error: Option<String>,
+}
+
+impl ProxyReport {
+    fn record(&mut self, event: &Value) {
+        match event["event"].as_str() {
+            Some("phase") => {
+                if let (Some(phase), Some(ms)) = (event["phase"].as_str(), event["ms"].as_f64()) {
+                    self.laps
+                        .push((phase.to_string(), Duration::from_secs_f64(ms / 1000.0)));
+                }
+            }
+            Some("ready") => self.ready = true,
+            Some("error") => self.error = event["message"].as_str().map(str::to_string),
+            _ => {}
+        }
+    }
+}
+
+pub async fn run(args: TestArgs) -> Result<()> {
+    let host = hostspec::with_suffix(&args.host);
+    let started = Instant::now();
+    let mut phases = Vec::new();
+
+    let at = Instant::now();
+    let resolved = proxy::resolve_host(&host).await;
+    let target = match resolved {
+        Ok((target, _)) => {
+            phases.push(passed("resolve", at));
+            Some(target)
+        }
+        Err(err) => {
+            phases.push(failed("resolve", at, &err));
+            None
+        }
+    };
+
+    if let Some(target) = &target {
+        let at = Instant::now();
+        match kubectl::exec_capture_target(target, &["echo", MARKER]).await {
+            Ok(out) if out.trim() == MARKER => phases.push(passed("kubectl exec", at)),
+            Ok(out) => phases.push(failed(
+                "kubectl exec",
+                at,
+                &format!("unexpected output {:?}", out.trim()),
+            )),
+            Err(err) => phases.push(failed("kubectl exec", at, &err)),
+        }
+    }
+
+    if phases.iter().all(|p| p.error.is_none()) {
+        let dir = std::env::temp_dir().join(format!("sshpod-test-{}", std::process::id()));
+        let key = dir.join("id");
+        let at = Instant::now();
+        match keys::generate_key(&key, args.crypto.key_type()).await {
+            Ok(()) => {
+                phases.push(passed("temporary key", at));
+                let at = Instant::now();
+                let (result, report) =
+                    ssh_with_events(&host, args.user.as_deref(), args.crypto, &key, &dir).await;
+                phases.extend(proxy_phases(&report, at.elapsed(), &result));
+            }
+            Err(err) => phases.push(failed("temporary key", at, &err)),
+        }
+        let at = Instant::now();
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => phases.push(passed("cleanup", at)),
+            Err(err) => phases.push(failed("cleanup", at, &err)),
+        }
+    }
+
+    for phase in &phases {
+        match &phase.error {
+            None => println!(
+                "PASS  {:<24} {:>6}ms",
+                phase.name,
+                phase.elapsed.as_millis()
+            ),
+            Some(err) => println!(
+                "FAIL  {:<24} {:>6}ms  {}",
+                phase.name,
+                phase.elapsed.as_millis(),
+                err
+            ),
+        }
+    }
+    println!("total {:>31}ms", started.elapsed().as_millis());
+    let failures = phases.iter().filter(|p| p.error.is_some()).count();
+    if failures > 0 {
+        bail!("{} of {} phases failed", failures, phases.len());
+    }
+    Ok(())
+}
+
+/// Runs [`ssh_check`] while collecting the proxy's events on a unix socket
+/// in `dir`.
+#[cfg(unix)]
+async fn ssh_with_events(
+    host: &str,
+    user: Option<&str>,
+    crypto: CryptoPreset,
+    key: &Path,
+    dir: &Path,
+) -> (Result<()>, ProxyReport) {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let socket = dir.join("events.sock");
+    let listener = match tokio::net::UnixListener::bind(&socket) {
+        Ok(listener) => listener,
+        Err(err) => {
+            let err =
+                anyhow::Error::new(err).context(format!("failed to bind {}", socket.display()));
+            return (Err(err), ProxyReport::default());
+        }
+    };
+    let report = Arc::new(Mutex::new(ProxyReport::default()));
+    let sink = report.clone();
+    let mut collector = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let mut lines = BufReader::new(stream).lines();
+        while let Some(line) = lines.next_line().await? {
+            if let Ok(event) = serde_json::from_str::<Value>(&line) {
+                sink.lock().unwrap().record(&event);
+            }
+        }
+        std::io::Result::Ok(())
+    });
+    let result = ssh_check(host, user, crypto, key, Some(&socket)).await;
+    // The proxy closes the socket when it exits; a proxy that never started
+    // never connects, so do not wait for it.
+    if tokio::time::timeout(Duration::from_secs(2), &mut collector)
+        .await
+        .is_err()
+    {
+        collector.abort();
+    }
+    let report = std::mem::take(&mut *report.lock().unwrap());
+    (result, report)
+}
+
+#[cfg(not(unix))]
+async fn ssh_with_events(
+    host: &str,
+    user: Option<&str>,
+    crypto: CryptoPreset,
+    key: &Path,
+    _dir: &Path,
+) -> (Result<()>, ProxyReport) {
+    let result = ssh_check(host, user, crypto, key, None).await;
+    (result, ProxyReport::default())
+}
+
+/// Splits the ssh login into the proxy's phases. On failure, the first
+/// phase that did not finish is the one reported as failed; whatever time
+/// the finished phases do not account for is charged to it (or to the
+/// handshake).
+fn proxy_phases(report: &ProxyReport, elapsed: Duration, result: &Result<()>) -> Vec<Phase> {
+    let seen = |laps: &[&str]| {
+        report
+            .laps
+            .iter()
+            .any(|(lap, _)| laps.contains(&lap.as_str()))
+    };
+    let mut phases = Vec::new();
+    let mut accounted = Duration::ZERO;
+    for (index, phase) in PROXY_PHASES.iter().enumerate() {
+        let ProxyPhase { name, laps, done } = *phase;
+        let finished = report.ready
+            || seen(done)
+            || PROXY_PHASES[index + 1..]
+                .iter()
+                .any(|later| seen(later.laps));
+        if finished {
+            if seen(laps) {
+                let spent: Duration = report
+                    .laps
+                    .iter()
+                    .filter(|(lap, _)| laps.contains(&lap.as_str()))
+                    .map(|(_, duration)| *duration)
+                    .sum();
+                phases.push(Phase {
+                    name,
+                    elapsed: spent,
+                    error: None,
+                });
+                accounted += spent;
+            }
+        } else if let Err(err) = result {
+            phases.push(Phase {
+                name,
+                elapsed: elapsed.saturating_sub(accounted),
+                error: Some(report.error.clone().unwrap_or_else(|| format!("{:#}", err))),
+            });
+            return phases;
+        }
+    }
+    phases.push(Phase {
+        name: HANDSHAKE,
+        elapsed: elapsed.saturating_sub(accounted),
+        error: result.as_ref().err().map(|err| format!("{:#}", err)),
+    });
+    phases
+}
+
+/// Logs in with the temporary key through `sshpod proxy`, which authorizes
+/// the key for this session only, and runs a trivial command. The proxy
+/// reports its progress to `events` when given.
+async fn ssh_check(
+    host: &str,
+    user: Option<&str>,
+    crypto: CryptoPreset,
+    key: &Path,
+    events: Option<&Path>,
+) -> Result<()> {
+    let exe = std::env::current_exe().context("failed to locate the sshpod binary")?;
+    let mut proxy = format!(
+        "ProxyCommand={} proxy --host %h --user %r --port %p --ephemeral-key --authorize-key {}",
+        shell_quote(&exe.to_string_lossy()),
+        shell_quote(&key.with_extension("pub").to_string_lossy())
+    );
+    if let Some(events) = events {
+        proxy.push_str(&format!(
+            " --events-socket {}",
+            shell_quote(&events.to_string_lossy())
+        ));
+    }
+    if crypto != CryptoPreset::Default {
+        proxy.push_str(&format!(" --crypto {}", crypto.name()));
+    }
+    let destination = match user {
+        Some(user) => format!("{}@{}", user, host),
+        None => host.to_string(),
+    };
+    let output = Command::new("ssh")
+        .args(["-F", "/dev/null", "-o", &proxy, "-i"])
+        .arg(key)
+        .args([
+            "-o",
+            "IdentitiesOnly=yes",
+            "-o",
+            "BatchMode=yes",
+            "-o",
+            "StrictHostKeyChecking=no",
+            "-o",
+            "UserKnownHostsFile=/dev/null",
+            "-o",
+            "LogLevel=ERROR",
+        ])
+        .arg(&destination)
+        .args(["echo", MARKER])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("failed to run ssh")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let last = stderr.lines().rev().find(|l| !l.trim().is_empty());
+        bail!(
+            "ssh exited with {}: {}",
+            output.status,
+            last.unwrap_or("no output")
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim() != MARKER {
+        bail!("unexpected command output {:?}", stdout.trim());
+    }
+    Ok(())
+}
+
+fn passed(name: &'static str, at: Instant) -> Phase {
+    Phase {
+        name,
+        elapsed: at.elapsed(),
+        error: None,
+    }
+}
+
+fn failed(name: &'static str, at: Instant, err: &dyn std::fmt::Display) -> Phase {
+    Phase {
+        name,
+        elapsed: at.elapsed(),
+        error: Some(format!("{:#}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(laps: &[(&str, u64)]) -> ProxyReport {
+        ProxyReport {
+            laps: laps
+                .iter()
+                .map(|&(lap, ms)| (lap.to_string(), Duration::from_millis(ms)))
+                .collect(),
+            ..ProxyReport::default()
+        }
+    }
+
+    fn names(phases: &[Phase]) -> Vec<(&str, bool)> {
+        phases.iter().map(|p| (p.name, p.error.is_none())).collect()
+    }
+
+    #[test]
+    fn groups_proxy_laps_into_phases() {
+        let mut report = report(&[
+            ("context check", 10),
+            ("pod get", 20),
+            ("local keys", 5),
+            ("host keys", 100),
+            ("sshd start", 200),
+            ("port-forward", 300),
+            ("first byte", 50),
+        ]);
+        report.ready = true;
+        let phases = proxy_phases(&report, Duration::from_millis(1000), &Ok(()));
+        assert_eq!(
+            names(&phases),
+            [
+                ("proxy resolve", true),
+                ("install", true),
+                ("sshd start", true),
+                ("port-forward", true),
+                (HANDSHAKE, true),
+            ]
+        );
+        assert_eq!(phases[0].elapsed, Duration::from_millis(30));
+        assert_eq!(phases[1].elapsed, Duration::from_millis(105));
+        assert_eq!(phases[4].elapsed, Duration::from_millis(365));
+    }
+
+    #[test]
+    fn blames_the_first_unfinished_phase() {
+        let mut failed_install = report(&[("pod get", 20), ("local keys", 5)]);
+        failed_install.error = Some("bundle upload failed".into());
+        let phases = proxy_phases(
+            &failed_install,
+            Duration::from_millis(500),
+            &Err(anyhow::anyhow!("ssh exited with 255")),
+        );
+        assert_eq!(
+            names(&phases),
+            [("proxy resolve", true), ("install", false)]
+        );
+        assert_eq!(phases[1].elapsed, Duration::from_millis(480));
+        assert_eq!(phases[1].error.as_deref(), Some("bundle upload failed"));
+
+        let failed_sshd = report(&[("pod get", 20), ("install reuse", 1)]);
+        let phases = proxy_phases(
+            &failed_sshd,
+            Duration::from_millis(500),
+            &Err(anyhow::anyhow!("ssh exited with 255")),
+        );
+        assert_eq!(
+            names(&phases),
+            [
+                ("proxy resolve", true),
+                ("install", true),
+                ("sshd start", false)
+            ]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! minisign signatures over the bundles sshpod pushes into pods. Release
+//! builds embed the public key (`SSHPOD_BUNDLE_PUBLIC_KEY` at build time)
+//! and then refuse any bundle without a valid signature; builds without a
+//! key skip the check.
+
+use anyhow::{anyhow, Context, Result};
+use minisign_verify::{PublicKey, Signature};
+use std::io::Read;
+
+const PUBLIC_KEY: Option<&str> = option_env!("SSHPOD_BUNDLE_PUBLIC_KEY");
+
+/// Checks `data` against `signature` (the contents of its `.minisig` file),
+/// reading it through once.
+pub fn check(what: &str, data: impl Read, signature: Option<&str>) -> Result<()> {
+    let Some(key) = PUBLIC_KEY else {
+        return Ok(());
+    };
+    let signature = signature.with_context(|| {
+        format!(
+            "{} has no .minisig signature; this sshpod build only installs signed bundles",
+            what
+        )
+    })?;
+    verify(key, data, signature).with_context(|| format!("refusing unverified {}", what))
+}
+
+/// `public_key` is the base64 key line, or a whole `minisign.pub` file.
+fn verify(public_key: &str, mut data: impl Read, signature: &str) -> Result<()> {
+    let key_line = public_key
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .unwrap_or_default();
+    let key = PublicKey::from_base64(key_line)
+        .map_err(|err| anyhow!("invalid bundle public key: {}", err))?;
+    let signature =
+        Signature::decode(signature).map_err(|err| anyhow!("invalid signature: {}", err))?;
+    let mut verifier = key
+        .verify_stream(&signature)
+        .map_err(|err| anyhow!("signature check failed: {}", err))?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = data.read(&mut buf).context("failed to read signed data")?;
+        if n == 0 {
+            break;
+        }
+        verifier.update(&buf[..n]);
+    }
+    verifier
+        .finalize()
+        .map_err(|err| anyhow!("signature check failed: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "untrusted comment: minisign public key 0123456789ABCDEF\nRWQBI0VniavN79jUg+ePQ4ijM55JFNpWKHEW8bs1p9n/9EP1xxU6FqEK\n";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key\nRUQBI0VniavN76eLQzZdbEjkQtxoh4rG3zAb0hrMw+AyWGNQ60CT4iGVetTr3WKFxrROZVR6tDrF2MDa5xoaJ/uej2Pig15TnQE=\ntrusted comment: timestamp:1700000000\tfile:sshd_amd64.xz\thashed\nqYPL8wYHs/jCdIboXoFlwTGshVt5VPSzIlwKw7vPuxHatjx4ukYGF4ivi/ScxyRN7Grj5AEWBG6njpicQRiqAA==\n";
+
+    #[test]
+    fn verifies_minisign_signatures() {
+        verify(KEY, &b"sshd bundle\n"[..], SIGNATURE).unwrap();
+        assert!(verify(KEY, &b"sshd bundle\n\0"[..], SIGNATURE).is_err());
+        assert!(verify(KEY, &b"sshd bundle\n"[..], "garbage").is_err());
+    }
+}
@@ -0,0 +1,463 @@
+use crate::paths;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const STATE_FILE: &str = "state.json";
+const LOCK_FILE: &str = "state.lock";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const STALE_LOCK_AGE: Duration = Duration::from_secs(60);
+/// Upper bound for waiting on another process's setup (bundle upload included).
+const SETUP_LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Everything sshpod remembers between invocations.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub installs: Vec<InstallRecord>,
+    #[serde(default)]
+    pub sessions: Vec<SessionRecord>,
+    /// Public host keys pushed into pods, keyed by key file name.
+    #[serde(default)]
+    pub host_keys: BTreeMap<String, String>,
+    /// Cached lookup results keyed by a caller-defined string; only used in
+    /// the [`StateStore::lookup_cache`] store.
+    #[serde(default)]
+    pub lookups: BTreeMap<String, LookupRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstallRecord {
+    pub context: Option<String>,
+    pub namespace: String,
+    pub pod: String,
+    pub pod_uid: String,
+    pub container: String,
+    pub base: String,
+    pub version: String,
+    pub updated_at: u64,
+    /// Port sshd listened on, re-requested on the next connection.
+    #[serde(default)]
+    pub sshd_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub pid: u32,
+    pub pod_uid: String,
+    pub container: String,
+    pub started_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LookupRecord {
+    pub value: String,
+    pub stored_at: u64,
+    /// Seconds the record stays valid; records of different kinds live side by side.
+    #[serde(default)]
+    pub ttl: u64,
+}
+
+impl State {
+    /// Port sshd last used for this pod uid and container, if recorded.
+    pub fn sshd_port(&self, pod_uid: &str, container: &str) -> Option<u16> {
+        self.installs
+            .iter()
+            .find(|r| r.pod_uid == pod_uid && r.container == container)
+            .and_then(|r| r.sshd_port)
+    }
+
+    /// Whether another running sshpod process has a session to this container.
+    pub fn has_live_session(&self, pod_uid: &str, container: &str) -> bool {
+        let own = std::process::id();
+        self.sessions.iter().any(|s| {
+            s.pod_uid == pod_uid && s.container == container && s.pid != own && pid_alive(s.pid)
+        })
+    }
+
+    /// Value cached under `key` within the last `ttl`.
+    pub fn lookup(&self, key: &str, ttl: Duration, now: u64) -> Option<&str> {
+        self.lookups
+            .get(key)
+            .filter(|record| now.saturating_sub(record.stored_at) < ttl.as_secs())
+            .map(|record| record.value.as_str())
+    }
+
+    /// Caches `value` under `key` for `ttl`, dropping expired entries.
+    pub fn record_lookup(&mut self, key: &str, value: &str, ttl: Duration, now: u64) {
+        self.lookups
+            .retain(|_, record| now.saturating_sub(record.stored_at) < record.ttl);
+        self.lookups.insert(
+            key.to_string(),
+            LookupRecord {
+                value: value.to_string(),
+                stored_at: now,
+                ttl: ttl.as_secs(),
+            },
+        );
+    }
+
+    /// Inserts or replaces the install for the same pod uid and container.
+    pub fn record_install(&mut self, record: InstallRecord) {
+        self.installs
+            .retain(|r| !(r.pod_uid == record.pod_uid && r.container == record.container));
+        self.installs.push(record);
+    }
+}
+
+/// File-backed store guarded by a lock file so concurrent sshpod processes
+/// never interleave writes.
+pub struct StateStore {
+    dir: PathBuf,
+}
+
+impl StateStore {
+    pub fn open() -> Result<StateStore> {
+        Ok(StateStore::at(paths::state_dir()?))
+    }
+
+    /// Store for the short-lived lookup cache in `~/.cache/sshpod/lookups`,
+    /// kept apart from installs and sessions because it can be thrown away.
+    pub fn lookup_cache() -> Result<StateStore> {
+        Ok(StateStore::at(
+            paths::home_dir()?.join(".cache/sshpod/lookups"),
+        ))
+    }
+
+    pub fn at(dir: PathBuf) -> StateStore {
+        StateStore { dir }
+    }
+
+    pub async fn load(&self) -> Result<State> {
+        let _lock = self.lock().await?;
+        self.read()
+    }
+
+    /// Applies `f` to the current state under the lock and persists the result.
+    pub async fn update<T>(&self, f: impl FnOnce(&mut State) -> T) -> Result<T> {
+        let _lock = self.lock().await?;
+        let mut state = self.read()?;
+        let result = f(&mut state);
+        self.write(&state)?;
+        Ok(result)
+    }
+
+    /// Reads the state, dropping sessions of processes that have exited
+    /// without cleaning up (killed, or the machine went to sleep mid-session).
+    fn read(&self) -> Result<State> {
+        let path = self.dir.join(STATE_FILE);
+        let mut state: State = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("failed to parse {}", path.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => State::default(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        state.sessions.retain(|session| pid_alive(session.pid));
+        Ok(state)
+    }
+
+    fn write(&self, state: &State) -> Result<()> {
+        let path = self.dir.join(STATE_FILE);
+        let tmp_path = self
+            .dir
+            .join(format!("{}.tmp.{}", STATE_FILE, std::process::id()));
+        let data = serde_json::to_vec_pretty(state).context("failed to serialize state")?;
+        fs::write(&tmp_path, data)
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600));
+        }
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("failed to replace {}", path.display()))
+    }
+
+    async fn lock(&self) -> Result<LockGuard> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o700));
+        }
+        let path = self.dir.join(LOCK_FILE);
+        let started = SystemTime::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(LockGuard { path }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if lock_is_stale(&path) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed().unwrap_or_default() > LOCK_TIMEOUT {
+                        anyhow::bail!(
+                            "timed out waiting for state lock {}; remove it if no sshpod is running",
+                            path.display()
+                        );
+                    }
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("failed to create lock {}", path.display()))
+                }
+            }
+        }
+    }
+}
+
+impl StateStore {
+    /// Serializes pod setup for `key` across local sshpod processes. The lock
+    /// file holds the owner's pid so a crashed owner never blocks others.
+    pub async fn setup_lock(&self, key: &str) -> Result<SetupLock> {
+        let dir = self.dir.join("setup");
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let name: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = dir.join(format!("{}.lock", name));
+        let started = SystemTime::now();
+        let mut waited = false;
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    let _ = write!(file, "{}", std::process::id());
+                    return Ok(SetupLock {
+                        _guard: LockGuard { path },
+                        waited,
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if !owner_alive(&path) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed().unwrap_or_default() > SETUP_LOCK_TIMEOUT {
+                        anyhow::bail!("timed out waiting for setup lock {}", path.display());
+                    }
+                    waited = true;
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("failed to create lock {}", path.display()))
+                }
+            }
+        }
+    }
+}
+
+/// Held while this process sets up a pod.
+pub struct SetupLock {
+    _guard: LockGuard,
+    /// Another process held the lock first, so it likely did the setup.
+    pub waited: bool,
+}
+
+fn owner_alive(path: &Path) -> bool {
+    let Some(pid) = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+    else {
+        // Just created and not yet written; fall back to the age check.
+        return !lock_is_stale(path);
+    };
+    if cfg!(unix) {
+        pid_alive(pid)
+    } else {
+        !lock_is_stale(path)
+    }
+}
+
+/// Whether a local process with this pid exists. Always true where that
+/// cannot be checked.
+pub fn pid_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = i32::try_from(pid) else {
+            return false;
+        };
+        if pid <= 0 {
+            return false;
+        }
+        // SAFETY: signal 0 only checks that the process exists.
+        let alive = unsafe { libc::kill(pid, 0) == 0 };
+        // EPERM: the process exists but belongs to another user.
+        alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+fn lock_is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|age| age > STALE_LOCK_AGE)
+        .unwrap_or(false)
+}
+
+struct LockGuard {
+    path: PathBuf,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> StateStore {
+        let dir =
+            std::env::temp_dir().join(format!("sshpod-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        StateStore::at(dir)
+    }
+
+    fn install(uid: &str, version: &str) -> InstallRecord {
+        InstallRecord {
+            context: None,
+            namespace: "ns".into(),
+            pod: "pod".into(),
+            pod_uid: uid.into(),
+            container: "app".into(),
+            base: format!("/tmp/sshpod/{}/app", uid),
+            version: version.into(),
+            updated_at: 0,
+            sshd_port: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn update_persists_and_replaces_installs() {
+        let store = temp_store("installs");
+        store
+            .update(|state| state.record_install(install("u1", "v1")))
+            .await
+            .unwrap();
+        store
+            .update(|state| state.record_install(install("u1", "v2")))
+            .await
+            .unwrap();
+        let state = store.load().await.unwrap();
+        assert_eq!(state.installs, vec![install("u1", "v2")]);
+        assert!(!store.dir.join(LOCK_FILE).exists());
+        fs::remove_dir_all(&store.dir).ok();
+    }
+
+    #[test]
+    fn lookups_expire() {
+        let ttl = Duration::from_secs(300);
+        let mut state = State::default();
+        state.record_lookup("old", "a", ttl, 100);
+        state.record_lookup("new", "b", ttl, 350);
+        assert_eq!(state.lookup("old", ttl, 350), Some("a"));
+        assert_eq!(state.lookup("old", ttl, 400), None);
+        state.record_lookup("newer", "c", ttl, 420);
+        assert!(!state.lookups.contains_key("old"));
+        assert_eq!(state.lookup("new", ttl, 420), Some("b"));
+        // A short-lived record does not evict longer-lived ones.
+        state.record_lookup("short", "d", Duration::from_secs(30), 500);
+        state.record_lookup("newest", "e", Duration::from_secs(30), 540);
+        assert!(!state.lookups.contains_key("short"));
+        assert_eq!(state.lookup("newer", ttl, 540), Some("c"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn load_prunes_sessions_of_dead_processes() {
+        let store = temp_store("sessions");
+        let session = |pid| SessionRecord {
+            pid,
+            pod_uid: "u1".into(),
+            container: "app".into(),
+            started_at: 0,
+        };
+        store
+            .update(|state| {
+                state.sessions.push(session(std::process::id()));
+                state.sessions.push(session(2147483646));
+            })
+            .await
+            .unwrap();
+        let state = store.load().await.unwrap();
+        assert_eq!(state.sessions, vec![session(std::process::id())]);
+        fs::remove_dir_all(&store.dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn live_sessions_skip_dead_and_own_pids() {
+        let session = |pid, uid: &str| SessionRecord {
+            pid,
+            pod_uid: uid.into(),
+            container: "app".into(),
+            started_at: 0,
+        };
+        let mut state = State::default();
+        state.sessions.push(session(std::process::id(), "u1"));
+        state.sessions.push(session(2147483646, "u1"));
+        assert!(!state.has_live_session("u1", "app"));
+        // pid 1 always exists.
+        state.sessions.push(session(1, "u2"));
+        assert!(state.has_live_session("u2", "app"));
+        assert!(!state.has_live_session("u2", "other"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn setup_lock_takes_over_from_dead_owner() {
+        let store = temp_store("setup");
+        fs::create_dir_all(store.dir.join("setup")).unwrap();
+        let path = store.dir.join("setup/uid_app.lock");
+        fs::write(&path, "2147483646").unwrap();
+        let lock = store.setup_lock("uid/app").await.unwrap();
+        assert!(!lock.waited);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+        drop(lock);
+        assert!(!path.exists());
+        fs::remove_dir_all(&store.dir).ok();
+    }
+}
@@ -0,0 +1,32 @@
+use crate::state::StateStore;
+use anyhow::Result;
+
+pub async fn run() -> Result<()> {
+    let state = StateStore::open()?.load().await?;
+    if state.installs.is_empty() {
+        println!("No sshpod installs recorded");
+    } else {
+        println!("Installs:");
+    }
+    for install in &state.installs {
+        println!(
+            "  {}/{} container={} context={} version={} base={}",
+            install.namespace,
+            install.pod,
+            install.container,
+            install.context.as_deref().unwrap_or("default"),
+            install.version,
+            install.base
+        );
+    }
+    if !state.sessions.is_empty() {
+        println!("Sessions:");
+        for session in &state.sessions {
+            println!(
+                "  pid={} pod_uid={} container={} started_at={}",
+                session.pid, session.pod_uid, session.container, session.started_at
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use crate::events;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// Lap timer for the `--timings` phase breakdown.
+pub struct Timings {
+    enabled: bool,
+    started: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Timings {
+        let now = Instant::now();
+        Timings {
+            enabled,
+            started: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Records the time since the previous lap under `phase`.
+    pub fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        events::emit(
+            "phase",
+            json!({"phase": phase, "ms": elapsed.as_secs_f64() * 1000.0}),
+        );
+        self.phases.push((phase, elapsed));
+        self.last = now;
+    }
+
+    /// Prints the table to stderr when `--timings` was requested.
+    pub fn report(&self) {
+        if self.enabled {
+            eprint!("{}", self.render());
+        }
+    }
+
+    fn render(&self) -> String {
+        let width = self
+            .phases
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("total".len());
+        let mut out = String::from("[sshpod] timings:\n");
+        for (name, duration) in &self.phases {
+            out.push_str(&format!(
+                "  {:<width$}  {:>8.1} ms\n",
+                name,
+                duration.as_secs_f64() * 1000.0
+            ));
+        }
+        out.push_str(&format!(
+            "  {:<width$}  {:>8.1} ms\n",
+            "total",
+            (self.last - self.started).as_secs_f64() * 1000.0
+        ));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_lists_phases_and_total() {
+        let mut timings = Timings::new(true);
+        timings.lap("pod get");
+        timings.lap("sshd start");
+        let out = timings.render();
+        assert!(out.contains("pod get"));
+        assert!(out.contains("sshd start"));
+        assert!(out
+            .lines()
+            .last()
+            .unwrap()
+            .trim_start()
+            .starts_with("total"));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::cli::TopArgs;
+use crate::kubectl;
+use crate::proxy;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio::time::sleep;
+
+const MAX_ROWS: usize = 20;
+
+const SAMPLE_SCRIPT: &str = r#"echo "conf $(getconf CLK_TCK 2>/dev/null || echo 100) $(getconf PAGESIZE 2>/dev/null || echo 4096)"
+if [ -r /sys/fs/cgroup/memory.current ]; then
+  echo "mem $(cat /sys/fs/cgroup/memory.current) $(cat /sys/fs/cgroup/memory.max)"
+elif [ -r /sys/fs/cgroup/memory/memory.usage_in_bytes ]; then
+  echo "mem $(cat /sys/fs/cgroup/memory/memory.usage_in_bytes) $(cat /sys/fs/cgroup/memory/memory.limit_in_bytes)"
+fi
+for d in /proc/[0-9]*; do
+  stat="$(cat "$d/stat" 2>/dev/null)" || continue
+  comm="${stat#*(}"
+  comm="${comm%)*}"
+  set -- ${stat##*) }
+  ticks=$(( ${12} + ${13} ))
+  set -- $(cat "$d/statm" 2>/dev/null || echo 0 0)
+  fds="$(ls "$d/fd" 2>/dev/null | wc -l)"
+  echo "proc ${d#/proc/} $ticks $2 $fds $comm"
+done
+"#;
+
+#[derive(Debug, Default, PartialEq)]
+struct Sample {
+    clk_tck: u64,
+    page_size: u64,
+    mem_used: Option<u64>,
+    /// `None` when the cgroup has no memory limit.
+    mem_limit: Option<u64>,
+    procs: Vec<ProcSample>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ProcSample {
+    pid: u32,
+    ticks: u64,
+    rss_pages: u64,
+    fds: u64,
+    comm: String,
+}
+
+pub async fn run(args: TopArgs) -> Result<()> {
+    let (target, _) = proxy::resolve_host(&args.host).await?;
+    let mut previous: Option<(Instant, Sample)> = None;
+    let mut iteration = 0;
+    loop {
+        let output = kubectl::exec_capture_target(&target, &["sh", "-c", SAMPLE_SCRIPT])
+            .await
+            .context("failed to sample /proc in the container")?;
+        let now = Instant::now();
+        let sample = parse_sample(&output);
+        let cpu = match &previous {
+            Some((at, prev)) => cpu_percent(prev, &sample, now.duration_since(*at).as_secs_f64()),
+            None => HashMap::new(),
+        };
+        print!("\x1b[H\x1b[2J");
+        println!("{}", render(&target, &sample, &cpu));
+        previous = Some((now, sample));
+
+        iteration += 1;
+        if args.count.is_some_and(|count| iteration >= count) {
+            return Ok(());
+        }
+        sleep(args.interval).await;
+    }
+}
+
+fn parse_sample(output: &str) -> Sample {
+    let mut sample = Sample {
+        clk_tck: 100,
+        page_size: 4096,
+        ..Sample::default()
+    };
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("conf") => {
+                let mut next = || fields.next().and_then(|v| v.parse().ok());
+                sample.clk_tck = next().unwrap_or(100);
+                sample.page_size = next().unwrap_or(4096);
+            }
+            Some("mem") => {
+                sample.mem_used = fields.next().and_then(|v| v.parse().ok());
+                // cgroup v1 reports "no limit" as a huge number, v2 as "max".
+                sample.mem_limit = fields
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|limit| *limit < 1 << 60);
+            }
+            Some("proc") => {
+                let mut next = || fields.next().and_then(|v| v.parse().ok());
+                let (Some(pid), Some(ticks), Some(rss_pages), Some(fds)) =
+                    (next(), next(), next(), next())
+                else {
+                    continue;
+                };
+                let comm = fields.collect::<Vec<_>>().join(" ");
+                sample.procs.push(ProcSample {
+                    pid: pid as u32,
+                    ticks,
+                    rss_pages,
+                    fds,
+                    comm,
+                });
+            }
+            _ => {}
+        }
+    }
+    sample
+}
+
+/// CPU usage per pid between two samples, as a percentage of one core.
+fn cpu_percent(prev: &Sample, current: &Sample, elapsed_secs: f64) -> HashMap<u32, f64> {
+    if elapsed_secs <= 0.0 {
+        return HashMap::new();
+    }
+    let before: HashMap<u32, u64> = prev.procs.iter().map(|p| (p.pid, p.ticks)).collect();
+    current
+        .procs
+        .iter()
+        .filter_map(|p| {
+            let delta = p.ticks.saturating_sub(*before.get(&p.pid)?);
+            let percent = delta as f64 / current.clk_tck as f64 / elapsed_secs * 100.0;
+            Some((p.pid, percent))
+        })
+        .collect()
+}
+
+fn render(target: &kubectl::RemoteTarget, sample: &Sample, cpu: &HashMap<u32, f64>) -> String {
+    let mut out = format!(
+        "sshpod top: {}/{} container={}",
+        target.namespace, target.pod, target.container
+    );
+    if let Some(used) = sample.mem_used {
+        out.push_str(&format!("  mem={}", format_bytes(used)));
+        if let Some(limit) = sample.mem_limit {
+            out.push_str(&format!("/{}", format_bytes(limit)));
+        }
+    }
+    let total_fds: u64 = sample.procs.iter().map(|p| p.fds).sum();
+    out.push_str(&format!(
+        "  procs={}  fds={}\n\n",
+        sample.procs.len(),
+        total_fds
+    ));
+    out.push_str(&format!(
+        "{:>7} {:>6} {:>9} {:>5}  COMMAND\n",
+        "PID", "CPU%", "RSS", "FDS"
+    ));
+    let mut procs = sample.procs.clone();
+    procs.sort_by(|a, b| {
+        let cpu_a = cpu.get(&a.pid).copied().unwrap_or(0.0);
+        let cpu_b = cpu.get(&b.pid).copied().unwrap_or(0.0);
+        cpu_b.total_cmp(&cpu_a).then(b.rss_pages.cmp(&a.rss_pages))
+    });
+    for p in procs.iter().take(MAX_ROWS) {
+        let cpu = cpu
+            .get(&p.pid)
+            .map(|c| format!("{:.1}", c))
+            .unwrap_or_else(|| "-".to_string());
+        out.push_str(&format!(
+            "{:>7} {:>6} {:>9} {:>5}  {}\n",
+            p.pid,
+            cpu,
+            format_bytes(p.rss_pages * sample.page_size),
+            p.fds,
+            p.comm
+        ));
+    }
+    out
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_samples_and_computes_cpu() {
+        let first = parse_sample(
+            "conf 100 4096\nmem 1048576 max\nproc 1 100 256 4 my app\nproc 7 5 10 3 sh\n",
+        );
+        assert_eq!(first.mem_used, Some(1048576));
+        assert_eq!(first.mem_limit, None);
+        assert_eq!(first.procs[0].comm, "my app");
+        let second = parse_sample("conf 100 4096\nproc 1 150 256 4 my app\n");
+        let cpu = cpu_percent(&first, &second, 1.0);
+        assert_eq!(cpu.get(&1).copied(), Some(50.0));
+        assert_eq!(format_bytes(1536), "1.5KiB");
+    }
+}
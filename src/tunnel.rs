@@ -0,0 +1,51 @@
+use crate::hostspec::Endpoint;
+use crate::kubectl::{self, RemoteTarget};
+use anyhow::{bail, Result};
+use log::info;
+
+/// Relays stdin/stdout to `host:port` using whatever the container offers.
+const RELAY_SCRIPT: &str = r#"H="$1"
+P="$2"
+if command -v nc >/dev/null 2>&1; then
+  exec nc "$H" "$P"
+fi
+if command -v socat >/dev/null 2>&1; then
+  exec socat - "TCP:$H:$P"
+fi
+if command -v bash >/dev/null 2>&1; then
+  exec bash -c 'exec 3<>"/dev/tcp/$0/$1" && { cat <&3 & cat >&3; wait; }' "$H" "$P"
+fi
+echo "no relay tool (nc, socat, bash) available in the container" >&2
+exit 1
+"#;
+
+/// Connects our stdio to `endpoint` through the pod's network namespace.
+pub async fn run(target: &RemoteTarget, endpoint: &Endpoint) -> Result<()> {
+    info!(
+        "[sshpod] tunneling to {}:{} via pod {}",
+        endpoint.host, endpoint.port, target.pod
+    );
+    let port = endpoint.port.to_string();
+    let status = kubectl::exec_stdio_target(
+        target,
+        &[
+            "sh",
+            "-c",
+            RELAY_SCRIPT,
+            "sshpod-relay",
+            &endpoint.host,
+            &port,
+        ],
+    )
+    .await?;
+    if !status.success() {
+        bail!(
+            "tunnel to {}:{} via pod {} exited with {}",
+            endpoint.host,
+            endpoint.port,
+            target.pod,
+            status
+        );
+    }
+    Ok(())
+}
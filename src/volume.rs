@@ -0,0 +1,175 @@
+use crate::audit;
+use crate::cli::VolumeArgs;
+use crate::config::Config;
+use crate::helper_pod::HelperPodOptions;
+use crate::hostspec::{self, Target};
+use crate::kubectl;
+use crate::proxy;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use serde_json::json;
+use tokio::process::Command;
+
+const MOUNT_PATH: &str = "/volume";
+
+pub async fn run(args: VolumeArgs) -> Result<()> {
+    let host = hostspec::with_suffix(&args.spec);
+    let spec = hostspec::parse(&host).context("failed to parse volume spec")?;
+    let Target::Pod(pvc) = &spec.target else {
+        bail!("volume spec must start with the PVC name, e.g. data.namespace--ns.sshpod");
+    };
+    let context = spec.context.as_deref();
+    if let Some(ctx) = context {
+        kubectl::ensure_context_exists(ctx).await?;
+    }
+    let namespace = audit::resolve_namespace(context, spec.namespace.clone()).await?;
+
+    let options = Config::load()?
+        .host_settings(&host)?
+        .helper_pod
+        .merge(&args.helper);
+
+    let helper = helper_name(pvc);
+    let manifest = helper_manifest(&helper, pvc, &options, !args.rw);
+    kubectl::create_object(context, &namespace, &manifest).await?;
+    eprintln!(
+        "[sshpod] helper pod {} mounts PVC {} at {}{}",
+        helper,
+        pvc,
+        MOUNT_PATH,
+        if args.rw { "" } else { " (read-only)" }
+    );
+
+    // The helper pod is deleted below however the session ends, including
+    // on SIGINT/SIGTERM/SIGHUP.
+    let result = async {
+        tokio::select! {
+            ready = kubectl::wait_pod_ready(context, &namespace, &helper) => ready?,
+            signal = proxy::shutdown_signal() => bail!("interrupted by {}", signal),
+        }
+        let mut host = format!(
+            "pod--{}.namespace--{}",
+            helper,
+            hostspec::escape(&namespace)
+        );
+        if let Some(ctx) = context {
+            host.push_str(&format!(".context--{}", hostspec::escape(ctx)));
+        }
+        let mut sftp = Command::new("sftp")
+            .arg(format!(
+                "root@{}:{}",
+                hostspec::with_suffix(&host),
+                MOUNT_PATH
+            ))
+            .kill_on_drop(true)
+            .spawn()
+            .context("failed to run sftp")?;
+        let status = tokio::select! {
+            status = sftp.wait() => status.context("failed to wait for sftp")?,
+            signal = termination_signal() => bail!("interrupted by {}", signal),
+        };
+        if !status.success() {
+            bail!("sftp exited with {}", status);
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(err) = kubectl::delete_pod(context, &namespace, &helper).await {
+        warn!("[sshpod] failed to delete helper pod {}: {:#}", helper, err);
+    }
+    result
+}
+
+/// Resolves on SIGTERM or SIGHUP. Ctrl-C is left to the foreground sftp,
+/// which uses it to cancel a transfer, so it must not stop sshpod here.
+async fn termination_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let (Ok(mut int), Ok(mut term), Ok(mut hup)) = (
+            signal(SignalKind::interrupt()),
+            signal(SignalKind::terminate()),
+            signal(SignalKind::hangup()),
+        ) else {
+            return std::future::pending().await;
+        };
+        loop {
+            tokio::select! {
+                _ = int.recv() => continue,
+                _ = term.recv() => return "SIGTERM",
+                _ = hup.recv() => return "SIGHUP",
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        std::future::pending().await
+    }
+}
+
+fn helper_name(pvc: &str) -> String {
+    let prefix: String = pvc.chars().take(40).collect();
+    format!(
+        "sshpod-volume-{}-{}",
+        prefix.trim_end_matches('-'),
+        std::process::id()
+    )
+}
+
+fn helper_manifest(name: &str, pvc: &str, options: &HelperPodOptions, read_only: bool) -> String {
+    let mut manifest = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {
+            "name": name,
+            "labels": { "app.kubernetes.io/managed-by": "sshpod" }
+        },
+        "spec": {
+            "restartPolicy": "Never",
+            "terminationGracePeriodSeconds": 1,
+            "containers": [{
+                "name": "volume",
+                "image": options.image(),
+                "command": ["sh", "-c", "sleep 86400"],
+                "volumeMounts": [{
+                    "name": "data",
+                    "mountPath": MOUNT_PATH,
+                    "readOnly": read_only
+                }]
+            }],
+            "volumes": [{
+                "name": "data",
+                "persistentVolumeClaim": { "claimName": pvc, "readOnly": read_only }
+            }]
+        }
+    });
+    options.apply(&mut manifest["spec"]);
+    manifest.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_mounts_claim_read_only() {
+        let manifest: serde_json::Value = serde_json::from_str(&helper_manifest(
+            "h",
+            "data",
+            &HelperPodOptions::default(),
+            true,
+        ))
+        .unwrap();
+        let spec = &manifest["spec"];
+        assert_eq!(
+            spec["volumes"][0]["persistentVolumeClaim"]["claimName"],
+            "data"
+        );
+        assert_eq!(
+            spec["volumes"][0]["persistentVolumeClaim"]["readOnly"],
+            true
+        );
+        assert_eq!(spec["containers"][0]["volumeMounts"][0]["readOnly"], true);
+    }
+}